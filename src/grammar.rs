@@ -1,52 +1,288 @@
 use const_format;
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
-use std::io;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::LazyLock;
 
+mod analysis;
 mod parser;
+mod sample;
+mod transform;
+pub use analysis::GrammarWarning;
+pub use analysis::Lookahead;
+pub use parser::collapse_unit_chains;
+pub use parser::forest_to_text;
+pub use parser::leftmost_derivation;
 pub use parser::render_tree;
+pub use parser::render_tree_to_writer;
+pub use parser::to_ascii;
+pub use parser::to_tree_string;
+pub use parser::write_forest_dag_to_dot;
+pub use parser::write_forest_dag_to_dot_with;
+pub use parser::write_forest_to_dot;
 pub use parser::write_tree_to_dot;
+pub use parser::write_tree_to_dot_file;
+pub use parser::write_tree_to_dot_styled;
+pub use parser::Associativity;
+pub use parser::BestEffort;
+pub use parser::CachingGrammar;
+pub use parser::ChartItem;
+pub use parser::ChartStats;
+pub use parser::DotStyle;
+pub use parser::EarleyChart;
+pub use parser::EvalError;
+pub use parser::Evaluator;
+pub use parser::IncrementalParser;
+pub use parser::NodeStyle;
+pub use parser::ParallelEdges;
+pub use parser::ParseLimitExceeded;
 pub use parser::ParseNode;
+pub use parser::ParseResult;
+pub use parser::ParseSummary;
+pub use parser::PhaseTimings;
+pub use parser::Precedence;
+pub use parser::RuleActions;
+pub use parser::RuleCoverage;
+pub use parser::ScanError;
+pub use parser::SyncParseNode;
+pub use parser::TooAmbiguous;
+pub use parser::TreeStyle;
+pub use sample::Rng;
+pub use sample::SplitMix64;
+pub use transform::MergeError;
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+/// A nonterminal of a grammar, known by its name.
+///
+/// Each grammar numbers its nonterminals, and the nonterminals of its rules
+/// carry their number, which the parser uses to index its tables instead of
+/// hashing names. Two nonterminals are still equal when they have the same
+/// name, so that a nonterminal built with [`NonTerminal::new`] finds the one
+/// of a grammar in a map, but the comparison is a pointer check when both
+/// come from the same grammar.
+#[derive(Copy, Clone)]
 pub struct NonTerminal<'a> {
     name: &'a str,
+    // The index of the nonterminal in the table of its grammar, or
+    // UNBOUND if it does not come from a grammar.
+    id: u32,
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
-pub struct Terminal {
-    content: char,
+/// The symbols of the input matched by the terminals. Grammars read from text
+/// match characters, but grammars built in code can match any symbol, like the
+/// tokens produced by a lexer.
+pub trait Symbol: Copy + Eq + Hash + Ord + fmt::Debug + fmt::Display {
+    /// Returns true if the symbol belongs to the character class.
+    /// Only characters belong to classes.
+    fn in_class(&self, _class: CharClass<'_>) -> bool {
+        false
+    }
+
+    /// Returns the lowercase and the uppercase forms of the symbol, used when
+    /// matching while ignoring case. Only ASCII characters have different forms.
+    fn ascii_cases(&self) -> (Self, Self) {
+        (*self, *self)
+    }
+}
+
+impl Symbol for char {
+    fn in_class(&self, class: CharClass<'_>) -> bool {
+        class.matches(*self)
+    }
+
+    fn ascii_cases(&self) -> (Self, Self) {
+        (self.to_ascii_lowercase(), self.to_ascii_uppercase())
+    }
+}
+
+impl Symbol for u8 {
+    fn ascii_cases(&self) -> (Self, Self) {
+        (self.to_ascii_lowercase(), self.to_ascii_uppercase())
+    }
+}
+
+/// A terminal matches a single symbol of the input.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum Terminal<'a, T = char> {
+    /// Matches exactly the given symbol.
+    Symbol(T),
+    /// Matches any character of the class.
+    Class(CharClass<'a>),
+}
+
+/// A set of characters matched by a single terminal.
+#[derive(Copy, Clone, Debug)]
+pub enum CharClass<'a> {
+    /// `\d`, any digit from 0 to 9.
+    Digit,
+    /// `[a-f]`, any character between the two (inclusive).
+    Range(char, char),
+    /// `[aeiou]`, any of the characters, as written in the grammar. Two sets
+    /// with the same characters are equal, whatever their order and repetitions.
+    Set(&'a str),
+    /// `\p{name}`, any character accepted by the predicate with that name,
+    /// given to `GrammarConfig::terminal_pred`.
+    Predicate(CharPredicate),
+}
+
+/// A named test for characters. Predicates are compared by their names, then by
+/// the addresses of their functions, so two predicates with the same name
+/// but different tests are different terminals.
+#[derive(Copy, Clone)]
+pub struct CharPredicate {
+    name: &'static str,
+    test: fn(char) -> bool,
 }
 
 #[derive(Copy, Clone, Hash, Eq, PartialEq)]
-pub enum Token<'a> {
+pub enum Token<'a, T = char> {
     NT(NonTerminal<'a>),
-    T(Terminal),
+    T(Terminal<'a, T>),
 }
 
 /// A production rule is a pair (from, to) where from is a nonterminal
 /// and to is a string of terminals/nonterminals.
 #[derive(Clone, Hash, Eq, PartialEq)]
-struct Rule<'a> {
+struct Rule<'a, T = char> {
     from: NonTerminal<'a>,
-    to: Vec<Token<'a>>,
+    to: Vec<Token<'a, T>>,
+    /// The optional label of the rule, written like `@add` after the production.
+    name: Option<&'a str>,
 }
 
-/// A context free grammar.
-pub struct Grammar<'a> {
+/// A context free grammar, whose terminals match symbols of type T.
+/// A clone borrows the same names as the original grammar.
+#[derive(Clone)]
+pub struct Grammar<'a, T = char> {
     nonterminals: HashMap<&'a str, NonTerminal<'a>>,
-    terminals: HashMap<&'a str, Terminal>,
-    rules: Vec<Rule<'a>>,
+    terminals: HashSet<Terminal<'a, T>>,
+    rules: Vec<Rule<'a, T>>,
     start: NonTerminal<'a>,
+    weights: Vec<f64>,
+    // The nonterminals sorted by name. The id of a nonterminal is its index.
+    table: Vec<NonTerminal<'a>>,
+    // The indices of the rules of each nonterminal, in order, by id.
+    rules_by_nonterminal: Vec<Vec<usize>>,
+    // The analyses computed so far.
+    analyses: analysis::AnalysisCache<'a, T>,
 }
 
+/// An error found while reading a grammar.
+///
+/// The line numbers are the 1-based numbers of the lines of the text, counting
+/// the comments and the empty lines, so that they point to the line in an editor.
 #[derive(Debug)]
 pub enum ParseError {
     IoError(io::Error),
-    InvalidRule { line_num: usize },
+    /// The line at the given (1-based) line number is not a valid rule.
+    InvalidRule {
+        line_num: usize,
+    },
+    /// A word in the right side of the rule at the given (1-based) line number
+    /// is neither a terminal nor a nonterminal.
+    UnknownSymbol {
+        line_num: usize,
+        token: String,
+    },
+    /// A word in the right side of the rule at the given (1-based) line number
+    /// holds a character outside of ASCII, which is not in the terminal alphabet.
+    /// Such a character can be written as a byte like `\xe9`, or added to the
+    /// alphabet of `GrammarConfig`.
+    NonAsciiTerminal {
+        line_num: usize,
+        ch: char,
+    },
     MissingStart,
     InvalidStart,
+    /// The first line at the given (1-based) line number has an arrow, but is not
+    /// a valid rule. The first line should be the bare start nonterminal, like `S`,
+    /// or a valid rule of the start nonterminal, like `S -> a S`.
+    StartLineHasRule {
+        line_num: usize,
+    },
+    /// The rule at the given (1-based) line number exceeds one of the limits.
+    LimitExceeded {
+        line_num: usize,
+        limit: Limit,
+    },
+}
+
+/// The limits that can be exceeded by a grammar.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Limit {
+    Rules,
+    RuleLength,
+    Nonterminals,
+}
+
+/// Limits on the size of a grammar read by `from_rules_limited`, for reading
+/// grammars from untrusted sources. By default, there are no limits.
+#[derive(Debug, Copy, Clone)]
+pub struct GrammarLimits {
+    /// The maximum number of rules.
+    pub max_rules: usize,
+    /// The maximum number of symbols in the right side of a rule.
+    pub max_rule_length: usize,
+    /// The maximum number of nonterminals, including the start nonterminal.
+    pub max_nonterminals: usize,
+}
+
+impl Default for GrammarLimits {
+    fn default() -> Self {
+        GrammarLimits {
+            max_rules: usize::MAX,
+            max_rule_length: usize::MAX,
+            max_nonterminals: usize::MAX,
+        }
+    }
+}
+
+/// Options for reading a grammar with `from_rules_with_config`.
+#[derive(Debug, Clone)]
+pub struct GrammarConfig {
+    /// Returns true for the characters that can be written as themselves as terminals.
+    /// By default, these are the lowercase letters, the digits and `+ - * ( ) /`.
+    ///
+    /// The words of a rule are tried as terminals before nonterminals, so if the
+    /// alphabet holds uppercase letters, a single uppercase letter after the arrow
+    /// is a terminal. Longer names are still nonterminals.
+    ///
+    /// If `#` is not in the alphabet, a rule can end with a comment that starts with `#`.
+    /// Otherwise, `#` is a terminal and only whole lines can be comments.
+    pub terminal_chars: fn(char) -> bool,
+    /// If true, a character after a quote, like `'A`, is a terminal for that character,
+    /// whether or not it is in the alphabet, and the words that look like nonterminals
+    /// are always nonterminals. This allows grammars over uppercase letters.
+    pub quoted_terminals: bool,
+    pub limits: GrammarLimits,
+    /// The character predicates that can be used as terminals, written like `\p{name}`.
+    pub predicates: Vec<CharPredicate>,
+}
+
+impl GrammarConfig {
+    /// Adds a terminal written `\p{name}`, which matches the characters accepted
+    /// by the predicate, like `GrammarConfig::default().terminal_pred("space", char::is_whitespace)`.
+    /// The name is made of lowercase letters and underscores. Adding a name again
+    /// replaces its predicate.
+    pub fn terminal_pred(mut self, name: &'static str, test: fn(char) -> bool) -> Self {
+        self.predicates.retain(|predicate| predicate.name != name);
+        self.predicates.push(CharPredicate { name, test });
+        self
+    }
+}
+
+impl Default for GrammarConfig {
+    fn default() -> Self {
+        GrammarConfig {
+            terminal_chars: is_plain_terminal,
+            quoted_terminals: false,
+            limits: GrammarLimits::default(),
+            predicates: Vec::new(),
+        }
+    }
 }
 
 impl From<io::Error> for ParseError {
@@ -55,72 +291,975 @@ impl From<io::Error> for ParseError {
     }
 }
 
-const TERMINAL_REGEX: &'static str = r"[a-z+\-\*0-9\(\)/]";
-const NONTERMINAL_REGEX: &'static str = r"[A-Z]+";
-const RULE_REGEX: &'static str = const_format::formatcp!(
-    r"^{}\s+->(\s+({}|{}))*$",
-    NONTERMINAL_REGEX,
-    NONTERMINAL_REGEX,
-    TERMINAL_REGEX
-);
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::IoError(error) => write!(f, "{}", error),
+            ParseError::InvalidRule { line_num } => {
+                write!(f, "Line {} is not a valid rule", line_num)
+            }
+            ParseError::UnknownSymbol { line_num, token } => {
+                write!(f, "Unknown symbol {} on line {}", token, line_num)
+            }
+            ParseError::NonAsciiTerminal { line_num, ch } => {
+                write!(f, "Non-ASCII character {:?} on line {}", ch, line_num)
+            }
+            ParseError::MissingStart => write!(f, "The grammar has no start nonterminal"),
+            ParseError::InvalidStart => write!(f, "The start nonterminal is not valid"),
+            ParseError::StartLineHasRule { line_num } => write!(
+                f,
+                "Line {} should be the start nonterminal or a valid rule of it",
+                line_num
+            ),
+            ParseError::LimitExceeded { line_num, limit } => {
+                write!(f, "Line {} exceeds the limit on {:?}", line_num, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParseError::IoError(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a str> for Grammar<'a> {
+    type Error = ParseError;
+
+    /// Reads the grammar like `from_rules`.
+    fn try_from(grammar: &'a str) -> Result<Self, Self::Error> {
+        Grammar::from_rules(grammar)
+    }
+}
+
+/// A terminal is a single character of the terminal alphabet (see `GrammarConfig`),
+/// or one of these escapes: `\s` which stands for a space (since words are separated
+/// by whitespace), a byte like `\x7f`, or a character class: `\d` for the digits,
+/// a range like `[a-f]`, a set of characters like `[aeiou]`, or a predicate like `\p{space}`.
+const ESCAPED_TERMINAL_REGEX: &str =
+    r"(?:\\s|\\d|\\x[0-9a-fA-F]{2}|\[[a-zA-Z0-9]-[a-zA-Z0-9]\]|\[[^\[\]\\\s]+\]|\\p\{[a-z_]+\})";
+/// A range has a single character on each side of the dash, so `[a-]` is a set.
+const RANGE_REGEX: &str = r"^\[[a-zA-Z0-9]-[a-zA-Z0-9]\]$";
+const SPACE_ESCAPE: &str = r"\s";
+const DIGIT_ESCAPE: &str = r"\d";
+const BYTE_ESCAPE: &str = r"\x";
+const PREDICATE_ESCAPE: &str = r"\p";
+/// A nonterminal starts with an uppercase letter, followed by uppercase letters,
+/// digits and underscores. This includes the names created by the grammar transforms,
+/// so that transformed grammars can be written and read back.
+const NONTERMINAL_REGEX: &str = r"[A-Z][A-Z0-9_]*";
+/// The first word of the section that declares named terminals.
+const TOKENS_SECTION: &str = "%tokens";
+/// The words that stand for the empty word, like an empty right side.
+const EPSILON_MARKERS: [&str; 2] = ["\u{03B5}", "_eps"];
+/// A rule can end with a label like `@add`, which names the rule.
+const LABEL_REGEX: &str = r"@[a-zA-Z_][a-zA-Z0-9_]*";
+/// A rule starts with a nonterminal and an arrow, either `->` or the BNF `::=`.
+/// The words after the arrow are classified one by one, so that an unknown symbol
+/// can be reported.
+const RULE_HEAD_REGEX: &str = const_format::formatcp!(r"^{}\s+(->|::=)(\s|$)", NONTERMINAL_REGEX);
+
+/// Returns true if the character can be written as itself in a grammar,
+/// without an escape.
+fn is_plain_terminal(c: char) -> bool {
+    matches!(c, 'a'..='z' | '0'..='9' | '+' | '-' | '*' | '(' | ')' | '/')
+}
 
 impl<'a> Grammar<'a> {
     /// Reads the grammar rules and constructs the grammar.
+    ///
+    /// The first line holds the start nonterminal, and every other line holds a rule.
+    /// The first line can also be the first rule of the start nonterminal, like `S -> a S`.
+    /// The arrow of a rule is `->`, or `::=` as in BNF, and both can be mixed in a grammar.
+    /// An epsilon rule has an empty right side, which can also be written as `ε` or `_eps`.
+    /// Lines that start with `#` are comments and are ignored, just like empty lines.
+    /// A rule can also be followed by a comment, like `EXP -> EXP + EXP # addition`,
+    /// which starts at a word that begins with `#`.
+    /// A long rule can be split over several lines by ending each line but the last
+    /// with a backslash.
+    ///
+    /// A rule can end with a label like `@add`, which is returned by `rule_name`.
+    ///
+    /// The grammar can start with a section that names terminals, like
+    /// `%tokens IF = "if" ; NUM = [0-9] ;`, before the start line. A name stands for
+    /// a string, which is matched character by character, or for a single terminal.
+    /// The rules use the names like symbols, and a name without a declaration or
+    /// rules is then reported as `UnknownSymbol`.
+    ///
+    /// A rule that appears more than once is kept only once, where it first appears.
+    /// This doesn't change the language or the parse trees, since the duplicates
+    /// would derive exactly the same trees. Rules with different labels are different rules.
+    ///
+    /// Any text can be given: a malformed grammar is reported as an error, never as a panic.
     pub fn from_rules(grammar: &'a str) -> Result<Self, ParseError> {
-        let rule_regex = Regex::new(RULE_REGEX).unwrap();
-        let terminal_regex = Regex::new(TERMINAL_REGEX).unwrap();
-        let first_line_regex = Regex::new(&format!(r"^{}$", NONTERMINAL_REGEX)).unwrap();
+        Grammar::from_rules_limited(grammar, GrammarLimits::default())
+    }
 
-        let mut terminals = HashMap::new();
+    /// Reads the grammar like `from_rules`, but fails with `LimitExceeded`
+    /// as soon as a rule exceeds one of the limits.
+    pub fn from_rules_limited(grammar: &'a str, limits: GrammarLimits) -> Result<Self, ParseError> {
+        let config = GrammarConfig {
+            limits,
+            ..GrammarConfig::default()
+        };
+        Grammar::from_rules_with_config(grammar, &config)
+    }
+
+    /// Reads the grammar like `from_rules`, with the terminal alphabet and
+    /// the limits of the configuration.
+    pub fn from_rules_with_config(
+        grammar: &'a str,
+        config: &GrammarConfig,
+    ) -> Result<Self, ParseError> {
+        Grammar::read_rules(grammar, config, None)
+    }
+
+    /// Reads a grammar made only of rules, without a line for the start nonterminal,
+    /// and uses the given start instead. This way, the same rules can be used
+    /// with different start nonterminals.
+    ///
+    /// Fails with `InvalidStart` if the start has no rule.
+    pub fn from_rules_with_start(grammar: &'a str, start: &'a str) -> Result<Self, ParseError> {
+        Grammar::read_rules(grammar, &GrammarConfig::default(), Some(start))
+    }
+
+    /// Reads the rules of the grammar. Without a start nonterminal, it is read
+    /// from the first line.
+    fn read_rules(
+        grammar: &'a str,
+        config: &GrammarConfig,
+        given_start: Option<&'a str>,
+    ) -> Result<Self, ParseError> {
+        let limits = config.limits;
+        // The patterns are constants, so compiling them can't fail.
+        static RULE_HEAD: LazyLock<Regex> = LazyLock::new(|| Regex::new(RULE_HEAD_REGEX).unwrap());
+        // Match the whole word, so that the digits of a nonterminal aren't taken for a terminal.
+        static ESCAPED_TERMINAL: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(const_format::formatcp!(r"^{}$", ESCAPED_TERMINAL_REGEX)).unwrap()
+        });
+        static NONTERMINAL: LazyLock<Regex> = LazyLock::new(|| {
+            Regex::new(const_format::formatcp!(r"^{}$", NONTERMINAL_REGEX)).unwrap()
+        });
+        static RANGE: LazyLock<Regex> = LazyLock::new(|| Regex::new(RANGE_REGEX).unwrap());
+        static LABEL: LazyLock<Regex> =
+            LazyLock::new(|| Regex::new(const_format::formatcp!(r"^{}$", LABEL_REGEX)).unwrap());
+        let (rule_head_regex, escaped_terminal_regex) = (&*RULE_HEAD, &*ESCAPED_TERMINAL);
+        let (nonterminal_regex, label_regex, range_regex) = (&*NONTERMINAL, &*LABEL, &*RANGE);
+
+        let is_quoted = |word: &str| {
+            let mut chars = word.chars();
+            config.quoted_terminals
+                && chars.next() == Some('\'')
+                && chars.next().is_some()
+                && chars.next().is_none()
+        };
+        let is_terminal = |word: &str| {
+            if config.quoted_terminals && nonterminal_regex.is_match(word) {
+                return false;
+            }
+            let mut chars = word.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => (config.terminal_chars)(c),
+                _ => is_quoted(word) || escaped_terminal_regex.is_match(word),
+            }
+        };
+
+        // Builds the terminal written as the word, which is a terminal.
+        let read_terminal = |line_num: usize, word: &'a str| -> Result<Terminal<'a>, ParseError> {
+            let chars: Vec<char> = word.chars().collect();
+            Ok(match word {
+                SPACE_ESCAPE => Terminal::Symbol(' '),
+                DIGIT_ESCAPE => Terminal::Class(CharClass::Digit),
+                _ if word.starts_with(BYTE_ESCAPE) => {
+                    // The byte is stored as the character with the same code.
+                    let byte = u8::from_str_radix(&word[2..], 16).unwrap();
+                    Terminal::Symbol(char::from(byte))
+                }
+                _ if range_regex.is_match(word) => {
+                    // A range like [a-f], which must not be empty.
+                    if chars[1] > chars[3] {
+                        return Err(ParseError::InvalidRule { line_num });
+                    }
+                    Terminal::Class(CharClass::Range(chars[1], chars[3]))
+                }
+                _ if word.starts_with('[') => {
+                    // A set like [aeiou], which borrows its characters from the text.
+                    Terminal::Class(CharClass::Set(&word[1..word.len() - 1]))
+                }
+                _ if word.starts_with(PREDICATE_ESCAPE) => {
+                    // A predicate like \p{space}, which must be in the config.
+                    let name = &word[3..word.len() - 1];
+                    match config.predicates.iter().find(|p| p.name == name) {
+                        Some(predicate) => Terminal::Class(CharClass::Predicate(*predicate)),
+                        None => {
+                            return Err(ParseError::UnknownSymbol {
+                                line_num,
+                                token: word.to_string(),
+                            })
+                        }
+                    }
+                }
+                _ if is_quoted(word) => Terminal::Symbol(chars[1]),
+                _ => Terminal::Symbol(chars[0]),
+            })
+        };
+
+        let mut terminals = HashSet::new();
         let mut nonterminals = HashMap::new();
         let mut rules = Vec::new();
+        let mut seen = HashSet::new();
+
+        // Files saved by some editors start with a byte order mark.
+        let grammar = grammar.strip_prefix('\u{FEFF}').unwrap_or(grammar);
+
+        // Number the lines before skipping the comments, so that errors
+        // point to the right line. `lines` splits on both "\n" and "\r\n", and
+        // trimming removes any other whitespace, including a lone '\r'.
+        // Unless '#' is a terminal, a word that starts with '#' starts a comment.
+        // Inside a word, like the set `[#a]` or the quoted `'#`, it is kept.
+        let inline_comments = !(config.terminal_chars)('#');
+        let comment_start = |line: &str| {
+            line.char_indices()
+                .find(|&(position, c)| {
+                    c == '#'
+                        && line[..position]
+                            .chars()
+                            .next_back()
+                            .is_none_or(char::is_whitespace)
+                })
+                .map(|(position, _)| position)
+        };
+        let lines = grammar
+            .lines()
+            .enumerate()
+            .map(|(index, line)| match comment_start(line) {
+                Some(position) if inline_comments => (index + 1, &line[..position]),
+                _ => (index + 1, line),
+            })
+            .map(|(line_num, line)| (line_num, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+        // A line that ends with a backslash continues on the next line.
+        // The words of the joined lines are numbered with the first line.
+        let mut joined = Vec::new();
+        let mut continued: Option<(usize, Vec<&str>)> = None;
+        for (line_num, line) in lines {
+            let (line, continues) = match line.strip_suffix('\\') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            let (_, words) = continued.get_or_insert((line_num, Vec::new()));
+            words.extend(line.split_whitespace());
+            if !continues {
+                joined.extend(continued.take());
+            }
+        }
+        if let Some((line_num, _)) = continued {
+            return Err(ParseError::InvalidRule { line_num });
+        }
+        let mut lines = joined.into_iter().peekable();
 
-        // Read the first line to get the start nonterminal.
-        let mut lines = grammar.lines();
-        let first_line = lines.next().ok_or(ParseError::MissingStart)?.trim();
-        if !first_line_regex.is_match(first_line) {
+        // The grammar can start with a section that declares named terminals, like
+        // `%tokens IF = "if" ; NUM = [0-9] ;`, where each declaration ends with `;`.
+        // The section can go on over the next lines that start with a declaration.
+        let declares_tokens = lines
+            .peek()
+            .is_some_and(|(_, words)| words[0] == TOKENS_SECTION);
+        let mut tokens: HashMap<&str, Vec<Terminal>> = HashMap::new();
+        if declares_tokens {
+            let (line_num, words) = lines.next().unwrap();
+            let mut declarations: Vec<(usize, &str)> =
+                words[1..].iter().map(|&word| (line_num, word)).collect();
+            while let Some((line_num, words)) =
+                lines.next_if(|(_, words)| words.get(1) == Some(&"="))
+            {
+                declarations.extend(words.iter().map(|&word| (line_num, word)));
+            }
+
+            for declaration in declarations.chunks(4) {
+                let line_num = declaration[0].0;
+                let (name, value) = match declaration {
+                    [(_, name), (_, "="), (_, value), (_, ";")]
+                        if nonterminal_regex.is_match(name) && !tokens.contains_key(name) =>
+                    {
+                        (*name, *value)
+                    }
+                    _ => return Err(ParseError::InvalidRule { line_num }),
+                };
+                let declared = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                    // A string like "if" stands for the sequence of its characters.
+                    Some("") => return Err(ParseError::InvalidRule { line_num }),
+                    Some(string) => {
+                        let mut declared = Vec::new();
+                        for ch in string.chars() {
+                            if !ch.is_ascii() && !(config.terminal_chars)(ch) {
+                                return Err(ParseError::NonAsciiTerminal { line_num, ch });
+                            }
+                            declared.push(Terminal::Symbol(ch));
+                        }
+                        declared
+                    }
+                    _ if is_terminal(value) => vec![read_terminal(line_num, value)?],
+                    _ => {
+                        return Err(ParseError::UnknownSymbol {
+                            line_num,
+                            token: value.to_string(),
+                        })
+                    }
+                };
+                tokens.insert(name, declared);
+            }
+        }
+        // The names used in the right sides, which must have rules if tokens are declared.
+        let mut references = Vec::new();
+
+        // Read the first line to get the start nonterminal. If the line is a rule,
+        // its left side is the start, and it is kept as the first rule.
+        let start = match given_start {
+            Some(name) if nonterminal_regex.is_match(name) => NonTerminal::new(name),
+            Some(_) => return Err(ParseError::InvalidStart),
+            None => {
+                let (line_num, first_line) = lines.peek().ok_or(ParseError::MissingStart)?;
+                let has_arrow = |word: &&str| word.contains("->") || word.contains("::=");
+                match first_line[..] {
+                    [name] if nonterminal_regex.is_match(name) => {
+                        lines.next();
+                        NonTerminal::new(name)
+                    }
+                    [name, ..] if rule_head_regex.is_match(&first_line.join(" ")) => {
+                        NonTerminal::new(name)
+                    }
+                    _ if first_line.iter().any(has_arrow) => {
+                        return Err(ParseError::StartLineHasRule {
+                            line_num: *line_num,
+                        })
+                    }
+                    _ => return Err(ParseError::InvalidStart),
+                }
+            }
+        };
+        if tokens.contains_key(start.name) {
             return Err(ParseError::InvalidStart);
         }
-        let start = NonTerminal { name: first_line };
-        nonterminals.insert(first_line, start);
+        nonterminals.insert(start.name, start);
 
         // Then build the rules.
-        for (line_num, line) in lines.enumerate() {
-            let line = line.trim();
-            if !rule_regex.is_match(&line) {
+        for (line_num, mut words) in lines {
+            if !rule_head_regex.is_match(&words.join(" ")) {
                 return Err(ParseError::InvalidRule { line_num });
             }
-            let words: Vec<&str> = line.split_whitespace().collect();
+            let name = match words.last() {
+                Some(word) if words.len() > 2 && label_regex.is_match(word) => {
+                    Some(&words.pop().unwrap()[1..])
+                }
+                _ => None,
+            };
+
+            let exceeded = |limit| Err(ParseError::LimitExceeded { line_num, limit });
+            if words.len() - 2 > limits.max_rule_length {
+                return exceeded(Limit::RuleLength);
+            }
 
             // Build the rule by iterating over the words.
             // Create nonterminals/terminals while doing so.
             let word = words[0];
-            let from = NonTerminal { name: word };
+            if tokens.contains_key(word) {
+                return Err(ParseError::InvalidRule { line_num });
+            }
+            let from = NonTerminal::new(word);
             nonterminals.entry(word).or_insert(from);
 
+            // The empty word can be written explicitly, as the only symbol of the rule.
+            let mut symbols = &words[2..];
+            if symbols.iter().any(|word| EPSILON_MARKERS.contains(word)) {
+                if symbols.len() != 1 {
+                    return Err(ParseError::InvalidRule { line_num });
+                }
+                symbols = &[];
+            }
+
             let mut to = Vec::new();
-            for &word in &words[2..] {
-                if terminal_regex.is_match(word) {
-                    let terminal = Terminal {
-                        content: word.chars().next().unwrap(),
-                    };
-                    terminals.entry(word).or_insert(terminal);
+            for &word in symbols {
+                if let Some(declared) = tokens.get(word) {
+                    terminals.extend(declared.iter().copied());
+                    to.extend(declared.iter().map(|&terminal| Token::T(terminal)));
+                } else if is_terminal(word) {
+                    let terminal = read_terminal(line_num, word)?;
+                    terminals.insert(terminal);
                     to.push(Token::T(terminal));
-                } else {
-                    let nonterminal = NonTerminal { name: word };
+                } else if nonterminal_regex.is_match(word) {
+                    let nonterminal = NonTerminal::new(word);
                     nonterminals.entry(word).or_insert(nonterminal);
+                    if declares_tokens {
+                        references.push((line_num, word));
+                    }
                     to.push(Token::NT(nonterminal));
+                } else if label_regex.is_match(word) {
+                    // A label must be the last word of the rule.
+                    return Err(ParseError::InvalidRule { line_num });
+                } else if let Some(ch) = word.chars().find(|c| !c.is_ascii()) {
+                    return Err(ParseError::NonAsciiTerminal { line_num, ch });
+                } else {
+                    return Err(ParseError::UnknownSymbol {
+                        line_num,
+                        token: word.to_string(),
+                    });
+                }
+            }
+            if nonterminals.len() > limits.max_nonterminals {
+                return exceeded(Limit::Nonterminals);
+            }
+
+            let rule = Rule { from, to, name };
+            if seen.insert(rule.clone()) {
+                if rules.len() == limits.max_rules {
+                    return exceeded(Limit::Rules);
                 }
+                rules.push(rule);
             }
-            rules.push(Rule { from, to });
         }
+        if given_start.is_some() && !rules.iter().any(|rule| rule.from == start) {
+            return Err(ParseError::InvalidStart);
+        }
+        // With declared tokens, a name without rules is most likely a misspelled token.
+        for (line_num, name) in references {
+            if !rules.iter().any(|rule| rule.from.name == name) {
+                return Err(ParseError::UnknownSymbol {
+                    line_num,
+                    token: name.to_string(),
+                });
+            }
+        }
+
+        Ok(Grammar::new(nonterminals, terminals, rules, start))
+    }
+
+    /// Reads the grammar from the file at the given path, like `from_rules`.
+    ///
+    /// The grammar borrows the names of its symbols from its text, so the text
+    /// is read into the given string, replacing its contents, and the grammar
+    /// borrows it. The caller owns the text, like the names made by `to_cnf`:
+    /// a type holding both the text and a grammar that borrows from it would
+    /// borrow from itself, which safe code can't express, and reading the text
+    /// again for each use would lose the weights and the analyses computed so far.
+    pub fn from_file<P: AsRef<Path>>(path: P, text: &'a mut String) -> Result<Self, ParseError> {
+        *text = fs::read_to_string(path)?;
+        Grammar::from_rules(text)
+    }
+
+    /// Constructs a grammar from the given start and productions, like
+    /// `from_productions`, but checks that each terminal could be written in
+    /// the text read by `from_rules_with_config` under the given config.
+    /// The errors are those of the text, and their line number is the
+    /// (1-based) index of the production.
+    ///
+    /// A character can be written if it is in the alphabet of the config, if it is
+    /// a space or a byte, or if it can be quoted. Otherwise, the error is
+    /// `NonAsciiTerminal`. A predicate must be in the config, or the error is
+    /// `UnknownSymbol`, and a range must not be empty, or the error is `InvalidRule`.
+    pub fn from_productions_checked<I>(
+        start: NonTerminal<'a>,
+        productions: I,
+        config: &GrammarConfig,
+    ) -> Result<Self, ParseError>
+    where
+        I: IntoIterator<Item = (NonTerminal<'a>, Vec<Token<'a>>)>,
+    {
+        let productions: Vec<_> = productions.into_iter().collect();
+        for (index, (_, to)) in productions.iter().enumerate() {
+            let line_num = index + 1;
+            for token in to.iter() {
+                match token {
+                    Token::T(Terminal::Symbol(ch)) => {
+                        let writable = (config.terminal_chars)(*ch)
+                            || (*ch as u32) < 0x100
+                            || (config.quoted_terminals && !ch.is_whitespace());
+                        if !writable {
+                            return Err(ParseError::NonAsciiTerminal { line_num, ch: *ch });
+                        }
+                    }
+                    Token::T(Terminal::Class(CharClass::Predicate(predicate)))
+                        if !config.predicates.contains(predicate) =>
+                    {
+                        return Err(ParseError::UnknownSymbol {
+                            line_num,
+                            token: format!("{:?}", predicate),
+                        });
+                    }
+                    Token::T(Terminal::Class(CharClass::Range(low, high))) if low > high => {
+                        return Err(ParseError::InvalidRule { line_num });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(Grammar::from_productions(start, productions))
+    }
 
-        Ok(Grammar {
+    /// Reads the grammar from the given source into the given string, like `from_file`.
+    pub fn from_reader<R: Read>(mut reader: R, text: &'a mut String) -> Result<Self, ParseError> {
+        text.clear();
+        reader.read_to_string(text)?;
+        Grammar::from_rules(text)
+    }
+
+    /// Writes the grammar in the format read by `from_rules`: the start nonterminal
+    /// on the first line, then one rule on each line, in order.
+    /// Reading the result back gives an equivalent grammar.
+    pub fn to_grammar_string(&self) -> String {
+        let mut result = String::new();
+        result.push_str(self.start.name);
+        result.push('\n');
+
+        for rule in self.rules.iter() {
+            result.push_str(rule.from.name);
+            result.push_str(" ->");
+            for token in rule.to.iter() {
+                result.push(' ');
+                match token {
+                    Token::NT(n) => result.push_str(n.name),
+                    Token::T(Terminal::Symbol(' ')) => result.push_str(SPACE_ESCAPE),
+                    Token::T(Terminal::Symbol(c)) if !is_plain_terminal(*c) => {
+                        result.push_str(&format!("{}{:02x}", BYTE_ESCAPE, *c as u32))
+                    }
+                    Token::T(t) => result.push_str(&t.to_string()),
+                }
+            }
+            if let Some(name) = rule.name {
+                result.push_str(&format!(" @{}", name));
+            }
+            result.push('\n');
+        }
+        result
+    }
+
+    /// Returns the characters matched by the terminals, sorted and without duplicates.
+    /// The characters of the classes are included, so this is None if a terminal
+    /// is a predicate, whose characters can't be listed.
+    pub fn alphabet(&self) -> Option<Vec<char>> {
+        let mut alphabet = Vec::new();
+        for terminal in self.terminals.iter() {
+            alphabet.extend(terminal.chars()?);
+        }
+        alphabet.sort();
+        alphabet.dedup();
+        Some(alphabet)
+    }
+}
+
+impl<'a, T: Symbol> Grammar<'a, T> {
+    /// Constructs a grammar from the given start and productions, for grammars
+    /// built in code, whose terminals can match any kind of symbol.
+    /// Like in `from_rules`, a production that appears more than once is kept once.
+    pub fn from_productions<I>(start: NonTerminal<'a>, productions: I) -> Self
+    where
+        I: IntoIterator<Item = (NonTerminal<'a>, Vec<Token<'a, T>>)>,
+    {
+        let mut seen = HashSet::new();
+        let rules: Vec<Rule<'a, T>> = productions
+            .into_iter()
+            .map(|(from, to)| Rule {
+                from,
+                to,
+                name: None,
+            })
+            .filter(|rule| seen.insert(rule.clone()))
+            .collect();
+        Grammar::with_symbols(start, rules)
+    }
+
+    /// Constructs the grammar from its parts, and computes the
+    /// information derived from the rules.
+    /// The nonterminals are numbered by name, and every nonterminal of the
+    /// start and of the rules is replaced by the numbered one.
+    fn new(
+        mut nonterminals: HashMap<&'a str, NonTerminal<'a>>,
+        terminals: HashSet<Terminal<'a, T>>,
+        mut rules: Vec<Rule<'a, T>>,
+        start: NonTerminal<'a>,
+    ) -> Self {
+        let mut table: Vec<NonTerminal<'a>> = nonterminals.values().copied().collect();
+        table.sort_by_key(|nonterminal| nonterminal.name);
+        for (id, nonterminal) in table.iter_mut().enumerate() {
+            nonterminal.id = u32::try_from(id)
+                .ok()
+                .filter(|&id| id != NonTerminal::UNBOUND)
+                .expect("Too many nonterminals!");
+            nonterminals.insert(nonterminal.name, *nonterminal);
+        }
+        let bind = |nonterminal: NonTerminal<'a>| nonterminals[nonterminal.name];
+        let start = bind(start);
+        for rule in rules.iter_mut() {
+            rule.from = bind(rule.from);
+            for token in rule.to.iter_mut() {
+                if let Token::NT(nonterminal) = token {
+                    *nonterminal = bind(*nonterminal);
+                }
+            }
+        }
+
+        let mut rules_by_nonterminal = vec![Vec::new(); table.len()];
+        for (index, rule) in rules.iter().enumerate() {
+            rules_by_nonterminal[rule.from.id as usize].push(index);
+        }
+
+        let weights = vec![0.0; rules.len()];
+        let analyses = analysis::AnalysisCache::new(
+            table.iter().copied(),
+            rules
+                .iter()
+                .flat_map(|rule| rule.to.iter())
+                .filter_map(|token| match token {
+                    Token::T(terminal) => Some(*terminal),
+                    Token::NT(_) => None,
+                }),
+        );
+        Grammar {
             nonterminals,
             terminals,
             rules,
             start,
-        })
+            weights,
+            table,
+            rules_by_nonterminal,
+            analyses,
+        }
+    }
+
+    /// Constructs a grammar with the given start and rules, whose
+    /// symbols are the ones used by the rules.
+    fn with_symbols(start: NonTerminal<'a>, rules: Vec<Rule<'a, T>>) -> Self {
+        let mut nonterminals = HashMap::new();
+        let mut terminals = HashSet::new();
+        nonterminals.insert(start.name, start);
+        for rule in rules.iter() {
+            nonterminals.insert(rule.from.name, rule.from);
+            for token in rule.to.iter() {
+                match token {
+                    Token::NT(n) => {
+                        nonterminals.insert(n.name, *n);
+                    }
+                    Token::T(t) => {
+                        terminals.insert(*t);
+                    }
+                }
+            }
+        }
+
+        Grammar::new(nonterminals, terminals, rules, start)
+    }
+
+    /// Returns the indices of the rules of the given nonterminal, in order.
+    fn rules_for(&self, nonterminal: NonTerminal<'a>) -> &[usize] {
+        match self.id_of(nonterminal) {
+            Some(id) => &self.rules_by_nonterminal[id],
+            None => &[],
+        }
+    }
+
+    /// Returns the id of the nonterminal in this grammar, if it has one.
+    /// The id carried by a nonterminal of this grammar is used as is; other
+    /// nonterminals are looked up by name.
+    pub(crate) fn id_of(&self, nonterminal: NonTerminal) -> Option<usize> {
+        let id = nonterminal.id as usize;
+        match self.table.get(id) {
+            Some(bound) if std::ptr::eq(bound.name, nonterminal.name) => Some(id),
+            _ => self
+                .table
+                .binary_search_by_key(&nonterminal.name, |bound| bound.name)
+                .ok(),
+        }
+    }
+
+    /// Returns the nonterminals of the grammar, sorted by name.
+    pub fn nonterminals(&self) -> impl Iterator<Item = NonTerminal<'a>> {
+        self.table.clone().into_iter()
+    }
+
+    /// Returns the terminals of the grammar, sorted: first the symbols, then the classes.
+    pub fn terminals(&self) -> impl Iterator<Item = Terminal<'a, T>> {
+        let mut terminals: Vec<Terminal<T>> = self.terminals.iter().copied().collect();
+        terminals.sort();
+        terminals.into_iter()
+    }
+
+    pub fn rule_count(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn nonterminal_count(&self) -> usize {
+        self.nonterminals.len()
+    }
+
+    pub fn terminal_count(&self) -> usize {
+        self.terminals.len()
+    }
+
+    /// Returns true if some terminal of the grammar matches the symbol.
+    pub fn is_terminal(&self, symbol: T) -> bool {
+        self.terminals.iter().any(|t| t.matches(symbol))
+    }
+
+    /// Returns the terminal of the grammar that matches the symbol. The symbol itself
+    /// is preferred over a character class, and the classes are tried in sorted order.
+    pub fn terminal(&self, symbol: T) -> Option<Terminal<'a, T>> {
+        self.terminals().find(|t| t.matches(symbol))
+    }
+
+    pub fn start(&self) -> NonTerminal<'a> {
+        self.start
+    }
+
+    /// Returns the left and the right side of each rule, in order.
+    /// The index of a rule in this sequence is the index used by `RuleActions`
+    /// and `set_weights`.
+    pub fn rules(&self) -> impl Iterator<Item = (NonTerminal<'a>, &[Token<'a, T>])> {
+        self.rules
+            .iter()
+            .map(|rule| (rule.from, rule.to.as_slice()))
+    }
+
+    /// Returns the right sides of the rules of the nonterminal with the given name,
+    /// in order. Unknown names have no rules.
+    pub fn productions_of(&self, name: &str) -> impl Iterator<Item = &[Token<'a, T>]> {
+        let indices = match self.nonterminals.get(name) {
+            Some(&nonterminal) => self.rules_for(nonterminal),
+            None => &[],
+        };
+        indices.iter().map(|&index| self.rules[index].to.as_slice())
+    }
+
+    /// Returns the label of the rule with the given index, if the rule has one.
+    ///
+    /// Panics if there is no rule with the given index.
+    pub fn rule_name(&self, index: usize) -> Option<&'a str> {
+        self.rules[index].name
+    }
+
+    /// Sets the weight of each rule, as the log-probability of the rule.
+    /// The weights are given in the same order as the rules.
+    /// By default, every rule has weight 0 (probability 1).
+    ///
+    /// Panics if the number of weights differs from the number of rules, or if
+    /// a weight is not a finite log-probability (a finite number, never positive):
+    /// with a positive weight, a cycle of the grammar could improve the score
+    /// of a derivation forever.
+    pub fn set_weights(&mut self, weights: &[f64]) {
+        assert_eq!(
+            weights.len(),
+            self.rules.len(),
+            "Expected one weight for each rule!"
+        );
+        if let Some(index) = weights
+            .iter()
+            .position(|weight| !weight.is_finite() || *weight > 0.0)
+        {
+            panic!(
+                "The weight {} of rule {} is not a finite log-probability!",
+                weights[index], index
+            );
+        }
+        self.weights = weights.to_vec();
+    }
+}
+
+impl<'a> NonTerminal<'a> {
+    const UNBOUND: u32 = u32::MAX;
+
+    /// Returns the nonterminal with the given name, for building grammars in code.
+    pub fn new(name: &'a str) -> Self {
+        NonTerminal {
+            name,
+            id: Self::UNBOUND,
+        }
+    }
+
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+}
+
+impl<T: Symbol> Terminal<'_, T> {
+    /// Returns the symbol matched by the terminal, or None for a class.
+    pub fn content(&self) -> Option<T> {
+        match self {
+            Terminal::Symbol(symbol) => Some(*symbol),
+            Terminal::Class(_) => None,
+        }
+    }
+
+    /// Returns true if the terminal matches the symbol.
+    pub fn matches(&self, symbol: T) -> bool {
+        match self {
+            Terminal::Symbol(content) => *content == symbol,
+            Terminal::Class(class) => symbol.in_class(*class),
+        }
+    }
+}
+
+impl Terminal<'_> {
+    /// Returns the characters matched by the terminal, in order,
+    /// or None for a predicate, whose characters can't be listed.
+    pub fn chars(&self) -> Option<Vec<char>> {
+        match self {
+            Terminal::Symbol(c) => Some(vec![*c]),
+            Terminal::Class(class) => class.chars(),
+        }
+    }
+}
+
+impl CharClass<'_> {
+    pub fn matches(&self, c: char) -> bool {
+        match self {
+            CharClass::Digit => c.is_ascii_digit(),
+            CharClass::Range(first, last) => (*first..=*last).contains(&c),
+            CharClass::Set(chars) => chars.contains(c),
+            CharClass::Predicate(predicate) => (predicate.test)(c),
+        }
+    }
+
+    /// Returns the characters of the class, in order, or None for a predicate,
+    /// which can match any character.
+    pub fn chars(&self) -> Option<Vec<char>> {
+        match self {
+            CharClass::Digit => Some(('0'..='9').collect()),
+            CharClass::Range(first, last) => Some((*first..=*last).collect()),
+            CharClass::Set(chars) => Some(sorted_chars(chars)),
+            CharClass::Predicate(_) => None,
+        }
+    }
+}
+
+/// Returns the characters of a set, sorted and unique.
+fn sorted_chars(set: &str) -> Vec<char> {
+    let mut chars: Vec<char> = set.chars().collect();
+    chars.sort();
+    chars.dedup();
+    chars
+}
+
+impl PartialEq for CharClass<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (CharClass::Digit, CharClass::Digit) => true,
+            (CharClass::Range(a, b), CharClass::Range(c, d)) => (a, b) == (c, d),
+            (CharClass::Set(a), CharClass::Set(b)) => {
+                a == b || (a.chars().all(|c| b.contains(c)) && b.chars().all(|c| a.contains(c)))
+            }
+            (CharClass::Predicate(a), CharClass::Predicate(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for CharClass<'_> {}
+
+impl Hash for CharClass<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            CharClass::Digit => {}
+            CharClass::Range(first, last) => (first, last).hash(state),
+            // Equal sets can be written differently, so only the characters
+            // they hold are hashed, folded into a mask.
+            CharClass::Set(chars) => chars
+                .chars()
+                .fold(0u64, |mask, c| mask | 1 << (c as u32 % 64))
+                .hash(state),
+            CharClass::Predicate(predicate) => predicate.hash(state),
+        }
+    }
+}
+
+impl PartialOrd for CharClass<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CharClass<'_> {
+    /// Orders the classes by kind, in the order of their declaration,
+    /// then by their bounds, their sorted characters, or their predicate.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let kind = |class: &CharClass| match class {
+            CharClass::Digit => 0,
+            CharClass::Range(..) => 1,
+            CharClass::Set(_) => 2,
+            CharClass::Predicate(_) => 3,
+        };
+        match (self, other) {
+            (CharClass::Range(a, b), CharClass::Range(c, d)) => (a, b).cmp(&(c, d)),
+            (CharClass::Set(a), CharClass::Set(b)) => sorted_chars(a).cmp(&sorted_chars(b)),
+            (CharClass::Predicate(a), CharClass::Predicate(b)) => a.cmp(b),
+            _ => kind(self).cmp(&kind(other)),
+        }
+    }
+}
+
+impl CharPredicate {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl PartialEq for CharPredicate {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && std::ptr::fn_addr_eq(self.test, other.test)
+    }
+}
+
+impl Eq for CharPredicate {}
+
+impl Hash for CharPredicate {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        (self.test as usize).hash(state);
+    }
+}
+
+impl PartialOrd for CharPredicate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CharPredicate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.name
+            .cmp(other.name)
+            .then((self.test as usize).cmp(&(other.test as usize)))
+    }
+}
+
+impl fmt::Debug for CharPredicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{{{}}}", PREDICATE_ESCAPE, self.name)
+    }
+}
+
+impl PartialEq for NonTerminal<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.name, other.name) || self.name == other.name
+    }
+}
+
+impl Eq for NonTerminal<'_> {}
+
+impl Hash for NonTerminal<'_> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl fmt::Debug for NonTerminal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NonTerminal")
+            .field("name", &self.name)
+            .finish()
     }
 }
 
@@ -130,13 +1269,32 @@ impl fmt::Display for NonTerminal<'_> {
     }
 }
 
-impl fmt::Display for Terminal {
+impl<T: fmt::Display> fmt::Display for Terminal<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Terminal::Symbol(c) => write!(f, "{}", c),
+            Terminal::Class(class) => write!(f, "{}", class),
+        }
+    }
+}
+
+impl fmt::Display for CharClass<'_> {
+    /// Sets are written with their characters sorted and unique.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.content)
+        match self {
+            CharClass::Digit => write!(f, "{}", DIGIT_ESCAPE),
+            CharClass::Range(first, last) => write!(f, "[{}-{}]", first, last),
+            CharClass::Set(chars) => write!(
+                f,
+                "[{}]",
+                sorted_chars(chars).into_iter().collect::<String>()
+            ),
+            CharClass::Predicate(predicate) => write!(f, "{:?}", predicate),
+        }
     }
 }
 
-impl fmt::Display for Token<'_> {
+impl<T: fmt::Display> fmt::Display for Token<'_, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Token::NT(x) => write!(f, "{}", x),
@@ -145,17 +1303,20 @@ impl fmt::Display for Token<'_> {
     }
 }
 
-impl fmt::Display for Rule<'_> {
+impl<T: fmt::Display> fmt::Display for Rule<'_, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} -> ", self.from)?;
         for token in self.to.iter() {
             write!(f, "{} ", token)?
         }
-        Ok(())
+        match self.name {
+            Some(name) => write!(f, "@{}", name),
+            None => Ok(()),
+        }
     }
 }
 
-impl fmt::Display for Grammar<'_> {
+impl<T: fmt::Display> fmt::Display for Grammar<'_, T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Nonterminals: ")?;
         for (_, v) in self.nonterminals.iter() {
@@ -164,7 +1325,7 @@ impl fmt::Display for Grammar<'_> {
         writeln!(f)?;
 
         write!(f, "Terminals: ")?;
-        for (_, v) in self.terminals.iter() {
+        for v in self.terminals.iter() {
             write!(f, "{}, ", v)?;
         }
 
@@ -180,3 +1341,1143 @@ impl fmt::Display for Grammar<'_> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_comments() {
+        let grammar_string = "# Arithmetic expressions.
+        EXP
+        # Binary operators.
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+
+        #EXP -> EXP - EXP
+        EXP -> ( EXP )
+        # Atoms.
+        EXP -> n";
+        let commented = Grammar::from_rules(grammar_string).unwrap();
+
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        assert!(commented.start == grammar.start);
+        assert!(commented.rules == grammar.rules);
+        for word in ["n+n*n", "(n+n)*n+n", "n-n"] {
+            assert_eq!(commented.parse(word).len(), grammar.parse(word).len());
+        }
+    }
+
+    #[test]
+    fn test_comments_only() {
+        let grammar_string = "# Nothing here.
+        # Still nothing.";
+        assert!(matches!(
+            Grammar::from_rules(grammar_string),
+            Err(ParseError::MissingStart)
+        ));
+
+        let grammar_string = "# The start.
+        S
+        # A bad rule.
+        S -> a ^";
+        assert!(matches!(
+            Grammar::from_rules(grammar_string),
+            Err(ParseError::UnknownSymbol { line_num: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let grammar_string = "S
+
+        S -> NP VP
+
+        VP -> VP PP
+        VP -> V NP
+        VP -> V
+        PP -> P NP
+
+        NP -> DET N
+        NP -> N
+        NP -> PN
+        NP -> DET A N
+        NP -> A NP
+
+
+        A -> f r e s h
+        A -> s i l v e r
+        PN -> s h e
+        N -> f i s h
+        N -> f o r k
+        V -> e a t s
+        DET -> a
+        P -> w i t h
+        ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.rules.len(), 18);
+
+        let trees = grammar.parse("sheeatsafishwithasilverfork");
+        assert_eq!(trees.len(), 1);
+
+        // The line numbers of the errors count the blank lines.
+        let grammar_string = "S
+
+        S -> NP VP
+
+        NP -> n p ?";
+        assert!(matches!(
+            Grammar::from_rules(grammar_string),
+            Err(ParseError::UnknownSymbol { line_num: 5, .. })
+        ));
+    }
+
+    #[test]
+    fn test_space_terminal() {
+        let grammar_string = r"S
+        S -> a \s b
+        S -> a \s S";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.rules[0].to[1] == Token::T(Terminal::Symbol(' ')));
+
+        let trees = grammar.parse("a b");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].children.len(), 3);
+        assert!(trees[0].children[1].token == Token::T(Terminal::Symbol(' ')));
+
+        assert_eq!(grammar.parse("a a a b").len(), 1);
+        assert_eq!(grammar.parse("ab").len(), 0);
+        assert_eq!(grammar.parse("a  b").len(), 0);
+    }
+
+    #[test]
+    fn test_grammar_string() {
+        let grammar_string = r"EXP
+        EXP -> EXP + EXP
+        EXP -> EXP \s * \s EXP
+        EXP -> ( EXP )
+        EXP -> N
+        N -> n
+        N -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let written = grammar.to_grammar_string();
+        assert_eq!(
+            written,
+            "EXP\nEXP -> EXP + EXP\nEXP -> EXP \\s * \\s EXP\nEXP -> ( EXP )\nEXP -> N\nN -> n\nN ->\n"
+        );
+
+        let read = Grammar::from_rules(&written).unwrap();
+        assert!(read.start == grammar.start);
+        assert!(read.rules == grammar.rules);
+        for word in ["n+n * n", "(n+)+n", "+ * ", "n*n"] {
+            assert_eq!(read.parse(word).len(), grammar.parse(word).len());
+        }
+    }
+
+    #[test]
+    fn test_grammar_string_transformed() {
+        let grammar_string = "S
+        S -> a S b
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let cnf = grammar.to_cnf(&mut names);
+
+        let written = cnf.to_grammar_string();
+        let read = Grammar::from_rules(&written).unwrap();
+        assert!(read.start == cnf.start);
+        assert!(read.rules == cnf.rules);
+        for word in ["", "ab", "aabb", "aab"] {
+            assert_eq!(read.parse(word).len(), cnf.parse(word).len());
+        }
+    }
+
+    #[test]
+    fn test_line_endings() {
+        let grammar_string = "EXP\nEXP -> EXP + EXP\nEXP -> ( EXP )\nEXP -> n\n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let windows_string = "EXP\r\nEXP -> EXP + EXP \r\n\r\nEXP -> ( EXP )\t\r\nEXP -> n\r\n";
+        let windows = Grammar::from_rules(windows_string).unwrap();
+        assert!(windows.start == grammar.start);
+        assert!(windows.rules == grammar.rules);
+
+        let bom_string = "\u{FEFF}EXP\r\nEXP -> EXP + EXP\r\nEXP -> ( EXP )\r\nEXP -> n";
+        let bom = Grammar::from_rules(bom_string).unwrap();
+        assert!(bom.start == grammar.start);
+        assert!(bom.rules == grammar.rules);
+        assert_eq!(bom.parse("(n+n)+n").len(), grammar.parse("(n+n)+n").len());
+
+        // The line numbers are the same with both line endings.
+        assert!(matches!(
+            Grammar::from_rules("S\r\n\r\nS -> a\r\nS -> %\r\n"),
+            Err(ParseError::UnknownSymbol { line_num: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let grammar_string = "EXP\nEXP -> EXP + EXP\nEXP -> n\n";
+        let mut text = String::from("S\nS -> a");
+        let mut grammar = Grammar::from_reader(grammar_string.as_bytes(), &mut text).unwrap();
+        assert_eq!(grammar.parse("n+n+n").len(), 2);
+        // The grammar keeps its weights and analyses while it is used.
+        grammar.set_weights(&[-1.0, 0.0]);
+        assert_eq!(grammar.parse_best("n+n").unwrap().1, -1.0);
+        assert_eq!(text, grammar_string);
+
+        let mut text = String::new();
+        assert!(matches!(
+            Grammar::from_reader("S\nS -> %".as_bytes(), &mut text),
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+        let mut text = String::new();
+        assert!(matches!(
+            Grammar::from_reader(&[0xFF, 0xFE][..], &mut text),
+            Err(ParseError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join(format!("grammar_{}", std::process::id()));
+        fs::write(&path, "S\nS -> a S\nS -> ").unwrap();
+        let mut text = String::new();
+        let grammar = Grammar::from_file(&path, &mut text);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(grammar.unwrap().parse("aaa").len(), 1);
+
+        let mut text = String::new();
+        assert!(matches!(
+            Grammar::from_file(&path, &mut text),
+            Err(ParseError::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let grammar_string = r"EXP
+        EXP -> EXP + TERM
+        EXP -> TERM
+        TERM -> ( EXP )
+        TERM -> n
+        TERM -> \s";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        assert_eq!(grammar.start().name(), "EXP");
+        let names: Vec<&str> = grammar.nonterminals().map(|n| n.name()).collect();
+        assert_eq!(names, vec!["EXP", "TERM"]);
+        let contents: Vec<char> = grammar.terminals().filter_map(|t| t.content()).collect();
+        assert_eq!(contents, vec![' ', '(', ')', '+', 'n']);
+
+        let rules: Vec<(NonTerminal, &[Token])> = grammar.rules().collect();
+        assert_eq!(rules.len(), 5);
+        assert_eq!(rules[2].0.name(), "TERM");
+        assert_eq!(rules[2].1.len(), 3);
+        assert!(rules[2].1[1] == Token::NT(grammar.start()));
+        assert!(rules[4].1 == [Token::T(Terminal::Symbol(' '))]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let grammar_string = "EXP
+        EXP -> EXP + TERM
+        EXP -> TERM
+        TERM -> ( EXP )
+        TERM -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.rule_count(), 4);
+        assert_eq!(grammar.nonterminal_count(), 2);
+        assert_eq!(grammar.terminal_count(), 4);
+
+        let limits = GrammarLimits {
+            max_rules: 4,
+            max_rule_length: 3,
+            max_nonterminals: 2,
+        };
+        assert!(Grammar::from_rules_limited(grammar_string, limits).is_ok());
+
+        let too_many_rules = GrammarLimits {
+            max_rules: 3,
+            ..limits
+        };
+        assert!(matches!(
+            Grammar::from_rules_limited(grammar_string, too_many_rules),
+            Err(ParseError::LimitExceeded {
+                line_num: 5,
+                limit: Limit::Rules
+            })
+        ));
+
+        let too_long = GrammarLimits {
+            max_rule_length: 2,
+            ..limits
+        };
+        assert!(matches!(
+            Grammar::from_rules_limited(grammar_string, too_long),
+            Err(ParseError::LimitExceeded {
+                line_num: 2,
+                limit: Limit::RuleLength
+            })
+        ));
+
+        let too_many_nonterminals = GrammarLimits {
+            max_nonterminals: 1,
+            ..limits
+        };
+        assert!(matches!(
+            Grammar::from_rules_limited(grammar_string, too_many_nonterminals),
+            Err(ParseError::LimitExceeded {
+                line_num: 2,
+                limit: Limit::Nonterminals
+            })
+        ));
+    }
+
+    #[test]
+    fn test_duplicated_rules() {
+        let grammar_string = "S
+        S -> A A
+        A -> a A
+        A -> b
+        A -> a A
+        A -> b";
+        let duplicated = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(duplicated.rule_count(), 3);
+
+        let grammar_string = "S
+        S -> A A
+        A -> a A
+        A -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(duplicated.rules == grammar.rules);
+        for word in ["bb", "abab", "aabb", "ab"] {
+            assert_eq!(duplicated.parse(word).len(), grammar.parse(word).len());
+        }
+    }
+
+    #[test]
+    fn test_nonterminal_names() {
+        let grammar_string = "S
+        S -> NP1 VP
+        NP1 -> DET N
+        NP1 -> NP_2
+        NP_2 -> n
+        VP -> v NP_2 1
+        DET -> d
+        N -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let names: Vec<&str> = grammar.nonterminals().map(|n| n.name()).collect();
+        assert_eq!(names, vec!["DET", "N", "NP1", "NP_2", "S", "VP"]);
+        assert!(grammar.rules[4].to[2] == Token::T(Terminal::Symbol('1')));
+        assert_eq!(grammar.parse("dnvn1").len(), 1);
+        assert_eq!(grammar.parse("nvn1").len(), 1);
+
+        // Names must start with an uppercase letter.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> _A"),
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> 1A"),
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+        assert!(matches!(
+            Grammar::from_rules("1S\nS -> a"),
+            Err(ParseError::InvalidStart)
+        ));
+    }
+
+    #[test]
+    fn test_malformed_grammars() {
+        let inputs = [
+            "",
+            "   \n\t\n  ",
+            "\u{FEFF}",
+            "->",
+            "S S",
+            "s",
+            "S\n->",
+            "S\n -> a",
+            "S\nS",
+            "S\nS S -> a",
+            "S\nS -> ->",
+            "S\nS -> \\",
+            "S\n\\",
+            "S\nS -> a \\",
+            "S\nS -> [",
+            "S\nS -> [a-",
+            "S\nS -> [z-a]",
+            "S\nS -> \\x",
+            "S\nS -> \\xZZ",
+            "S\nS -> \\x123",
+            "S\nS -> @",
+            "S\nS -> a @",
+            "S\nS -> @label a",
+            "S\nS -> \u{e9}",
+            "S\nS -> \u{1F600}",
+            "S\nS -> a\u{0}",
+            "S\nS -> \u{03B5} \u{03B5}",
+        ];
+        for input in inputs {
+            assert!(
+                Grammar::from_rules(input).is_err(),
+                "{:?} was read as a grammar",
+                input
+            );
+        }
+
+        // Odd, but valid.
+        for input in ["S\nS ->", "S ->\n", "\u{FEFF}S\r\nS -> a\r\n"] {
+            assert!(
+                Grammar::from_rules(input).is_ok(),
+                "{:?} was rejected",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_epsilon_marker() {
+        let blank = Grammar::from_rules("S\nS -> a b C d e\nC -> D\nD -> E\nE -> ").unwrap();
+        for marker in ["\u{03B5}", "_eps"] {
+            let grammar_string = format!("S\nS -> a b C d e\nC -> D\nD -> E\nE -> {}", marker);
+            let grammar = Grammar::from_rules(&grammar_string).unwrap();
+            assert!(grammar.rules[3].to.is_empty());
+            assert_eq!(grammar.to_grammar_string(), blank.to_grammar_string());
+            assert_eq!(grammar.parse("abde"), blank.parse("abde"));
+        }
+
+        let grammar = Grammar::from_rules("S\nS -> _eps @empty").unwrap();
+        assert_eq!(grammar.rule_name(0), Some("empty"));
+
+        // The marker must be the only symbol.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> a \u{03B5} b"),
+            Err(ParseError::InvalidRule { line_num: 2 })
+        ));
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> _eps _eps"),
+            Err(ParseError::InvalidRule { line_num: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_inline_comments() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP   # addition
+        EXP -> EXP * EXP\t# multiplication, with # inside
+        EXP -> n #
+        EXP -> \\
+            ( EXP ) # the continued line is commented too
+        # a whole line
+        EXP -> # the empty word";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let plain = Grammar::from_rules(
+            "EXP\nEXP -> EXP + EXP\nEXP -> EXP * EXP\nEXP -> n\nEXP -> ( EXP )\nEXP -> ",
+        )
+        .unwrap();
+        assert_eq!(grammar.to_grammar_string(), plain.to_grammar_string());
+
+        // When '#' is a terminal, it is kept in the rules.
+        let config = GrammarConfig {
+            terminal_chars: |c| c == '#' || c.is_ascii_lowercase(),
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_rules_with_config("S\nS -> a # b", &config).unwrap();
+        assert!(grammar.recognizes("a#b"));
+
+        // A '#' inside a word is not a comment.
+        let grammar = Grammar::from_rules("S\nS -> [#a] S # a set\nS -> ").unwrap();
+        assert!(grammar.recognizes("#a#"));
+        let config = GrammarConfig {
+            quoted_terminals: true,
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_rules_with_config("S\nS -> '# b #'#", &config).unwrap();
+        assert!(grammar.recognizes("#b"));
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> a#b"),
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_bnf_arrow() {
+        let arrow = Grammar::from_rules("S\nS -> a S b\nS -> \nS -> c @c").unwrap();
+        let bnf = Grammar::from_rules("S\nS ::= a S b\nS ::=\nS ::= c @c").unwrap();
+        assert_eq!(bnf.to_grammar_string(), arrow.to_grammar_string());
+        assert_eq!(bnf.parse("aacbb"), arrow.parse("aacbb"));
+        assert_eq!(bnf.parse("ab"), arrow.parse("ab"));
+
+        // The arrows can be mixed, and the first line can be a BNF rule.
+        let mixed = Grammar::from_rules("S ::= a S b\nS -> \nS ::= c @c").unwrap();
+        assert_eq!(mixed.to_grammar_string(), arrow.to_grammar_string());
+
+        // The arrow is a word of its own.
+        assert!(matches!(
+            Grammar::from_rules("S\nS ::=a"),
+            Err(ParseError::InvalidRule { line_num: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_start_rule_header() {
+        let bare = Grammar::from_rules("S\nS -> a S\nS -> b").unwrap();
+        let inline = Grammar::from_rules("S -> a S\nS -> b").unwrap();
+        assert_eq!(inline.start().to_string(), "S");
+        assert_eq!(inline.rule_count(), 2);
+        assert_eq!(inline.to_grammar_string(), bare.to_grammar_string());
+        assert_eq!(inline.parse("aab"), bare.parse("aab"));
+
+        let inline = Grammar::from_rules("# comment\nS -> a\nA -> b @label").unwrap();
+        assert_eq!(inline.rule_name(1), Some("label"));
+
+        // Errors in the inline rule point at its line.
+        assert!(matches!(
+            Grammar::from_rules("S -> a ^"),
+            Err(ParseError::UnknownSymbol { line_num: 1, .. })
+        ));
+        assert!(matches!(
+            Grammar::from_rules("S ->> a"),
+            Err(ParseError::StartLineHasRule { line_num: 1 })
+        ));
+    }
+
+    #[test]
+    fn test_start_line_errors() {
+        let start_error = |grammar| Grammar::from_rules(grammar).err().unwrap();
+
+        // A first line with an arrow that isn't a rule of a nonterminal.
+        for grammar in [
+            "s -> a",
+            "S A -> a",
+            "-> a",
+            "S->a",
+            "\n\n  # start\nS ::=a\nS -> a",
+        ] {
+            assert!(
+                matches!(start_error(grammar), ParseError::StartLineHasRule { .. }),
+                "{:?}",
+                grammar
+            );
+        }
+        assert!(matches!(
+            start_error("\n\ns -> a\nS -> a"),
+            ParseError::StartLineHasRule { line_num: 3 }
+        ));
+
+        // A first line without an arrow that isn't a nonterminal.
+        for grammar in ["s\nS -> a", "S A\nS -> a", "1S", "S a"] {
+            assert!(
+                matches!(start_error(grammar), ParseError::InvalidStart),
+                "{:?}",
+                grammar
+            );
+        }
+
+        // Blank lines and comments before the start are skipped.
+        for grammar in ["", "  \n\t\n", "# only a comment\n   "] {
+            assert!(
+                matches!(start_error(grammar), ParseError::MissingStart),
+                "{:?}",
+                grammar
+            );
+        }
+        let grammar = Grammar::from_rules("\n   \n\t# start\n  S  \n\nS -> a").unwrap();
+        assert_eq!(grammar.start().to_string(), "S");
+        assert!(grammar.recognizes("a"));
+    }
+
+    #[test]
+    fn test_unknown_symbol() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP ^ EXP
+        EXP -> n";
+        match Grammar::from_rules(grammar_string) {
+            Err(ParseError::UnknownSymbol { line_num, token }) => {
+                assert_eq!(line_num, 3);
+                assert_eq!(token, "^");
+            }
+            _ => panic!("Expected an unknown symbol!"),
+        }
+
+        // The first unknown word of the rule is reported.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> a Bc ab"),
+            Err(ParseError::UnknownSymbol { token, .. }) if token == "Bc"
+        ));
+
+        // The characters outside of ASCII are reported on their own.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> caf\u{e9} A\nA -> a \u{e9}"),
+            Err(ParseError::NonAsciiTerminal {
+                line_num: 2,
+                ch: '\u{e9}'
+            })
+        ));
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> c a f A\nA -> a \u{e9}"),
+            Err(ParseError::NonAsciiTerminal {
+                line_num: 3,
+                ch: '\u{e9}'
+            })
+        ));
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> a\u{1F600}"),
+            Err(ParseError::NonAsciiTerminal {
+                line_num: 2,
+                ch: '\u{1F600}'
+            })
+        ));
+        let config = GrammarConfig {
+            terminal_chars: |c| c == '\u{e9}' || c.is_ascii_lowercase(),
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_rules_with_config("S\nS -> c a f \u{e9}", &config).unwrap();
+        assert!(grammar.recognizes("caf\u{e9}"));
+        let grammar = Grammar::from_rules("S\nS -> c a f \\xe9").unwrap();
+        assert!(grammar.recognizes("caf\u{e9}"));
+
+        // Rules without a valid left side or arrow are still invalid as a whole.
+        for line in ["s -> a", "S => a", "S ->a", "S a"] {
+            assert!(matches!(
+                Grammar::from_rules(&format!("S\n{}", line)),
+                Err(ParseError::InvalidRule { line_num: 2 })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rule_names() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP  @add
+        EXP -> ( EXP ) @Paren_2
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.rule_name(0), Some("add"));
+        assert_eq!(grammar.rule_name(1), Some("Paren_2"));
+        assert_eq!(grammar.rule_name(2), None);
+        assert_eq!(grammar.rules[0].to_string(), "EXP -> EXP + EXP @add");
+        assert_eq!(grammar.rules[2].to_string(), "EXP -> n ");
+        assert_eq!(grammar.parse("(n+n)").len(), 1);
+
+        let written = grammar.to_grammar_string();
+        assert_eq!(
+            written,
+            "EXP\nEXP -> EXP + EXP @add\nEXP -> ( EXP ) @Paren_2\nEXP -> n\n"
+        );
+        assert!(Grammar::from_rules(&written).unwrap().rules == grammar.rules);
+
+        // The same production with different labels gives different rules.
+        let grammar_string = "S
+        S -> a @first
+        S -> a @second
+        S -> a @first";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.rule_count(), 2);
+
+        // The label must be the last word, and must be a valid name.
+        for line in ["S -> @x a", "S -> a @x @y"] {
+            assert!(matches!(
+                Grammar::from_rules(&format!("S\n{}", line)),
+                Err(ParseError::InvalidRule { line_num: 2 })
+            ));
+        }
+        for line in ["S -> a @", "S -> a @1x"] {
+            assert!(matches!(
+                Grammar::from_rules(&format!("S\n{}", line)),
+                Err(ParseError::UnknownSymbol { line_num: 2, .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_character_classes() {
+        let grammar_string = "S
+        S -> \\d [a-c] [0-1]";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.rules[0].to[0] == Token::T(Terminal::Class(CharClass::Digit)));
+        assert!(grammar.rules[0].to[1] == Token::T(Terminal::Class(CharClass::Range('a', 'c'))));
+        assert_eq!(grammar.to_grammar_string(), "S\nS -> \\d [a-c] [0-1]\n");
+        assert_eq!(grammar.terminal_count(), 3);
+
+        let words: Vec<String> = grammar.enumerate(3).collect();
+        assert_eq!(words.len(), 60);
+        assert_eq!(words[0], "0a0");
+
+        let grammar_string = "S
+        S -> \\x00 \\xFF a \\x2b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.rules[0].to[1] == Token::T(Terminal::Symbol('\u{ff}')));
+        assert_eq!(grammar.to_grammar_string(), "S\nS -> \\x00 \\xff a +\n");
+
+        // A range can't be empty.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> [f-a]"),
+            Err(ParseError::InvalidRule { line_num: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_quoted_terminals() {
+        let grammar_string = "S
+        S -> 'A S 'B
+        S -> A
+        A -> 'C
+        A -> c '' \\s";
+        let config = GrammarConfig {
+            quoted_terminals: true,
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_rules_with_config(grammar_string, &config).unwrap();
+        assert!(grammar.rules[0].to[0] == Token::T(Terminal::Symbol('A')));
+        assert!(grammar.rules[1].to[0] == Token::NT(NonTerminal::new("A")));
+        assert!(grammar.recognizes("AACBB"));
+        assert!(grammar.recognizes("Ac' B"));
+        assert!(!grammar.recognizes("AaCBB"));
+
+        // Even with uppercase letters in the alphabet, an uppercase word is a nonterminal.
+        let config = GrammarConfig {
+            terminal_chars: |c| c.is_ascii_alphabetic(),
+            quoted_terminals: true,
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_rules_with_config("S\nS -> 'S S\nS -> s", &config).unwrap();
+        assert!(grammar.recognizes("SSs"));
+
+        // Without the option, the quote is an unknown symbol.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> 'A"),
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_character_sets() {
+        let grammar_string = "WORD
+        WORD -> C VOWEL C
+        VOWEL -> [uoiea]
+        C -> [bcd]
+        C -> [+-]";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let vowels = Terminal::Class(CharClass::Set("aeiou"));
+        assert!(grammar.rules[1].to[0] == Token::T(vowels));
+        assert!(grammar.rules[3].to[0] == Token::T(Terminal::Class(CharClass::Set("+-"))));
+
+        // The sets borrow their characters as written, but are compared as sets.
+        let sets = HashSet::from([CharClass::Set("cab"), CharClass::Set("abca")]);
+        assert_eq!(sets.len(), 1);
+        assert_ne!(CharClass::Set("ab"), CharClass::Set("abc"));
+        assert_eq!(
+            CharClass::Set("ba").cmp(&CharClass::Set("ab")),
+            std::cmp::Ordering::Equal
+        );
+
+        for word in ["bad", "cub", "dic", "+o-", "beb"] {
+            let trees = grammar.parse(word);
+            assert_eq!(trees.len(), 1);
+            // The leaf holds the matched character.
+            let vowel = word.chars().nth(1).unwrap();
+            assert_eq!(trees[0].children[1].children[0].as_terminal(), Some(vowel));
+        }
+        assert!(!grammar.recognizes("bxb"));
+        assert!(!grammar.recognizes("bab+"));
+
+        // The set is written back sorted, and three characters with a dash in
+        // the middle are a range, not a set.
+        let grammar = Grammar::from_rules("S\nS -> [cba] [a-c] [a-] [aa]").unwrap();
+        assert_eq!(
+            grammar.to_grammar_string(),
+            "S\nS -> [abc] [a-c] [-a] [a]\n"
+        );
+        assert!(grammar.recognizes("cb-a"));
+        assert!(!grammar.recognizes("cbba"));
+    }
+
+    #[test]
+    fn test_predicate_terminals() {
+        let grammar_string = "NUM
+        NUM -> \\p{digit} NUM
+        NUM -> \\p{digit}";
+        let config = GrammarConfig::default().terminal_pred("digit", |c| c.is_ascii_digit());
+        let grammar = Grammar::from_rules_with_config(grammar_string, &config).unwrap();
+
+        let trees = grammar.parse("2024");
+        assert_eq!(trees.len(), 1);
+        // The leaves hold the matched characters.
+        assert_eq!(trees[0].span_text("2024"), "2024");
+        assert_eq!(trees[0].children[0].as_terminal(), Some('2'));
+        assert!(!grammar.recognizes("20a4"));
+        assert!(!grammar.recognizes(""));
+        assert_eq!(
+            grammar.to_grammar_string(),
+            "NUM\nNUM -> \\p{digit} NUM\nNUM -> \\p{digit}\n"
+        );
+
+        // Any character can be matched, not only the ones that can be written.
+        let config = GrammarConfig::default().terminal_pred("space", char::is_whitespace);
+        let grammar = Grammar::from_rules_with_config("S\nS -> a \\p{space} b", &config).unwrap();
+        assert!(grammar.recognizes("a b"));
+        assert!(grammar.recognizes("a\u{2003}b"));
+        assert!(!grammar.recognizes("a_b"));
+
+        // Two predicates with the same name but different tests are different terminals.
+        let alpha = GrammarConfig::default().terminal_pred("class", char::is_alphabetic);
+        let digit = GrammarConfig::default().terminal_pred("class", char::is_numeric);
+        assert_ne!(alpha.predicates[0], digit.predicates[0]);
+        assert_eq!(alpha.predicates[0], alpha.clone().predicates[0]);
+        let mut terminals = HashSet::new();
+        for config in [&alpha, &digit, &alpha] {
+            let grammar = Grammar::from_rules_with_config("S\nS -> \\p{class}", config).unwrap();
+            terminals.extend(grammar.terminals());
+        }
+        assert_eq!(terminals.len(), 2);
+
+        // The predicate must be given.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> \\p{digit}"),
+            Err(ParseError::UnknownSymbol { line_num: 2, token }) if token == "\\p{digit}"
+        ));
+    }
+
+    #[test]
+    fn test_token_declarations() {
+        let grammar_string = "%tokens IF = \"if\" ; NUM = [0-9] ;
+        THEN = \"then\" ;
+        S
+        S -> IF C THEN S
+        S -> x
+        C -> NUM C
+        C -> NUM";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.recognizes("if42thenx"));
+        assert!(grammar.recognizes("if1thenif2thenx"));
+        assert!(!grammar.recognizes("ifthenx"));
+        assert!(!grammar.recognizes("i42thenx"));
+        // The tokens are expanded, so the grammar reads back without them.
+        let written = grammar.to_grammar_string();
+        assert!(written.contains("S -> i f C t h e n S"));
+        assert!(Grammar::from_rules(&written)
+            .unwrap()
+            .recognizes("if7thenx"));
+
+        // A name without a declaration or rules is reported.
+        assert!(matches!(
+            Grammar::from_rules("%tokens IF = \"if\" ;\nS -> IFF S\nS -> x"),
+            Err(ParseError::UnknownSymbol { line_num: 2, token }) if token == "IFF"
+        ));
+        // Declarations are checked.
+        for section in [
+            "%tokens IF = \"if\"",
+            "%tokens IF \"if\" ;",
+            "%tokens IF = \"\" ;",
+        ] {
+            let grammar_string = format!("{}\nS -> IF", section);
+            assert!(matches!(
+                Grammar::from_rules(&grammar_string),
+                Err(ParseError::InvalidRule { line_num: 1 })
+            ));
+        }
+        assert!(matches!(
+            Grammar::from_rules("%tokens IF = \"if\" ;\nIF -> x"),
+            Err(ParseError::InvalidStart)
+        ));
+        // Without the section, the same names are plain nonterminals.
+        assert!(Grammar::from_rules("S -> IF S\nS -> x").is_ok());
+    }
+
+    #[test]
+    fn test_nonterminal_equality() {
+        // The same name gives equal nonterminals, whatever string holds it.
+        let text = String::from("EXP");
+        let grammar = Grammar::from_rules("EXP\nEXP -> n").unwrap();
+        assert_eq!(grammar.start(), NonTerminal::new(&text));
+        assert_ne!(grammar.start(), NonTerminal::new("EXPR"));
+        let set: HashSet<NonTerminal> = HashSet::from([NonTerminal::new(&text)]);
+        assert!(set.contains(&grammar.start()));
+
+        // The grammar numbers its nonterminals by name, and its rules use them.
+        let grammar = Grammar::from_rules("S\nS -> A B\nB -> b\nA -> a").unwrap();
+        let ids: Vec<u32> = grammar.nonterminals().map(|n| n.id).collect();
+        assert_eq!(ids, [0, 1, 2]);
+        for (from, to) in grammar.rules() {
+            assert_eq!(grammar.id_of(from), Some(from.id as usize));
+            for token in to {
+                if let Token::NT(nonterminal) = token {
+                    assert_eq!(grammar.id_of(*nonterminal), Some(nonterminal.id as usize));
+                }
+            }
+        }
+        assert_eq!(grammar.id_of(NonTerminal::new("B")), Some(1));
+        assert_eq!(grammar.id_of(NonTerminal::new("C")), None);
+        let grammar = Grammar::from_rules("EXP\nEXP -> n").unwrap();
+
+        assert_eq!(format!("{}", grammar.start()), "EXP");
+        assert_eq!(
+            format!("{:?}", grammar.start()),
+            "NonTerminal { name: \"EXP\" }"
+        );
+    }
+
+    #[test]
+    fn test_from_productions_checked() {
+        let s = NonTerminal::new("S");
+        let productions = || {
+            vec![
+                (s, vec![Token::T(Terminal::Symbol('a')), Token::NT(s)]),
+                (s, vec![Token::T(Terminal::Symbol('\u{20ac}'))]),
+            ]
+        };
+        let config = GrammarConfig::default();
+        // The euro sign is neither in the alphabet nor a byte.
+        assert!(matches!(
+            Grammar::from_productions_checked(s, productions(), &config),
+            Err(ParseError::NonAsciiTerminal {
+                line_num: 2,
+                ch: '\u{20ac}'
+            })
+        ));
+
+        // It can be written once it is quoted.
+        let config = GrammarConfig {
+            quoted_terminals: true,
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_productions_checked(s, productions(), &config).unwrap();
+        assert!(grammar.recognizes("aa\u{20ac}"));
+
+        // A byte can always be written, and the predicates must be in the config.
+        let byte = vec![(s, vec![Token::T(Terminal::Symbol('\u{e9}'))])];
+        assert!(Grammar::from_productions_checked(s, byte, &GrammarConfig::default()).is_ok());
+        let predicate = CharPredicate {
+            name: "digit",
+            test: |c| c.is_ascii_digit(),
+        };
+        let class = vec![(
+            s,
+            vec![Token::T(Terminal::Class(CharClass::Predicate(predicate)))],
+        )];
+        assert!(matches!(
+            Grammar::from_productions_checked(s, class, &GrammarConfig::default()),
+            Err(ParseError::UnknownSymbol { line_num: 1, token }) if token == "\\p{digit}"
+        ));
+    }
+
+    #[test]
+    fn test_line_continuation() {
+        let grammar_string = "S
+        S -> NP VP \\
+             @sentence
+        NP -> d \\
+              n
+        VP -> v NP";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let single = Grammar::from_rules(
+            "S
+        S -> NP VP @sentence
+        NP -> d n
+        VP -> v NP",
+        )
+        .unwrap();
+        assert_eq!(grammar.to_grammar_string(), single.to_grammar_string());
+        assert_eq!(grammar.parse("dnvdn"), single.parse("dnvdn"));
+
+        // Errors point at the first line of the rule.
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> a \\\n  b %"),
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+        assert!(matches!(
+            Grammar::from_rules("S\nS -> a\nS -> b \\"),
+            Err(ParseError::InvalidRule { line_num: 3 })
+        ));
+    }
+
+    #[test]
+    fn test_terminal_alphabet() {
+        let grammar_string = "BLOCK
+        BLOCK -> { STMTS }
+        STMTS -> STMTS STMT
+        STMTS ->
+        STMT -> x = N ;
+        STMT -> BLOCK
+        N -> 1";
+        assert!(matches!(
+            Grammar::from_rules(grammar_string),
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+
+        let config = GrammarConfig {
+            terminal_chars: |c| !c.is_whitespace() && !c.is_ascii_uppercase(),
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_rules_with_config(grammar_string, &config).unwrap();
+        assert!(grammar.is_terminal('{') && grammar.is_terminal(';'));
+        assert_eq!(grammar.parse("{x=1;{}x=1;}").len(), 1);
+        assert!(grammar.parse("{x=1}").is_empty());
+
+        // Terminals are tried first, so a single uppercase letter in the alphabet is a terminal.
+        let config = GrammarConfig {
+            terminal_chars: |c| c.is_ascii_alphabetic(),
+            ..GrammarConfig::default()
+        };
+        let grammar = Grammar::from_rules_with_config("S\nS -> A AB\nAB -> b", &config).unwrap();
+        assert_eq!(grammar.parse("Ab").len(), 1);
+    }
+
+    #[test]
+    fn test_productions_of() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        T -> n
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let productions: Vec<String> = grammar
+            .productions_of("EXP")
+            .map(|to| {
+                let words: Vec<String> = to.iter().map(|token| token.to_string()).collect();
+                words.join(" ")
+            })
+            .collect();
+        assert_eq!(productions, vec!["EXP + EXP", "( EXP )", "n"]);
+        assert_eq!(grammar.productions_of("T").count(), 1);
+        assert_eq!(grammar.productions_of("MISSING").count(), 0);
+    }
+
+    #[test]
+    fn test_rules_with_start() {
+        let rules = "EXP -> TERM + EXP
+        EXP -> TERM
+        TERM -> n * TERM
+        TERM -> n";
+        let expression = Grammar::from_rules_with_start(rules, "EXP").unwrap();
+        let term = Grammar::from_rules_with_start(rules, "TERM").unwrap();
+        assert_eq!(expression.start().name(), "EXP");
+        assert_eq!(term.start().name(), "TERM");
+        assert_eq!(expression.rule_count(), 4);
+        assert_eq!(term.rule_count(), 4);
+
+        assert!(expression.recognizes("n*n+n"));
+        assert!(!term.recognizes("n*n+n"));
+        assert!(expression.recognizes("n*n"));
+        assert!(term.recognizes("n*n"));
+
+        // With a line for the start, `from_rules` reads the same grammar.
+        let grammar_string = format!("EXP\n{}", rules);
+        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        assert_eq!(grammar.to_grammar_string(), expression.to_grammar_string());
+
+        // The start must have a rule.
+        for start in ["FACTOR", "exp", ""] {
+            assert!(matches!(
+                Grammar::from_rules_with_start(rules, start),
+                Err(ParseError::InvalidStart)
+            ));
+        }
+        assert!(matches!(
+            Grammar::from_rules_with_start("", "EXP"),
+            Err(ParseError::InvalidStart)
+        ));
+    }
+
+    #[test]
+    fn test_try_from() {
+        let grammar: Grammar = "S\nS -> a S\nS -> a".try_into().unwrap();
+        assert!(grammar.recognizes("aaa"));
+
+        let result: Result<Grammar, ParseError> = "S\nS -> a ^".try_into();
+        assert!(matches!(
+            result,
+            Err(ParseError::UnknownSymbol { line_num: 2, .. })
+        ));
+
+        // The errors can be returned through a boxed error.
+        fn read(text: &str) -> Result<usize, Box<dyn std::error::Error>> {
+            let grammar = Grammar::try_from(text)?;
+            Ok(grammar.rule_count())
+        }
+        assert_eq!(read("S\nS -> a").unwrap(), 1);
+        let error = read("S\nS -> a ^").unwrap_err();
+        assert_eq!(error.to_string(), "Unknown symbol ^ on line 2");
+    }
+
+    #[test]
+    fn test_clone() {
+        let grammar_string = "S
+        S -> a S
+        S -> b";
+        let mut grammar = Grammar::from_rules(grammar_string).unwrap();
+        let copy = grammar.clone();
+        grammar.set_weights(&[-1.0, -2.0]);
+
+        assert_eq!(copy.to_grammar_string(), grammar.to_grammar_string());
+        assert_eq!(copy.parse("aab"), grammar.parse("aab"));
+        assert_eq!(copy.weights, vec![0.0, 0.0]);
+        assert_eq!(grammar.parse_best("ab").unwrap().1, -3.0);
+        assert_eq!(copy.parse_best("ab").unwrap().1, 0.0);
+
+        // The clone outlives the original.
+        drop(grammar);
+        assert!(copy.recognizes("aaab"));
+        assert!(!copy.recognizes("ba"));
+    }
+
+    #[test]
+    fn test_alphabet() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> EXP - EXP
+        EXP -> EXP / EXP
+        EXP -> ( EXP )
+        EXP -> n
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(
+            grammar.alphabet(),
+            Some(vec!['(', ')', '*', '+', '-', '/', 'n'])
+        );
+
+        let grammar = Grammar::from_rules("S\nS -> [a-c] b [ea] \\s").unwrap();
+        assert_eq!(grammar.alphabet(), Some(vec![' ', 'a', 'b', 'c', 'e']));
+
+        // The characters of a predicate can't be listed.
+        let config = GrammarConfig::default().terminal_pred("space", char::is_whitespace);
+        let grammar = Grammar::from_rules_with_config("S\nS -> a \\p{space}", &config).unwrap();
+        assert_eq!(grammar.alphabet(), None);
+    }
+
+    #[test]
+    fn test_terminal_lookup() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        for c in ['n', '+', '(', '*', ')'] {
+            assert!(grammar.is_terminal(c));
+            assert!(grammar.terminal(c) == Some(Terminal::Symbol(c)));
+        }
+        assert!(!grammar.is_terminal('z'));
+        assert!(grammar.terminal('z').is_none());
+
+        let grammar = Grammar::from_rules("S\nS -> \\d [0-4] 2").unwrap();
+        assert!(grammar.terminal('2') == Some(Terminal::Symbol('2')));
+        assert!(grammar.terminal('3') == Some(Terminal::Class(CharClass::Digit)));
+        assert!(grammar.terminal('9') == Some(Terminal::Class(CharClass::Digit)));
+        assert!(!grammar.is_terminal('a'));
+    }
+}
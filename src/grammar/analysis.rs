@@ -0,0 +1,1282 @@
+use super::*;
+use std::collections::HashSet;
+#[cfg(test)]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+/// A symbol that can follow a nonterminal: either a terminal,
+/// or the end of the input.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum Lookahead<'a, T = char> {
+    Terminal(Terminal<'a, T>),
+    EndOfInput,
+}
+
+/// The indices of the nonterminals and the terminals that are left corners
+/// of a nonterminal.
+type Corners = (Vec<usize>, Vec<usize>);
+
+/// The analyses of a grammar that other analyses build on, computed on the
+/// first call and kept for the next ones. The rules of a grammar never change,
+/// so they never have to be computed again. `OnceLock` keeps the grammar
+/// `Sync`, so that it can still be shared by threads.
+///
+/// The analyses refer to the nonterminals by their index in `nonterminals`,
+/// and to the terminals by their index in `terminals`: holding the names or
+/// the sets of characters in a `OnceLock` would make the grammar invariant over
+/// their lifetime, so a grammar could no longer be used for a shorter one.
+/// The end of the input, which can follow a nonterminal, has no index.
+pub struct AnalysisCache<'a, T = char> {
+    // The nonterminals of the grammar, sorted by name.
+    nonterminals: Vec<NonTerminal<'a>>,
+    // The terminals of the rules, sorted.
+    terminals: Vec<Terminal<'a, T>>,
+    nullable: OnceLock<Vec<usize>>,
+    first: OnceLock<Vec<Vec<usize>>>,
+    follow: OnceLock<Vec<Vec<Option<usize>>>>,
+    left_corners: OnceLock<Vec<Corners>>,
+    // The number of analyses computed, checked by the tests.
+    #[cfg(test)]
+    computed: AtomicUsize,
+}
+
+impl<'a, T: Symbol> AnalysisCache<'a, T> {
+    pub fn new<I, J>(nonterminals: I, terminals: J) -> Self
+    where
+        I: IntoIterator<Item = NonTerminal<'a>>,
+        J: IntoIterator<Item = Terminal<'a, T>>,
+    {
+        let mut nonterminals: Vec<NonTerminal<'a>> = nonterminals.into_iter().collect();
+        nonterminals.sort_by_key(|nonterminal| nonterminal.name);
+        let mut terminals: Vec<Terminal<'a, T>> = terminals.into_iter().collect();
+        terminals.sort();
+        terminals.dedup();
+        AnalysisCache {
+            nonterminals,
+            terminals,
+            nullable: OnceLock::new(),
+            first: OnceLock::new(),
+            follow: OnceLock::new(),
+            left_corners: OnceLock::new(),
+            #[cfg(test)]
+            computed: AtomicUsize::new(0),
+        }
+    }
+
+    /// Called before computing an analysis.
+    fn record(&self) {
+        #[cfg(test)]
+        self.computed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The nonterminals are sorted like the table of the grammar, so the
+    /// index of a nonterminal of the grammar is its id.
+    fn index(&self, nonterminal: NonTerminal) -> usize {
+        let id = nonterminal.id as usize;
+        match self.nonterminals.get(id) {
+            Some(bound) if std::ptr::eq(bound.name, nonterminal.name) => id,
+            _ => self
+                .nonterminals
+                .binary_search_by_key(&nonterminal.name, |other| other.name)
+                .expect("The nonterminal does not belong to this grammar!"),
+        }
+    }
+
+    fn terminal_index(&self, terminal: &Terminal<'a, T>) -> usize {
+        self.terminals
+            .binary_search(terminal)
+            .expect("The terminal does not belong to this grammar!")
+    }
+
+    /// Turns a set of terminals into their indices.
+    fn terminal_indices(&self, terminals: HashSet<Terminal<'a, T>>) -> Vec<usize> {
+        terminals
+            .iter()
+            .map(|terminal| self.terminal_index(terminal))
+            .collect()
+    }
+
+    /// Turns the indices of terminals back into a set.
+    fn terminal_set(&self, indices: &[usize]) -> HashSet<Terminal<'a, T>> {
+        indices.iter().map(|&index| self.terminals[index]).collect()
+    }
+
+    /// Turns a map over all the nonterminals into a list of values by index.
+    fn indexed<V: Default>(&self, map: HashMap<NonTerminal<'a>, V>) -> Vec<V> {
+        let mut values: Vec<V> = self.nonterminals.iter().map(|_| V::default()).collect();
+        for (nonterminal, value) in map {
+            values[self.index(nonterminal)] = value;
+        }
+        values
+    }
+
+    /// Turns a list of values by index back into a map.
+    fn keyed<V: Clone>(&self, values: &[V]) -> HashMap<NonTerminal<'a>, V> {
+        self.nonterminals
+            .iter()
+            .copied()
+            .zip(values.iter().cloned())
+            .collect()
+    }
+}
+
+impl<T: Clone> Clone for AnalysisCache<'_, T> {
+    fn clone(&self) -> Self {
+        AnalysisCache {
+            nonterminals: self.nonterminals.clone(),
+            terminals: self.terminals.clone(),
+            nullable: self.nullable.clone(),
+            first: self.first.clone(),
+            follow: self.follow.clone(),
+            left_corners: self.left_corners.clone(),
+            #[cfg(test)]
+            computed: AtomicUsize::new(self.computed.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// A problem of a grammar, found by `validate_strict`. These grammars can still
+/// be used, but some of their rules can never be part of a derivation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum GrammarWarning<'a> {
+    /// The nonterminal is used, but has no rules.
+    Undefined(NonTerminal<'a>),
+    /// The nonterminal has rules, but can't be reached from the start nonterminal.
+    Unreachable(NonTerminal<'a>),
+    /// The nonterminal has rules, but derives no word.
+    Unproductive(NonTerminal<'a>),
+    /// Each nonterminal derives the next one without consuming any input,
+    /// as returned by `epsilon_cycle`.
+    EpsilonCycle(Vec<NonTerminal<'a>>),
+}
+
+/// Returns the nonterminals that can derive the empty word using the given rules.
+pub fn nullable_nonterminals<'a>(rules: &[Rule<'a>]) -> HashSet<NonTerminal<'a>> {
+    let mut nullable = HashSet::new();
+
+    // A nonterminal is nullable if one of its rules has only nullable symbols.
+    // Repeat until no new nullable nonterminal is found.
+    loop {
+        let old_size = nullable.len();
+        for rule in rules.iter() {
+            let all_nullable = rule.to.iter().all(|token| match token {
+                Token::T(_) => false,
+                Token::NT(n) => nullable.contains(n),
+            });
+            if all_nullable {
+                nullable.insert(rule.from);
+            }
+        }
+
+        if nullable.len() == old_size {
+            break;
+        }
+    }
+    nullable
+}
+
+/// Returns the nonterminals that can derive at least one word using the given rules.
+pub fn productive_nonterminals<'a>(rules: &[Rule<'a>]) -> HashSet<NonTerminal<'a>> {
+    let mut productive = HashSet::new();
+
+    // A nonterminal is productive if one of its rules has only terminals
+    // and productive nonterminals. Repeat until no new one is found.
+    loop {
+        let old_size = productive.len();
+        for rule in rules.iter() {
+            let all_productive = rule.to.iter().all(|token| match token {
+                Token::T(_) => true,
+                Token::NT(n) => productive.contains(n),
+            });
+            if all_productive {
+                productive.insert(rule.from);
+            }
+        }
+
+        if productive.len() == old_size {
+            break;
+        }
+    }
+    productive
+}
+
+/// Returns the nonterminals that appear in some sentential form derived from
+/// the start nonterminal using the given rules, including the start itself.
+pub fn reachable_nonterminals<'a>(
+    start: NonTerminal<'a>,
+    rules: &[Rule<'a>],
+) -> HashSet<NonTerminal<'a>> {
+    let mut reachable = HashSet::from([start]);
+    let mut to_visit = vec![start];
+    while let Some(current) = to_visit.pop() {
+        for rule in rules.iter().filter(|rule| rule.from == current) {
+            for token in rule.to.iter() {
+                if let Token::NT(next) = *token {
+                    if reachable.insert(next) {
+                        to_visit.push(next);
+                    }
+                }
+            }
+        }
+    }
+    reachable
+}
+
+/// Returns the terminals that can begin a word derived from the given
+/// sequence of tokens, and whether the whole sequence is nullable.
+fn first_of_sequence<'a>(
+    tokens: &[Token<'a>],
+    first: &HashMap<NonTerminal<'a>, HashSet<Terminal<'a>>>,
+    nullable: &HashSet<NonTerminal<'a>>,
+) -> (HashSet<Terminal<'a>>, bool) {
+    let mut result = HashSet::new();
+    for token in tokens {
+        match token {
+            Token::T(t) => {
+                result.insert(*t);
+                return (result, false);
+            }
+            Token::NT(n) => {
+                result.extend(first[n].iter().copied());
+                if !nullable.contains(n) {
+                    return (result, false);
+                }
+            }
+        }
+    }
+    (result, true)
+}
+
+impl<'a> Grammar<'a> {
+    /// Returns the nonterminals that can derive the empty word.
+    pub fn nullable(&self) -> HashSet<NonTerminal<'a>> {
+        let cache = &self.analyses;
+        let nullable = cache.nullable.get_or_init(|| {
+            cache.record();
+            let nullable = nullable_nonterminals(&self.rules);
+            nullable.into_iter().map(|n| cache.index(n)).collect()
+        });
+        nullable
+            .iter()
+            .map(|&index| cache.nonterminals[index])
+            .collect()
+    }
+
+    /// Returns the nonterminals that can derive at least one word.
+    pub fn productive(&self) -> HashSet<NonTerminal<'a>> {
+        productive_nonterminals(&self.rules)
+    }
+
+    /// Returns the nonterminals that can be reached from the start nonterminal.
+    pub fn reachable(&self) -> HashSet<NonTerminal<'a>> {
+        reachable_nonterminals(self.start, &self.rules)
+    }
+
+    /// Returns the nonterminals used by the live grammar, i.e. the ones that can
+    /// be reached from the start nonterminal. Unlike [`Grammar::nonterminals`],
+    /// the nonterminals of dead rules are left out.
+    pub fn used_nonterminals(&self) -> HashSet<NonTerminal<'a>> {
+        self.reachable()
+    }
+
+    /// Returns the terminals that appear in at least one rule reachable from the
+    /// start nonterminal. Unlike [`Grammar::terminals`], the terminals used only
+    /// by dead rules are left out.
+    pub fn used_terminals(&self) -> HashSet<Terminal<'a>> {
+        let reachable = self.reachable();
+        self.rules
+            .iter()
+            .filter(|rule| reachable.contains(&rule.from))
+            .flat_map(|rule| rule.to.iter())
+            .filter_map(|token| match token {
+                Token::T(t) => Some(*t),
+                Token::NT(_) => None,
+            })
+            .collect()
+    }
+
+    /// Returns, for each nonterminal, the length of the shortest word derived from it,
+    /// or None if it derives no word. The nullable nonterminals have a length of 0.
+    pub fn min_length(&self) -> HashMap<NonTerminal<'a>, Option<usize>> {
+        let mut lengths: HashMap<NonTerminal, usize> = HashMap::new();
+
+        // The length of a rule is the sum of the lengths of its symbols, where
+        // a terminal counts as 1. Repeat until no length gets shorter.
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                let length = rule.to.iter().try_fold(0, |sum, token| match token {
+                    Token::T(_) => Some(sum + 1),
+                    Token::NT(n) => lengths.get(n).map(|length| sum + length),
+                });
+                if let Some(length) = length {
+                    if lengths.get(&rule.from).is_none_or(|&old| length < old) {
+                        lengths.insert(rule.from, length);
+                        changed = true;
+                    }
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        self.nonterminals
+            .values()
+            .map(|nonterminal| (*nonterminal, lengths.get(nonterminal).copied()))
+            .collect()
+    }
+
+    /// Returns, for each nonterminal, the set of terminals that can begin
+    /// a word derived from it.
+    pub fn first_sets(&self) -> HashMap<NonTerminal<'a>, HashSet<Terminal<'a>>> {
+        let cache = &self.analyses;
+        let first = cache.first.get_or_init(|| {
+            cache.record();
+            let first = self.compute_first_sets();
+            cache.indexed(
+                first
+                    .into_iter()
+                    .map(|(n, terminals)| (n, cache.terminal_indices(terminals)))
+                    .collect(),
+            )
+        });
+        cache
+            .keyed(first)
+            .into_iter()
+            .map(|(n, indices)| (n, cache.terminal_set(&indices)))
+            .collect()
+    }
+
+    fn compute_first_sets(&self) -> HashMap<NonTerminal<'a>, HashSet<Terminal<'a>>> {
+        let nullable = self.nullable();
+        let mut first: HashMap<NonTerminal, HashSet<Terminal>> = self
+            .nonterminals
+            .values()
+            .map(|&nonterminal| (nonterminal, HashSet::new()))
+            .collect();
+
+        // For each rule A -> X1 X2 ... Xn, FIRST(A) includes FIRST(Xi)
+        // as long as X1 ... Xi-1 are all nullable.
+        // Repeat until no set grows anymore.
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                let (to_add, _) = first_of_sequence(&rule.to, &first, &nullable);
+
+                let set = first.get_mut(&rule.from).unwrap();
+                let old_size = set.len();
+                set.extend(to_add);
+                changed |= set.len() != old_size;
+            }
+
+            if !changed {
+                break;
+            }
+        }
+        first
+    }
+
+    /// Returns, for each nonterminal, the set of terminal sequences of length
+    /// at most `k` that can begin a word derived from it: the words shorter
+    /// than `k`, and the first `k` terminals of the longer ones. The empty
+    /// sequence belongs to the set of every nullable nonterminal.
+    pub fn first_k(&self, k: usize) -> HashMap<NonTerminal<'a>, HashSet<Vec<Terminal<'a>>>> {
+        let mut first: HashMap<NonTerminal, HashSet<Vec<Terminal>>> = self
+            .nonterminals
+            .values()
+            .map(|&nonterminal| (nonterminal, HashSet::new()))
+            .collect();
+
+        // For each rule A -> X1 X2 ... Xn, FIRST-k(A) includes the sequences
+        // obtained by concatenating one sequence of each Xi, truncated to k.
+        // Repeat until no set grows anymore.
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                let mut prefixes: HashSet<Vec<Terminal>> = HashSet::from([Vec::new()]);
+                for token in rule.to.iter() {
+                    let mut next = HashSet::new();
+                    for prefix in prefixes.iter() {
+                        if prefix.len() == k {
+                            next.insert(prefix.clone());
+                            continue;
+                        }
+                        match token {
+                            Token::T(terminal) => {
+                                let mut extended = prefix.clone();
+                                extended.push(*terminal);
+                                next.insert(extended);
+                            }
+                            Token::NT(nonterminal) => {
+                                for suffix in first[nonterminal].iter() {
+                                    let mut extended = prefix.clone();
+                                    let room = k - prefix.len();
+                                    extended.extend(suffix.iter().take(room));
+                                    next.insert(extended);
+                                }
+                            }
+                        }
+                    }
+                    prefixes = next;
+                    if prefixes.is_empty() {
+                        break;
+                    }
+                }
+
+                let set = first.get_mut(&rule.from).unwrap();
+                let old_size = set.len();
+                set.extend(prefixes);
+                changed |= set.len() != old_size;
+            }
+
+            if !changed {
+                break;
+            }
+        }
+        first
+    }
+
+    /// Returns, for each nonterminal, the set of symbols that can follow it
+    /// in a sentential form derived from the start nonterminal.
+    /// The end of the input follows the start nonterminal.
+    pub fn follow_sets(&self) -> HashMap<NonTerminal<'a>, HashSet<Lookahead<'a>>> {
+        let cache = &self.analyses;
+        let follow = cache.follow.get_or_init(|| {
+            cache.record();
+            let index = |lookahead| match lookahead {
+                Lookahead::Terminal(terminal) => Some(cache.terminal_index(&terminal)),
+                Lookahead::EndOfInput => None,
+            };
+            let follow = self.compute_follow_sets();
+            cache.indexed(
+                follow
+                    .into_iter()
+                    .map(|(n, lookaheads)| (n, lookaheads.into_iter().map(index).collect()))
+                    .collect(),
+            )
+        });
+        let lookahead = |index: &Option<usize>| match *index {
+            Some(index) => Lookahead::Terminal(cache.terminals[index]),
+            None => Lookahead::EndOfInput,
+        };
+        cache
+            .keyed(follow)
+            .into_iter()
+            .map(|(n, indices)| (n, indices.iter().map(lookahead).collect()))
+            .collect()
+    }
+
+    fn compute_follow_sets(&self) -> HashMap<NonTerminal<'a>, HashSet<Lookahead<'a>>> {
+        let nullable = self.nullable();
+        let first = self.first_sets();
+        let mut follow: HashMap<NonTerminal, HashSet<Lookahead>> = self
+            .nonterminals
+            .values()
+            .map(|&nonterminal| (nonterminal, HashSet::new()))
+            .collect();
+        follow
+            .get_mut(&self.start)
+            .unwrap()
+            .insert(Lookahead::EndOfInput);
+
+        // For each rule A -> ... B β, FOLLOW(B) includes FIRST(β),
+        // and also FOLLOW(A) if β is nullable.
+        // Repeat until no set grows anymore.
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                for (index, token) in rule.to.iter().enumerate() {
+                    let nonterminal = match token {
+                        Token::T(_) => continue,
+                        Token::NT(n) => *n,
+                    };
+
+                    let (suffix_first, suffix_nullable) =
+                        first_of_sequence(&rule.to[index + 1..], &first, &nullable);
+                    let mut to_add: HashSet<Lookahead> =
+                        suffix_first.into_iter().map(Lookahead::Terminal).collect();
+                    if suffix_nullable {
+                        to_add.extend(follow[&rule.from].iter().copied());
+                    }
+
+                    let set = follow.get_mut(&nonterminal).unwrap();
+                    let old_size = set.len();
+                    set.extend(to_add);
+                    changed |= set.len() != old_size;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+        follow
+    }
+
+    /// Returns, for each nonterminal, its left corners: the symbols, terminals and
+    /// nonterminals, that can begin a sentential form derived from it in one or more
+    /// steps. The nonterminals that derive the empty word are skipped over, so with
+    /// `A -> B c` and `B -> `, both B and c are left corners of A.
+    pub fn left_corners(&self) -> HashMap<NonTerminal<'a>, HashSet<Token<'a>>> {
+        let cache = &self.analyses;
+        let corners = cache.left_corners.get_or_init(|| {
+            cache.record();
+            let corners = self.compute_left_corners();
+            let split = |tokens: HashSet<Token<'a>>| {
+                let mut split = (Vec::new(), Vec::new());
+                for token in tokens {
+                    match token {
+                        Token::NT(n) => split.0.push(cache.index(n)),
+                        Token::T(t) => split.1.push(cache.terminal_index(&t)),
+                    }
+                }
+                split
+            };
+            cache.indexed(
+                corners
+                    .into_iter()
+                    .map(|(n, tokens)| (n, split(tokens)))
+                    .collect(),
+            )
+        });
+        cache
+            .keyed(corners)
+            .into_iter()
+            .map(|(n, (nonterminals, terminals))| {
+                let nonterminals = nonterminals
+                    .iter()
+                    .map(|&index| Token::NT(cache.nonterminals[index]));
+                let terminals = terminals
+                    .iter()
+                    .map(|&index| Token::T(cache.terminals[index]));
+                (n, nonterminals.chain(terminals).collect())
+            })
+            .collect()
+    }
+
+    fn compute_left_corners(&self) -> HashMap<NonTerminal<'a>, HashSet<Token<'a>>> {
+        let nullable = self.nullable();
+        let mut corners: HashMap<NonTerminal, HashSet<Token>> = self
+            .nonterminals
+            .values()
+            .map(|&nonterminal| (nonterminal, HashSet::new()))
+            .collect();
+
+        // For each rule A -> X1 X2 ... Xn, Xi and its left corners are left corners
+        // of A as long as X1 ... Xi-1 are all nullable.
+        // Repeat until no set grows anymore.
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                let mut to_add = HashSet::new();
+                for &token in rule.to.iter() {
+                    to_add.insert(token);
+                    match token {
+                        Token::T(_) => break,
+                        Token::NT(n) => {
+                            to_add.extend(corners[&n].iter().copied());
+                            if !nullable.contains(&n) {
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                let set = corners.get_mut(&rule.from).unwrap();
+                let old_size = set.len();
+                set.extend(to_add);
+                changed |= set.len() != old_size;
+            }
+
+            if !changed {
+                break;
+            }
+        }
+        corners
+    }
+
+    /// Returns the left recursive nonterminals, sorted by name: the nonterminals A
+    /// that derive a sentential form starting with A, like `EXP -> EXP + EXP`.
+    /// The recursion can be indirect, and can go through nullable symbols,
+    /// like in `A -> B A x`, where B derives the empty word.
+    pub fn left_recursive(&self) -> Vec<NonTerminal<'a>> {
+        let corners = self.left_corners();
+        let mut result: Vec<NonTerminal> = corners
+            .iter()
+            .filter(|(&nonterminal, set)| set.contains(&Token::NT(nonterminal)))
+            .map(|(&nonterminal, _)| nonterminal)
+            .collect();
+        result.sort_by_key(|n| n.name);
+        result
+    }
+
+    /// Returns, for each nonterminal A, the nonterminals B such that A derives B
+    /// without consuming any input, using a single rule A -> α B β where α and β
+    /// are nullable.
+    fn epsilon_successors(&self) -> HashMap<NonTerminal<'a>, Vec<NonTerminal<'a>>> {
+        let nullable = self.nullable();
+        let is_nullable = |token: &Token| match token {
+            Token::T(_) => false,
+            Token::NT(n) => nullable.contains(n),
+        };
+
+        let mut successors: HashMap<NonTerminal, Vec<NonTerminal>> = HashMap::new();
+        for rule in self.rules.iter() {
+            for (index, token) in rule.to.iter().enumerate() {
+                let nonterminal = match token {
+                    Token::T(_) => continue,
+                    Token::NT(n) => *n,
+                };
+                let others_nullable = rule.to[..index].iter().all(is_nullable)
+                    && rule.to[index + 1..].iter().all(is_nullable);
+
+                let list = successors.entry(rule.from).or_default();
+                if others_nullable && !list.contains(&nonterminal) {
+                    list.push(nonterminal);
+                }
+            }
+        }
+        successors
+    }
+
+    /// Looks for a cycle that goes through `current`, in depth-first order.
+    /// `path` holds the nonterminals on the path to `current`.
+    fn find_epsilon_cycle(
+        &self,
+        current: NonTerminal<'a>,
+        successors: &HashMap<NonTerminal<'a>, Vec<NonTerminal<'a>>>,
+        path: &mut Vec<NonTerminal<'a>>,
+        visited: &mut HashSet<NonTerminal<'a>>,
+    ) -> Option<Vec<NonTerminal<'a>>> {
+        if let Some(position) = path.iter().position(|&n| n == current) {
+            return Some(path[position..].to_vec());
+        }
+        if !visited.insert(current) {
+            return None;
+        }
+
+        path.push(current);
+        for &next in successors.get(&current).into_iter().flatten() {
+            if let Some(cycle) = self.find_epsilon_cycle(next, successors, path, visited) {
+                return Some(cycle);
+            }
+        }
+        path.pop();
+        None
+    }
+
+    /// Returns a cycle of nonterminals A1, A2, ..., An, where each nonterminal derives
+    /// the next one (and An derives A1) without consuming any input, if there is one.
+    ///
+    /// With such a cycle, A1 derives itself, so every word derived from A1 has
+    /// infinitely many derivations.
+    pub fn epsilon_cycle(&self) -> Option<Vec<NonTerminal<'a>>> {
+        let successors = self.epsilon_successors();
+        let mut visited = HashSet::new();
+
+        // Visit the nonterminals in the order of the rules, so the same cycle is always found.
+        for rule in self.rules.iter() {
+            let mut path = Vec::new();
+            let cycle = self.find_epsilon_cycle(rule.from, &successors, &mut path, &mut visited);
+            if cycle.is_some() {
+                return cycle;
+            }
+        }
+        None
+    }
+
+    /// Returns true if the grammar accepts finitely many words, so that they can all
+    /// be enumerated.
+    ///
+    /// Only the rules of derivations of words are considered: the ones whose nonterminals
+    /// are all productive and reachable. The language is infinite iff one of these
+    /// nonterminals derives a form that holds itself and a nonempty word around it,
+    /// i.e. a cycle of rules where one of the steps adds at least one character.
+    /// Cycles that only add the empty word, like `A -> B`, `B -> A`, give more
+    /// derivations but no more words.
+    pub fn is_finite(&self) -> bool {
+        let productive = self.productive();
+        let useful: Vec<Rule<'a>> = self
+            .rules
+            .iter()
+            .filter(|rule| {
+                productive.contains(&rule.from)
+                    && rule.to.iter().all(|token| match token {
+                        Token::T(_) => true,
+                        Token::NT(n) => productive.contains(n),
+                    })
+            })
+            .cloned()
+            .collect();
+        let reachable = reachable_nonterminals(self.start, &useful);
+
+        // The nonterminals that derive at least one nonempty word.
+        let mut nonempty = HashSet::new();
+        loop {
+            let old_size = nonempty.len();
+            for rule in useful.iter() {
+                let grows = rule.to.iter().any(|token| match token {
+                    Token::T(_) => true,
+                    Token::NT(n) => nonempty.contains(n),
+                });
+                if grows {
+                    nonempty.insert(rule.from);
+                }
+            }
+            if nonempty.len() == old_size {
+                break;
+            }
+        }
+
+        // The edges A -> B for the rules A -> α B β, which grow if α β derives a nonempty word.
+        let mut successors: HashMap<NonTerminal, Vec<NonTerminal>> = HashMap::new();
+        let mut growing = Vec::new();
+        for rule in useful.iter().filter(|rule| reachable.contains(&rule.from)) {
+            for (index, token) in rule.to.iter().enumerate() {
+                let Token::NT(next) = *token else {
+                    continue;
+                };
+                successors.entry(rule.from).or_default().push(next);
+                let others_grow = rule.to.iter().enumerate().any(|(other, token)| {
+                    other != index
+                        && match token {
+                            Token::T(_) => true,
+                            Token::NT(n) => nonempty.contains(n),
+                        }
+                });
+                if others_grow {
+                    growing.push((rule.from, next));
+                }
+            }
+        }
+
+        // The language is infinite iff a growing edge is on a cycle.
+        !growing.iter().any(|&(from, to)| {
+            let mut visited = HashSet::new();
+            let mut stack = vec![to];
+            while let Some(current) = stack.pop() {
+                if current == from {
+                    return true;
+                }
+                if visited.insert(current) {
+                    stack.extend(successors.get(&current).into_iter().flatten());
+                }
+            }
+            false
+        })
+    }
+
+    /// Returns true if a nonterminal can derive itself without consuming any input.
+    pub fn has_epsilon_cycle(&self) -> bool {
+        self.epsilon_cycle().is_some()
+    }
+
+    /// Checks the grammar for undefined, unreachable and unproductive nonterminals,
+    /// and for an epsilon cycle, and returns all the problems found.
+    ///
+    /// The warnings are grouped in that order, and the nonterminals of each
+    /// group are sorted by name. A grammar without problems gives `Ok(())`.
+    pub fn validate_strict(&self) -> Result<(), Vec<GrammarWarning<'a>>> {
+        let (defined, undefined): (Vec<NonTerminal<'a>>, Vec<NonTerminal<'a>>) = self
+            .nonterminals()
+            .partition(|&nonterminal| !self.rules_for(nonterminal).is_empty());
+
+        let (reachable, productive) = (self.reachable(), self.productive());
+        let mut warnings: Vec<GrammarWarning<'a>> = undefined
+            .into_iter()
+            .map(GrammarWarning::Undefined)
+            .collect();
+        warnings.extend(
+            defined
+                .iter()
+                .filter(|nonterminal| !reachable.contains(nonterminal))
+                .map(|&nonterminal| GrammarWarning::Unreachable(nonterminal)),
+        );
+        warnings.extend(
+            defined
+                .iter()
+                .filter(|nonterminal| !productive.contains(nonterminal))
+                .map(|&nonterminal| GrammarWarning::Unproductive(nonterminal)),
+        );
+        warnings.extend(self.epsilon_cycle().map(GrammarWarning::EpsilonCycle));
+
+        if warnings.is_empty() {
+            Ok(())
+        } else {
+            Err(warnings)
+        }
+    }
+
+    /// Returns true if both grammars accept the same words of at most `max_len`
+    /// characters, over the characters matched by the terminals of either grammar.
+    /// Returns None if a terminal of either grammar is a predicate, whose
+    /// characters can't be listed (see `alphabet`).
+    ///
+    /// This only approximates equivalence, which is undecidable for context free
+    /// grammars: the grammars may still disagree on longer words. Every word is
+    /// checked, so this is only practical for small alphabets and lengths.
+    pub fn equivalent_up_to(&self, other: &Grammar, max_len: usize) -> Option<bool> {
+        let mut alphabet = self.alphabet()?;
+        alphabet.extend(other.alphabet()?);
+        alphabet.sort();
+        alphabet.dedup();
+        // Without terminals, the empty word is the only word to compare.
+        if alphabet.is_empty() {
+            return Some(self.recognizes("") == other.recognizes(""));
+        }
+
+        // Count in base |alphabet|, with the digits as indices in the alphabet.
+        for len in 0..=max_len {
+            let mut digits = vec![0; len];
+            loop {
+                let word: String = digits.iter().map(|&digit| alphabet[digit]).collect();
+                if self.recognizes(&word) != other.recognizes(&word) {
+                    return Some(false);
+                }
+
+                let Some(position) = digits.iter().rposition(|&d| d + 1 < alphabet.len()) else {
+                    break;
+                };
+                digits[position] += 1;
+                digits[position + 1..].fill(0);
+            }
+        }
+        Some(true)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nullable() {
+        let grammar_string = "S
+        S -> a b C d e
+        S -> C D
+        C -> D
+        D -> E
+        E -> 
+        F -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let nullable = grammar.nullable();
+        let mut names: Vec<&str> = nullable.iter().map(|n| n.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["C", "D", "E", "S"]);
+    }
+
+    #[test]
+    fn test_reachable() {
+        let grammar_string = "S
+        S -> a A
+        A -> B
+        B -> b
+        C -> S";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let mut names: Vec<&str> = grammar.reachable().iter().map(|n| n.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["A", "B", "S"]);
+    }
+
+    #[test]
+    fn test_used_symbols() {
+        // The grammar of test_grammar_aa, where B can also derive c.
+        let grammar_string = "S
+        S -> A A
+        A -> a A
+        A -> b
+        A -> a A
+        B -> b
+        B -> c";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let mut names: Vec<&str> = grammar.used_nonterminals().iter().map(|n| n.name).collect();
+        names.sort();
+        assert_eq!(names, vec!["A", "S"]);
+
+        let mut terminals: Vec<char> = grammar
+            .used_terminals()
+            .iter()
+            .filter_map(|t| t.content())
+            .collect();
+        terminals.sort();
+        assert_eq!(terminals, vec!['a', 'b']);
+        assert_eq!(grammar.terminals().count(), 3);
+    }
+
+    #[test]
+    fn test_min_length() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let lengths = grammar.min_length();
+        assert_eq!(lengths[&NonTerminal::new("EXP")], Some(1));
+
+        let grammar_string = "S
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.min_length()[&NonTerminal::new("S")], Some(0));
+
+        let grammar_string = "S
+        S -> A B
+        S -> a b c d
+        A -> a A
+        A -> B B
+        B -> b c
+        C -> C c
+        D -> C";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let lengths = grammar.min_length();
+        let length = |name| lengths[&NonTerminal::new(name)];
+        assert_eq!(length("S"), Some(4));
+        assert_eq!(length("A"), Some(4));
+        assert_eq!(length("B"), Some(2));
+        assert_eq!(length("C"), None);
+        assert_eq!(length("D"), None);
+    }
+
+    #[test]
+    fn test_equivalent_up_to() {
+        let grammar_string = "S
+        S -> a S b S
+        S -> b S a S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        assert_eq!(
+            grammar.equivalent_up_to(&grammar.remove_epsilon(&mut names), 6),
+            Some(true)
+        );
+        assert_eq!(
+            grammar.equivalent_up_to(&grammar.to_cnf(&mut names), 6),
+            Some(true)
+        );
+
+        // The grammars agree on the words shorter than abab.
+        let short = Grammar::from_rules("S\nS -> a b\nS -> b a\nS -> ").unwrap();
+        assert_eq!(grammar.equivalent_up_to(&short, 4), Some(false));
+        assert_eq!(grammar.equivalent_up_to(&short, 3), Some(true));
+
+        // Without terminals, only the empty word is compared.
+        let cyclic = Grammar::from_rules("S\nS -> S").unwrap();
+        assert_eq!(cyclic.equivalent_up_to(&cyclic, 3), Some(true));
+        let empty = Grammar::from_rules("S\nS -> ").unwrap();
+        assert_eq!(cyclic.equivalent_up_to(&empty, 3), Some(false));
+        assert_eq!(empty.equivalent_up_to(&empty, 0), Some(true));
+
+        // The characters of a predicate can't be listed.
+        let config = GrammarConfig::default().terminal_pred("space", char::is_whitespace);
+        let spaces =
+            Grammar::from_rules_with_config("S\nS -> \\p{space} S\nS -> ", &config).unwrap();
+        assert_eq!(spaces.equivalent_up_to(&empty, 3), None);
+        assert_eq!(empty.equivalent_up_to(&spaces, 3), None);
+    }
+
+    #[test]
+    fn test_left_corners() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> T
+        T -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let corners = grammar.left_corners();
+
+        let mut names: Vec<String> = corners[&grammar.start]
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["(", "EXP", "T", "n"]);
+        assert!(
+            corners[&NonTerminal::new("T")] == HashSet::from([Token::T(Terminal::Symbol('n'))])
+        );
+    }
+
+    #[test]
+    fn test_left_recursive() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let names: Vec<&str> = grammar.left_recursive().iter().map(|n| n.name).collect();
+        assert_eq!(names, vec!["EXP"]);
+
+        // A is left recursive through B, and C through the nullable D.
+        let grammar_string = "S
+        S -> A
+        A -> B a
+        B -> A b
+        B -> c
+        C -> D C x
+        C -> y
+        D -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let names: Vec<&str> = grammar.left_recursive().iter().map(|n| n.name).collect();
+        assert_eq!(names, vec!["A", "B", "C"]);
+
+        let grammar = Grammar::from_rules("S\nS -> a S\nS -> ").unwrap();
+        assert!(grammar.left_recursive().is_empty());
+    }
+
+    #[test]
+    fn test_first_sets() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let first = grammar.first_sets();
+        let mut contents: Vec<char> = first[&grammar.start]
+            .iter()
+            .map(|t| t.content().unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, vec!['(', 'n']);
+
+        // Nullable prefixes let the following symbols contribute.
+        let grammar_string = "S
+        S -> A B c
+        A -> a
+        A ->
+        B -> b
+        B ->";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let first = grammar.first_sets();
+        let mut contents: Vec<char> = first[&grammar.start]
+            .iter()
+            .map(|t| t.content().unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, vec!['a', 'b', 'c']);
+    }
+
+    #[test]
+    fn test_cached_analyses() {
+        let grammar_string = "S
+        S -> A B c
+        A -> a
+        A ->
+        B -> A b
+        B ->";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let computed = |grammar: &Grammar| grammar.analyses.computed.load(Ordering::Relaxed);
+        assert_eq!(computed(&grammar), 0);
+
+        // The follow sets need the first sets, which need the nullable set.
+        let follow = grammar.follow_sets();
+        assert_eq!(computed(&grammar), 3);
+        for _ in 0..3 {
+            assert_eq!(grammar.follow_sets(), follow);
+            assert_eq!(grammar.first_sets(), grammar.first_sets());
+            assert_eq!(grammar.nullable(), nullable_nonterminals(&grammar.rules));
+        }
+        assert_eq!(computed(&grammar), 3);
+        let corners = grammar.left_corners();
+        assert!(grammar.left_corners() == corners);
+        assert_eq!(computed(&grammar), 4);
+
+        // The cached values are the ones computed again from scratch.
+        assert_eq!(grammar.first_sets(), grammar.compute_first_sets());
+        assert_eq!(follow, grammar.compute_follow_sets());
+        assert!(corners == grammar.compute_left_corners());
+        let names: HashSet<&str> = grammar.nullable().iter().map(|n| n.name).collect();
+        assert_eq!(names, HashSet::from(["A", "B"]));
+
+        // A clone keeps the analyses of the original.
+        let clone = grammar.clone();
+        assert_eq!(clone.follow_sets(), follow);
+        assert_eq!(computed(&clone), 4);
+    }
+
+    #[test]
+    fn test_first_k() {
+        let grammar_string = "S
+        S -> A B c
+        A -> a A
+        A ->
+        B -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let pairs = |set: &HashSet<Vec<Terminal>>| {
+            let mut words: Vec<String> = set
+                .iter()
+                .map(|word| word.iter().map(|t| t.content().unwrap()).collect())
+                .collect();
+            words.sort();
+            words
+        };
+
+        let first = grammar.first_k(2);
+        assert_eq!(pairs(&first[&grammar.start]), vec!["aa", "ab", "bc"]);
+        // The nullable A also begins with the empty sequence and a lone a.
+        let a = grammar.nonterminals["A"];
+        assert_eq!(pairs(&first[&a]), vec!["", "a", "aa"]);
+        let b = grammar.nonterminals["B"];
+        assert_eq!(pairs(&first[&b]), vec!["b"]);
+
+        assert_eq!(pairs(&grammar.first_k(0)[&grammar.start]), vec![""]);
+    }
+
+    #[test]
+    fn test_follow_sets() {
+        let grammar_string = "S
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let follow = grammar.follow_sets();
+        let expected: HashSet<Lookahead> = [
+            Lookahead::Terminal(Terminal::Symbol(')')),
+            Lookahead::EndOfInput,
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(follow[&grammar.start], expected);
+
+        let grammar_string = "S
+        S -> A B c
+        A -> a
+        B -> b
+        B ->";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let follow = grammar.follow_sets();
+        let expected: HashSet<Lookahead> = [
+            Lookahead::Terminal(Terminal::Symbol('b')),
+            Lookahead::Terminal(Terminal::Symbol('c')),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(follow[&NonTerminal::new("A")], expected);
+    }
+
+    #[test]
+    fn test_is_finite() {
+        let grammar = Grammar::from_rules("S\nS -> a A\nA -> b\nA -> ").unwrap();
+        assert!(grammar.is_finite());
+
+        let expressions = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let parens = "S
+        S -> ( S ) S
+        S -> ";
+        for grammar_string in [expressions, parens] {
+            assert!(!Grammar::from_rules(grammar_string).unwrap().is_finite());
+        }
+
+        // Cycles that don't add characters, or that can't be part of a derivation,
+        // don't make the language infinite.
+        let grammar_string = "S
+        S -> A
+        A -> S
+        A -> a B
+        B -> E B E
+        B -> b
+        E -> 
+        C -> c C
+        S -> D
+        D -> d D";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.is_finite());
+
+        // The cycle adds a character through a nullable nonterminal.
+        let grammar_string = "S
+        S -> A S
+        S -> b
+        A -> a
+        A -> ";
+        assert!(!Grammar::from_rules(grammar_string).unwrap().is_finite());
+    }
+
+    #[test]
+    fn test_validate_strict() {
+        let grammar_string = "S
+        S -> a S
+        S -> a
+        S -> B
+        S -> X
+        B -> C
+        C -> B
+        C -> c
+        D -> d
+        E -> a E";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let nonterminal = NonTerminal::new;
+        let warnings = grammar.validate_strict().unwrap_err();
+        assert_eq!(
+            warnings,
+            vec![
+                GrammarWarning::Undefined(nonterminal("X")),
+                GrammarWarning::Unreachable(nonterminal("D")),
+                GrammarWarning::Unreachable(nonterminal("E")),
+                GrammarWarning::Unproductive(nonterminal("E")),
+                GrammarWarning::EpsilonCycle(vec![nonterminal("B"), nonterminal("C")]),
+            ]
+        );
+
+        let grammar_string = "S
+        S -> a S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.validate_strict(), Ok(()));
+    }
+
+    #[test]
+    fn test_epsilon_cycle() {
+        // B -> A is a unit rule, but A -> B A is not, since B A needs at least
+        // two characters, so there is no cycle.
+        let grammar_string = "S
+        S -> A B
+        S -> B
+        A -> B A
+        A -> a
+        B -> A
+        B -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(!grammar.has_epsilon_cycle());
+
+        // A derives B C, which derives A when C derives the empty word.
+        let grammar_string = "S
+        S -> a A
+        A -> B C
+        A -> a
+        B -> A
+        C -> c
+        C -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.has_epsilon_cycle());
+        let cycle: Vec<&str> = grammar
+            .epsilon_cycle()
+            .unwrap()
+            .iter()
+            .map(|n| n.name)
+            .collect();
+        assert_eq!(cycle, vec!["A", "B"]);
+
+        // A nonterminal that derives itself directly.
+        let grammar_string = "S
+        S -> S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let cycle: Vec<&str> = grammar
+            .epsilon_cycle()
+            .unwrap()
+            .iter()
+            .map(|n| n.name)
+            .collect();
+        assert_eq!(cycle, vec!["S"]);
+    }
+}
@@ -1,33 +1,117 @@
 use super::*;
+use std::cell::RefCell;
 use std::cmp::{Eq, PartialEq};
 use std::collections::HashSet;
-use std::hash::Hash;
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
 use std::rc::Rc;
-
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod actions;
+mod cache;
+mod chart;
+mod coverage;
+mod cyk;
+mod diagnostics;
+mod forest;
+mod incremental;
+mod k_best;
+mod parse_result;
 mod parse_tree;
+mod precedence;
+mod viterbi;
+pub use actions::EvalError;
+pub use actions::Evaluator;
+pub use actions::RuleActions;
+pub use cache::CachingGrammar;
+pub use chart::ChartItem;
+pub use chart::ChartStats;
+pub use chart::EarleyChart;
+pub use chart::PhaseTimings;
+pub use coverage::RuleCoverage;
+pub use diagnostics::BestEffort;
+pub use diagnostics::ScanError;
+pub use incremental::IncrementalParser;
+pub use parse_result::ParseResult;
+pub use parse_result::ParseSummary;
+pub use parse_result::TooAmbiguous;
+pub use parse_tree::collapse_unit_chains;
+pub use parse_tree::forest_to_text;
+pub use parse_tree::leftmost_derivation;
 pub use parse_tree::render_tree;
+pub use parse_tree::render_tree_to_writer;
+pub use parse_tree::to_ascii;
+pub use parse_tree::to_tree_string;
+pub use parse_tree::write_forest_dag_to_dot;
+pub use parse_tree::write_forest_dag_to_dot_with;
+pub use parse_tree::write_forest_to_dot;
 pub use parse_tree::write_tree_to_dot;
+pub use parse_tree::write_tree_to_dot_file;
+pub use parse_tree::write_tree_to_dot_styled;
+pub use parse_tree::DotStyle;
+pub use parse_tree::NodeStyle;
+pub use parse_tree::ParallelEdges;
 pub use parse_tree::ParseNode;
+pub use parse_tree::SyncParseNode;
+pub use parse_tree::TreeStyle;
+pub use precedence::Associativity;
+pub use precedence::Precedence;
 
 /// Each state consists of:
 /// - the production currently being matched
 /// - the current position in that production
 /// - the position in the input at witch the matching began.
-#[derive(Eq, PartialEq, Hash)]
-struct EarleyState<'a> {
-    rule: &'a Rule<'a>,
+///
+/// A set holds at most one state for each (rule, dot, origin) triple.
+/// The different ways in which the state was reached are kept in its list
+/// of derivations, so equality and hashing only look at the triple.
+struct EarleyState<'a, T = char> {
+    rule: &'a Rule<'a, T>,
     dot: usize,
     origin: usize,
-    children: Vec<Rc<EarleyState<'a>>>,
+    derivations: RefCell<Vec<Derivation<'a, T>>>,
+}
+
+/// Describes how a state was obtained by advancing its dot over a symbol.
+/// Predicted states have no derivations.
+enum Derivation<'a, T = char> {
+    /// The dot was moved over a terminal, starting from the given state
+    /// in the previous set, by scanning the given character.
+    Scan(Rc<EarleyState<'a, T>>, T),
+
+    /// The dot was moved over a nonterminal, starting from the `previous` state
+    /// in the set at the origin of the `child` state, which is a finished
+    /// state for that nonterminal.
+    Complete {
+        previous: Rc<EarleyState<'a, T>>,
+        child: Rc<EarleyState<'a, T>>,
+    },
+}
+
+impl<T: Symbol> PartialEq for Derivation<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Derivation::Scan(a, c), Derivation::Scan(b, d)) => Rc::ptr_eq(a, b) && c == d,
+            (
+                Derivation::Complete { previous, child },
+                Derivation::Complete {
+                    previous: other_previous,
+                    child: other_child,
+                },
+            ) => Rc::ptr_eq(previous, other_previous) && Rc::ptr_eq(child, other_child),
+            _ => false,
+        }
+    }
 }
 
-impl<'a> EarleyState<'a> {
-    fn new(rule: &'a Rule<'a>, dot: usize, origin: usize) -> Self {
+impl<'a, T: Symbol> EarleyState<'a, T> {
+    fn new(rule: &'a Rule<'a, T>, dot: usize, origin: usize) -> Self {
         EarleyState {
             rule,
             dot,
             origin,
-            children: Vec::new(),
+            derivations: RefCell::new(Vec::new()),
         }
     }
 
@@ -36,179 +120,1051 @@ impl<'a> EarleyState<'a> {
         self.dot == self.rule.to.len()
     }
 
-    fn current_token(&self) -> Token<'a> {
+    fn current_token(&self) -> Token<'a, T> {
         self.rule.to[self.dot]
     }
+
+    /// Returns the token of the leaf obtained by scanning the character.
+    fn scanned_token(&self, c: T) -> Token<'a, T> {
+        leaf_token(self.current_token(), c)
+    }
+
+    /// Returns the state obtained by moving the dot one position to the right.
+    fn advance(&self, derivation: Derivation<'a, T>) -> Self {
+        EarleyState {
+            rule: self.rule,
+            dot: self.dot + 1,
+            origin: self.origin,
+            derivations: RefCell::new(vec![derivation]),
+        }
+    }
+}
+
+impl<T: Symbol> PartialEq for EarleyState<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.rule == other.rule && self.dot == other.dot && self.origin == other.origin
+    }
+}
+
+impl<T: Symbol> Eq for EarleyState<'_, T> {}
+
+impl<T: Symbol> Hash for EarleyState<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rule.hash(state);
+        self.dot.hash(state);
+        self.origin.hash(state);
+    }
+}
+
+/// A set of earley states.
+/// The states that are waiting for a nonterminal to be matched are also
+/// indexed by the id of that nonterminal, so that completion can find them quickly.
+struct EarleySet<'a, T = char> {
+    states: HashSet<Rc<EarleyState<'a, T>>>,
+    waiting: HashMap<u32, Vec<Rc<EarleyState<'a, T>>>>,
+}
+
+impl<'a, T: Symbol> EarleySet<'a, T> {
+    fn new() -> Self {
+        EarleySet {
+            states: HashSet::new(),
+            waiting: HashMap::new(),
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &Rc<EarleyState<'a, T>>> {
+        self.states.iter()
+    }
+
+    /// Returns the states that are waiting for the given nonterminal.
+    fn waiting_for(&self, nonterminal: NonTerminal<'a>) -> &[Rc<EarleyState<'a, T>>] {
+        match self.waiting.get(&nonterminal.id) {
+            Some(states) => states,
+            None => &[],
+        }
+    }
 }
 
 /// The early table has k+1 sets, where k is the length
 /// of the word to recognize.
 /// Each set i holds the states at position i.
-struct EarleyTable<'a> {
-    sets: Vec<HashSet<Rc<EarleyState<'a>>>>,
+struct EarleyTable<'a, T = char> {
+    sets: Vec<EarleySet<'a, T>>,
+    // The number of states in all the sets.
+    state_count: usize,
+    // The work done to fill the sets.
+    stats: ChartStats,
+    // The time spent in each phase, only measured if the options ask for it.
+    timings: PhaseTimings,
 }
 
-impl<'a> EarleyTable<'a> {
+impl<'a, T: Symbol> EarleyTable<'a, T> {
     fn new(size: usize) -> Self {
         let mut sets = Vec::new();
         for _ in 0..size {
-            sets.push(HashSet::new())
+            sets.push(EarleySet::new())
+        }
+        EarleyTable {
+            sets,
+            state_count: 0,
+            stats: ChartStats::default(),
+            timings: PhaseTimings::default(),
+        }
+    }
+
+    /// Adds the state to the set at index k. If the set already holds
+    /// an equal state, its derivations are merged into the existing one.
+    /// Returns the state if it is new.
+    fn insert(&mut self, k: usize, state: EarleyState<'a, T>) -> Option<Rc<EarleyState<'a, T>>> {
+        let set = &mut self.sets[k];
+        if let Some(existing) = set.states.get(&state) {
+            let mut derivations = existing.derivations.borrow_mut();
+            for derivation in state.derivations.into_inner() {
+                if !derivations.contains(&derivation) {
+                    derivations.push(derivation);
+                }
+            }
+            return None;
+        }
+
+        let state = Rc::new(state);
+        if !state.is_finished() {
+            if let Token::NT(nonterminal) = state.current_token() {
+                set.waiting
+                    .entry(nonterminal.id)
+                    .or_default()
+                    .push(state.clone());
+            }
+        }
+        self.state_count += 1;
+        set.states.insert(state.clone());
+        Some(state)
+    }
+
+    /// Changes the number of sets, dropping the sets at the end
+    /// or adding empty ones.
+    fn resize(&mut self, size: usize) {
+        let kept = size.min(self.sets.len());
+        for set in self.sets.drain(kept..) {
+            // The dropped states may be derived from themselves, like in `drop`.
+            for state in set.states.iter() {
+                state.derivations.borrow_mut().clear();
+            }
+            self.state_count -= set.states.len();
+        }
+        while self.sets.len() < size {
+            self.sets.push(EarleySet::new());
         }
-        EarleyTable { sets }
     }
 }
 
-impl<'a> Grammar<'a> {
-    /// For each state
-    fn prediction<'g>(&'g self, early_table: &mut EarleyTable<'g>, k: usize) {
-        let mut to_add = Vec::new();
-        for state in early_table.sets[k].iter() {
-            if state.is_finished() {
-                continue;
+/// The states of a set that are left to process while the set is filled.
+struct Agenda<'a, T = char> {
+    pending: Vec<Rc<EarleyState<'a, T>>>,
+    // The finished states that started in this set, by nonterminal id. They
+    // derive the empty word, so the states that wait for their nonterminal
+    // can be advanced over them at once, even if they are added later.
+    empty: HashMap<u32, Vec<Rc<EarleyState<'a, T>>>>,
+}
+
+impl<'a, T: Symbol> Agenda<'a, T> {
+    /// Starts with the states already in the set.
+    fn new(set: &EarleySet<'a, T>) -> Self {
+        Agenda {
+            pending: set.iter().cloned().collect(),
+            empty: HashMap::new(),
+        }
+    }
+
+    fn next(&mut self) -> Option<Rc<EarleyState<'a, T>>> {
+        self.pending.pop()
+    }
+
+    /// Adds the state to the set at index k, and to the agenda if it is new.
+    fn add(&mut self, table: &mut EarleyTable<'a, T>, k: usize, state: EarleyState<'a, T>) {
+        if let Some(state) = table.insert(k, state) {
+            self.pending.push(state);
+        }
+    }
+
+    /// Records a finished state that started in this set.
+    fn finished_empty(&mut self, state: &Rc<EarleyState<'a, T>>) {
+        self.empty
+            .entry(state.rule.from.id)
+            .or_default()
+            .push(state.clone());
+    }
+
+    /// Returns the finished states of the nonterminal that started in this set.
+    fn empty_at(&self, nonterminal: NonTerminal<'a>) -> &[Rc<EarleyState<'a, T>>] {
+        match self.empty.get(&nonterminal.id) {
+            Some(states) => states,
+            None => &[],
+        }
+    }
+}
+
+/// The error returned when the earley table grows past the allowed number of states.
+#[derive(Debug)]
+pub struct ParseLimitExceeded {
+    /// The allowed number of states.
+    pub max_states: usize,
+    /// The index of the set that was being filled when the limit was exceeded.
+    pub position: usize,
+}
+
+/// Options that change how the earley table is built.
+#[derive(Default, Clone, Copy)]
+struct TableOptions {
+    /// Compare terminals with the input ignoring ASCII case.
+    case_insensitive: bool,
+    /// Stop building the table once it holds more states than this.
+    max_states: Option<usize>,
+    /// Measure the time spent in each phase.
+    timed: bool,
+}
+
+/// Measures the time between the calls to `lap`, if it is enabled.
+/// Otherwise, the clock is never read, and every lap takes no time.
+struct Stopwatch(Option<Instant>);
+
+impl Stopwatch {
+    fn new(enabled: bool) -> Self {
+        Stopwatch(enabled.then(Instant::now))
+    }
+
+    /// Returns the time since the previous lap, or since the stopwatch was created.
+    fn lap(&mut self) -> Duration {
+        match &mut self.0 {
+            Some(last) => {
+                let now = Instant::now();
+                let elapsed = now - *last;
+                *last = now;
+                elapsed
             }
+            None => Duration::ZERO,
+        }
+    }
+}
 
-            let current_token = state.current_token();
+impl TableOptions {
+    /// Returns true if the terminal matches the given input symbol.
+    fn matches<T: Symbol>(&self, terminal: Terminal<T>, symbol: T) -> bool {
+        if self.case_insensitive {
+            let (lower, upper) = symbol.ascii_cases();
+            terminal.matches(lower) || terminal.matches(upper)
+        } else {
+            terminal.matches(symbol)
+        }
+    }
 
-            let nonterminal = match current_token {
-                Token::T(_) => continue,
-                Token::NT(n) => n,
-            };
+    /// Returns an error if the table holds more states than allowed.
+    fn check_limit<T>(
+        &self,
+        table: &EarleyTable<T>,
+        position: usize,
+    ) -> Result<(), ParseLimitExceeded> {
+        match self.max_states {
+            Some(max_states) if table.state_count > max_states => Err(ParseLimitExceeded {
+                max_states,
+                position,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
 
-            for rule in self.rules.iter() {
-                if rule.from == nonterminal {
-                    to_add.push(Rc::new(EarleyState::new(rule, 0, k)));
-                }
+impl<T> Drop for EarleyTable<'_, T> {
+    fn drop(&mut self) {
+        // Cyclic grammars can create states that are derived from themselves.
+        // Clear the derivations so that the reference cycles are dropped.
+        for set in self.sets.iter() {
+            for state in set.states.iter() {
+                state.derivations.borrow_mut().clear();
             }
         }
+    }
+}
+
+/// Returns the token of the leaf for a terminal that scanned the symbol.
+/// A character class is replaced with the character it matched,
+/// so that the leaves spell the input.
+fn leaf_token<T: Symbol>(token: Token<'_, T>, symbol: T) -> Token<'_, T> {
+    match token {
+        Token::T(Terminal::Class(_)) => Token::T(Terminal::Symbol(symbol)),
+        token => token,
+    }
+}
+
+/// The index of the first matching rule for the nodes derived by a rule, keyed by
+/// the nonterminal and the address and length of the right side of the rule.
+type RuleIndices<'a, T> = HashMap<(NonTerminal<'a>, *const Token<'a, T>, usize), usize>;
+
+/// Returns true if the token of a node can stand for the given token of a rule.
+fn leaf_matches<T: Symbol>(rule_token: Token<T>, node_token: Token<T>) -> bool {
+    match (rule_token, node_token) {
+        (Token::T(Terminal::Class(class)), Token::T(Terminal::Symbol(symbol))) => {
+            symbol.in_class(class)
+        }
+        _ => rule_token == node_token,
+    }
+}
+
+impl<'a, T: Symbol> Grammar<'a, T> {
+    /// Predicts the rules of the nonterminal the state is waiting for, and
+    /// advances the state over the finished states of that nonterminal which
+    /// started at this position, since they were completed before the state
+    /// was added. The new states are added to the agenda.
+    fn prediction<'g>(
+        &'g self,
+        early_table: &mut EarleyTable<'g, T>,
+        k: usize,
+        state: &Rc<EarleyState<'g, T>>,
+        agenda: &mut Agenda<'g, T>,
+    ) {
+        let nonterminal = match state.current_token() {
+            Token::T(_) => return,
+            Token::NT(n) => n,
+        };
+
+        for &index in self.rules_for(nonterminal) {
+            early_table.stats.predictions += 1;
+            agenda.add(early_table, k, EarleyState::new(&self.rules[index], 0, k));
+        }
+
+        let empty: Vec<_> = agenda.empty_at(nonterminal).to_vec();
+        early_table.stats.completions += empty.len();
+        for child in empty {
+            let completed = state.advance(Derivation::Complete {
+                previous: state.clone(),
+                child,
+            });
+            agenda.add(early_table, k, completed);
+        }
+    }
+
+    /// Scans the next symbol, if the state is waiting for a terminal that
+    /// matches it. The new state belongs to the next set, so it is not
+    /// added to the agenda.
+    fn scan(
+        &self,
+        early_table: &mut EarleyTable<'a, T>,
+        k: usize,
+        state: &Rc<EarleyState<'a, T>>,
+        next_symbol: T,
+        options: TableOptions,
+    ) {
+        let terminal = match state.current_token() {
+            Token::NT(_) => return,
+            Token::T(t) => t,
+        };
+
+        if options.matches(terminal, next_symbol) {
+            early_table.stats.scans += 1;
+            early_table.insert(
+                k + 1,
+                state.advance(Derivation::Scan(state.clone(), next_symbol)),
+            );
+        }
+    }
+
+    /// Advances the states waiting for the nonterminal of the finished state,
+    /// in the set at its origin. The new states are added to the agenda.
+    fn complete(
+        &self,
+        early_table: &mut EarleyTable<'a, T>,
+        k: usize,
+        state: &Rc<EarleyState<'a, T>>,
+        agenda: &mut Agenda<'a, T>,
+    ) {
+        if state.origin == k {
+            agenda.finished_empty(state);
+        }
+
+        // Find old states that are waiting for the current nonterminal to be matched.
+        let to_add: Vec<_> = early_table.sets[state.origin]
+            .waiting_for(state.rule.from)
+            .iter()
+            .map(|old_state| {
+                old_state.advance(Derivation::Complete {
+                    previous: old_state.clone(),
+                    child: state.clone(),
+                })
+            })
+            .collect();
+
+        early_table.stats.completions += to_add.len();
+        for completed in to_add {
+            agenda.add(early_table, k, completed);
+        }
+    }
+
+    /// Returns the index of the given rule in the grammar.
+    fn rule_index(&self, rule: &Rule<T>) -> usize {
+        self.rules
+            .iter()
+            .position(|r| std::ptr::eq(r, rule))
+            .expect("The rule does not belong to this grammar!")
+    }
+
+    /// Builds the earley table for the given symbols, using the given options.
+    /// Fails only if the options limit the number of states.
+    fn build_table_with(
+        &self,
+        input: &[T],
+        options: TableOptions,
+    ) -> Result<EarleyTable<'_, T>, ParseLimitExceeded> {
+        self.build_table_from(self.start, input, options)
+    }
+
+    /// Builds the earley table for the given symbols, starting from the rules
+    /// of the given nonterminal instead of the start symbol.
+    fn build_table_from(
+        &self,
+        start: NonTerminal<'a>,
+        input: &[T],
+        options: TableOptions,
+    ) -> Result<EarleyTable<'_, T>, ParseLimitExceeded> {
+        let mut table = EarleyTable::new(input.len() + 1);
 
-        for state in to_add {
-            early_table.sets[k].insert(state);
+        // Add the starting rules.
+        for &index in self.rules_for(start) {
+            table.insert(0, EarleyState::new(&self.rules[index], 0, 0));
         }
+
+        self.fill_table(&mut table, input, 0, options)?;
+        Ok(table)
     }
 
-    fn scan(&self, early_table: &mut EarleyTable<'a>, k: usize, next_char: char) {
-        let mut to_add = Vec::new();
+    /// Fills the sets of the table from the one at index `first` onwards.
+    /// The sets before it must be complete: each set depends only on the symbols
+    /// before its position, so they stay the same when the rest of the input changes.
+    fn fill_table<'g>(
+        &'g self,
+        table: &mut EarleyTable<'g, T>,
+        input: &[T],
+        first: usize,
+        options: TableOptions,
+    ) -> Result<(), ParseLimitExceeded> {
+        for (position, &c) in input.iter().enumerate().skip(first) {
+            self.fill_set(table, position, Some(c), options)?;
+        }
+
+        // Nothing is scanned in the last set. For the empty input, it is the only set,
+        // and it holds a finished start state iff the start nonterminal is nullable.
+        self.fill_set(table, input.len(), None, options)
+    }
 
-        for state in early_table.sets[k].iter() {
+    /// Fills the set at the given position, scanning the next symbol into the
+    /// following set, which must exist. The last set has no next symbol.
+    fn fill_set<'g>(
+        &'g self,
+        table: &mut EarleyTable<'g, T>,
+        position: usize,
+        next: Option<T>,
+        options: TableOptions,
+    ) -> Result<(), ParseLimitExceeded> {
+        // Each state of the set is taken from the agenda once. A state that is
+        // reached again only gets new derivations, which the states derived
+        // from it share, so it needs no more work.
+        let mut stopwatch = Stopwatch::new(options.timed);
+        let mut agenda = Agenda::new(&table.sets[position]);
+        while let Some(state) = agenda.next() {
+            table.stats.iterations += 1;
             if state.is_finished() {
-                continue;
+                self.complete(table, position, &state, &mut agenda);
+                table.timings.completion += stopwatch.lap();
+            } else {
+                self.prediction(table, position, &state, &mut agenda);
+                table.timings.prediction += stopwatch.lap();
+                if let Some(c) = next {
+                    self.scan(table, position, &state, c, options);
+                }
+                table.timings.scan += stopwatch.lap();
             }
+            options.check_limit(table, position)?;
+        }
+        Ok(())
+    }
 
-            let current_token = state.current_token();
+    /// Returns the finished start states in the last set of the table.
+    /// Their derivations are the derivations of the whole word.
+    fn accepting_states<'t, 'g>(
+        &self,
+        table: &'t EarleyTable<'g, T>,
+    ) -> Vec<&'t Rc<EarleyState<'g, T>>> {
+        self.accepting_states_of(table, self.start)
+    }
 
-            let terminal = match current_token {
-                Token::NT(_) => continue,
-                Token::T(t) => t,
-            };
+    /// Returns the finished states of the given nonterminal that span the whole table.
+    fn accepting_states_of<'t, 'g>(
+        &self,
+        table: &'t EarleyTable<'g, T>,
+        start: NonTerminal<'a>,
+    ) -> Vec<&'t Rc<EarleyState<'g, T>>> {
+        table.sets[table.sets.len() - 1]
+            .iter()
+            .filter(|state| state.rule.from == start && state.is_finished() && state.origin == 0)
+            .collect()
+    }
 
-            if terminal.content != next_char {
-                continue;
-            }
+    /// Returns the length of the longest prefix of the input of the table
+    /// derived from the given start, if there is one.
+    ///
+    /// The sets are never changed after the next ones are filled, so the
+    /// table of a prefix is the start of the table of the input.
+    fn longest_derived_prefix(
+        &self,
+        table: &EarleyTable<T>,
+        start: NonTerminal<'a>,
+    ) -> Option<usize> {
+        (0..table.sets.len()).rev().find(|&k| {
+            table.sets[k]
+                .iter()
+                .any(|state| state.rule.from == start && state.is_finished() && state.origin == 0)
+        })
+    }
 
-            to_add.push(Rc::new(EarleyState {
-                rule: state.rule,
-                dot: state.dot + 1,
-                origin: state.origin,
-                children: state.children.clone(),
-            }));
-        }
+    /// Returns the index of the last set of the table that holds any state.
+    /// The sets after it are empty, because no state could scan the next character.
+    fn furthest_position(&self, table: &EarleyTable<T>) -> usize {
+        table
+            .sets
+            .iter()
+            .rposition(|set| set.iter().next().is_some())
+            .unwrap_or(0)
+    }
+
+    /// Returns true if the sequence of symbols belongs to the language of the grammar.
+    /// This is `recognizes` for grammars over any kind of symbol.
+    pub fn recognizes_symbols(&self, input: &[T]) -> bool {
+        let table = self
+            .build_table_with(input, TableOptions::default())
+            .expect("The table has no limit!");
+        !self.accepting_states(&table).is_empty()
+    }
+
+    /// Returns the parse trees of all the derivations of the sequence of symbols,
+    /// in the same order as `parse`. This is `parse` for grammars over any kind of symbol.
+    pub fn parse_symbols(&self, input: &[T]) -> Vec<Rc<ParseNode<'_, T>>> {
+        self.parse_with(input, TableOptions::default())
+            .expect("The table has no limit!")
+    }
+
+    fn parse_with(
+        &self,
+        input: &[T],
+        options: TableOptions,
+    ) -> Result<Vec<Rc<ParseNode<'_, T>>>, ParseLimitExceeded> {
+        let table = self.build_table_with(input, options)?;
+        Ok(self.parse_trees(&table, input.len()))
+    }
+
+    /// Builds the parse trees of the derivations of the whole word,
+    /// whose length is `last`, in the order of their leftmost derivations.
+    fn parse_trees<'g>(
+        &self,
+        table: &EarleyTable<'g, T>,
+        last: usize,
+    ) -> Vec<Rc<ParseNode<'g, T>>> {
+        self.parse_trees_of(table, self.start, 0, last)
+    }
+
+    /// Builds the parse trees of the derivations of the whole table from the given
+    /// nonterminal. The spans of the nodes are shifted by `offset`.
+    fn parse_trees_of<'g>(
+        &self,
+        table: &EarleyTable<'g, T>,
+        start: NonTerminal<'a>,
+        offset: usize,
+        last: usize,
+    ) -> Vec<Rc<ParseNode<'g, T>>> {
+        let mut result = Vec::new();
 
-        for state in to_add {
-            early_table.sets[k + 1].insert(state);
+        for state in self.accepting_states_of(table, start) {
+            result.extend(parse_tree::build_parse_trees(state, offset, last));
         }
+
+        self.sort_trees(&mut result);
+        result
     }
 
-    fn complete(&self, early_table: &mut EarleyTable<'a>, k: usize) {
-        let mut to_add = Vec::new();
+    /// Sorts the trees by their leftmost derivations and removes the duplicates.
+    ///
+    /// The derivations are found in an order that depends on the iteration
+    /// order of the sets, so the trees are sorted to always return them in the same order.
+    /// Rules that differ only by their label derive the same trees, which end up
+    /// next to each other, since the leftmost derivation uses the first matching rule.
+    fn sort_trees(&self, trees: &mut Vec<Rc<ParseNode<'_, T>>>) {
+        let indices = self.leftmost_rule_indices();
+        trees.sort_by_cached_key(|tree| self.leftmost_derivation_with(tree, &indices));
+        trees.dedup();
+    }
 
-        for state in early_table.sets[k].iter() {
-            // We only look at finished states.
-            if !state.is_finished() {
-                continue;
+    /// Returns the indices of the rules used by the leftmost derivation of the tree.
+    #[cfg(test)]
+    fn leftmost_derivation(&self, tree: &ParseNode<T>) -> Vec<usize> {
+        self.leftmost_derivation_with(tree, &self.leftmost_rule_indices())
+    }
+
+    fn leftmost_derivation_with(
+        &self,
+        tree: &ParseNode<T>,
+        indices: &RuleIndices<'a, T>,
+    ) -> Vec<usize> {
+        // The rules of the leftmost derivation are the ones of the nonterminals in pre-order.
+        tree.preorder()
+            .filter_map(|node| match node.token {
+                Token::NT(n) => Some(self.rule_of_node(node, n, indices)),
+                Token::T(_) => None,
+            })
+            .collect()
+    }
+
+    /// Maps the right side of each rule, by its address, to the index of the first
+    /// rule that matches the nodes it derives. The nodes of the rules with a class
+    /// of terminals are left out, since the rule that matches them depends on their leaves.
+    fn leftmost_rule_indices(&self) -> RuleIndices<'a, T> {
+        self.rules
+            .iter()
+            .filter(|rule| {
+                !rule
+                    .to
+                    .iter()
+                    .any(|t| matches!(t, Token::T(Terminal::Class(_))))
+            })
+            .map(|rule| {
+                let index = self
+                    .first_matching_rule(rule.from, &rule.to)
+                    .expect("A rule always matches itself!");
+                ((rule.from, rule.to.as_ptr(), rule.to.len()), index)
+            })
+            .collect()
+    }
+
+    /// Returns the index of the first rule of the nonterminal whose right side
+    /// matches the tokens of the children.
+    fn first_matching_rule(
+        &self,
+        nonterminal: NonTerminal<'a>,
+        children: &[Token<'a, T>],
+    ) -> Option<usize> {
+        self.rules_for(nonterminal).iter().copied().find(|&index| {
+            let to = &self.rules[index].to;
+            to.len() == children.len()
+                && to
+                    .iter()
+                    .zip(children.iter())
+                    .all(|(&t, &c)| leaf_matches(t, c))
+        })
+    }
+
+    /// Returns the index of the rule that derived the node of the nonterminal.
+    fn rule_of_node(
+        &self,
+        node: &ParseNode<T>,
+        nonterminal: NonTerminal<'a>,
+        indices: &RuleIndices<'a, T>,
+    ) -> usize {
+        let known = node
+            .rule
+            .and_then(|to| indices.get(&(nonterminal, to.as_ptr(), to.len())));
+        match known {
+            Some(&index) => index,
+            None => {
+                let children: Vec<Token<T>> = node.children.iter().map(|c| c.token).collect();
+                self.first_matching_rule(nonterminal, &children)
+                    .expect("The tree does not belong to this grammar!")
             }
+        }
+    }
+}
+
+impl<'a> Grammar<'a> {
+    /// Builds the earley table for the given word.
+    fn build_table(&self, s: &str) -> EarleyTable<'_> {
+        let input: Vec<char> = s.chars().collect();
+        self.build_table_with(&input, TableOptions::default())
+            .expect("The table has no limit!")
+    }
 
-            let current_nonterminal = state.rule.from;
-            let origin = state.origin;
+    /// Returns true if the word belongs to the language of the grammar.
+    /// This only builds the earley table, without enumerating the derivations.
+    pub fn recognizes(&self, s: &str) -> bool {
+        let table = self.build_table(s);
+        !self.accepting_states(&table).is_empty()
+    }
 
-            for old_state in early_table.sets[origin].iter() {
-                // Find old states that are waiting for the current_nonterminal to be matched.
-                if old_state.is_finished() {
-                    continue;
-                }
+    /// Returns the number of rules of the start nonterminal that derive the whole word,
+    /// which is the number of finished states of the start nonterminal in the last set.
+    ///
+    /// This tells if the word is accepted through several top-level rules, without
+    /// enumerating the derivations. It is not the number of derivations, which is
+    /// given by `parse_all(s).count()`: a single accepting rule can have many derivations.
+    pub fn accepting_items(&self, s: &str) -> usize {
+        let table = self.build_table(s);
+        self.accepting_states(&table).len()
+    }
 
-                let current_token = old_state.current_token();
-                let nonterminal = match current_token {
-                    Token::T(_) => continue,
-                    Token::NT(n) => n,
-                };
-
-                if nonterminal == current_nonterminal {
-                    let mut new_children_list = old_state.children.clone();
-                    new_children_list.push(state.clone());
-
-                    to_add.push(Rc::new(EarleyState {
-                        rule: old_state.rule,
-                        dot: old_state.dot + 1,
-                        origin: old_state.origin,
-                        children: new_children_list,
-                    }));
-                }
+    /// Returns the parse trees of all the derivations of the word.
+    ///
+    /// The trees are sorted by the indices of the rules used in their leftmost
+    /// derivation (see `leftmost_derivation`), compared lexicographically. The order
+    /// only depends on the grammar and the word, and the first tree is the one
+    /// that uses the earliest declared rules first. Reordering the rules of a
+    /// grammar reorders the trees in the same way. Derivations that build
+    /// the same tree, using rules that differ only by their labels, give one tree.
+    ///
+    /// With a cyclic grammar (see `has_epsilon_cycle`), a word can have infinitely
+    /// many derivations, like `S -> A`, `A -> A`, `A -> a` for `a`. The derivations
+    /// are then cut where a nonterminal would derive itself over the same symbols,
+    /// which gives a finite set of trees, always the same one: here, only `S[A[a]]`.
+    pub fn parse(&self, s: &str) -> Vec<Rc<ParseNode<'_>>> {
+        let input: Vec<char> = s.chars().collect();
+        self.parse_symbols(&input)
+    }
+
+    /// Returns the same trees as `parse`, for the characters given by the iterator,
+    /// which is consumed lazily, like the characters read from a stream.
+    ///
+    /// The number of characters is not known in advance, so the table grows by one
+    /// set for each character. The iterator stops being consumed as soon as no
+    /// derivation can continue, since the word can't be derived anymore.
+    pub fn parse_chars<I: Iterator<Item = char>>(&self, chars: I) -> Vec<Rc<ParseNode<'_>>> {
+        let options = TableOptions::default();
+        let mut table = self.build_table("");
+        let mut last = 0;
+        for c in chars {
+            // The first set was filled without a character to scan.
+            table.resize(last + 2);
+            self.fill_set(&mut table, last, Some(c), options)
+                .expect("The table has no limit!");
+            last += 1;
+            if table.sets[last].states.is_empty() {
+                return Vec::new();
             }
         }
-        for state in to_add {
-            early_table.sets[k].insert(state);
+        self.fill_set(&mut table, last, None, options)
+            .expect("The table has no limit!");
+        self.parse_trees(&table, last)
+    }
+
+    /// Returns the same trees as [`Grammar::parse`], converted to trees that
+    /// can be sent to other threads. See [`SyncParseNode`] for the cost.
+    pub fn parse_arc(&self, s: &str) -> Vec<Arc<SyncParseNode<'_>>> {
+        SyncParseNode::from_trees(&self.parse(s))
+    }
+
+    /// Parses every input, returning the trees of each input in the order
+    /// of `inputs`, the same as calling [`Grammar::parse`] on each of them.
+    ///
+    /// The inputs are split into consecutive chunks, one for each of the
+    /// threads given by `available_parallelism`, and each chunk is parsed on its
+    /// own scoped thread, which builds its own tables and shares only the grammar,
+    /// read-only. Since `Rc` cannot cross threads, the workers return [`SyncParseNode`]
+    /// trees, and all of them are joined before the trees are converted back
+    /// on the calling thread.
+    pub fn parse_batch(&self, inputs: &[&str]) -> Vec<Vec<Rc<ParseNode<'_>>>> {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = inputs.len().div_ceil(threads).max(1);
+
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let workers: Vec<_> = inputs
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|input| self.parse_arc(input))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("A parsing thread panicked!"))
+                .collect()
+        });
+        results
+            .iter()
+            .map(|trees| SyncParseNode::to_trees(trees))
+            .collect()
+    }
+
+    /// Parses a sequence of bytes instead of a string. Each byte is matched
+    /// by the terminal holding the character with the same code, which is
+    /// written `\xHH` in the grammar (or as itself, for the plain terminals).
+    /// The leaves of the parse trees hold these characters.
+    pub fn parse_bytes(&self, input: &[u8]) -> Vec<Rc<ParseNode<'_>>> {
+        let input: Vec<char> = input.iter().map(|&byte| char::from(byte)).collect();
+        self.parse_symbols(&input)
+    }
+
+    /// Returns the parse trees of the word for which `keep` returns true,
+    /// in the same order as `parse`. The rejected trees are dropped as soon as
+    /// they are built, before the trees are sorted.
+    pub fn parse_filtered<F>(&self, s: &str, keep: F) -> Vec<Rc<ParseNode<'_>>>
+    where
+        F: Fn(&ParseNode) -> bool,
+    {
+        let table = self.build_table(s);
+        let last = s.chars().count();
+
+        let mut result = Vec::new();
+        for state in self.accepting_states(&table) {
+            let trees = parse_tree::build_parse_trees(state, 0, last);
+            result.extend(trees.into_iter().filter(|tree| keep(tree)));
         }
+        self.sort_trees(&mut result);
+        result
     }
 
-    pub fn parse(&self, s: &str) -> Vec<Rc<ParseNode>> {
-        let mut table = EarleyTable::new(s.len() + 1);
+    /// Returns the trees of `parse` without the nonterminals that derive the empty
+    /// word, as if they weren't there. Only the root is kept, even if the word is empty.
+    ///
+    /// Trees that differ only by their empty parts become the same tree, which is
+    /// returned once, where it first appears in the order of `parse`.
+    pub fn parse_pruned(&self, s: &str) -> Vec<Rc<ParseNode<'_>>> {
+        let mut seen = HashSet::new();
+        self.parse(s)
+            .iter()
+            .map(parse_tree::prune_empty)
+            .filter(|tree| seen.insert(tree.clone()))
+            .collect()
+    }
 
-        // Add the starting rules.
-        for rule in self.rules.iter() {
-            if rule.from == self.start {
-                table.sets[0].insert(Rc::new(EarleyState::new(rule, 0, 0)));
+    /// Returns the trees of `parse` with their chains of single nonterminal children
+    /// collapsed, see `collapse_unit_chains`. Like for `parse_pruned`, trees that
+    /// become the same are returned once.
+    pub fn parse_collapsed(&self, s: &str) -> Vec<Rc<ParseNode<'_>>> {
+        let mut seen = HashSet::new();
+        self.parse(s)
+            .iter()
+            .map(collapse_unit_chains)
+            .filter(|tree| seen.insert(tree.clone()))
+            .collect()
+    }
+
+    /// Parses the longest prefix of the word derived from the start nonterminal.
+    /// Returns the first parse tree of the prefix, in the order of `parse`, and
+    /// the number of characters in the prefix, or None if no prefix, not even
+    /// the empty one, is derived from the start nonterminal.
+    pub fn parse_prefix(&self, s: &str) -> Option<(Rc<ParseNode<'_>>, usize)> {
+        let mut table = self.build_table(s);
+        let last = self.longest_derived_prefix(&table, self.start)?;
+        table.resize(last + 1);
+
+        let trees = self.parse_trees_of(&table, self.start, 0, last);
+        trees.into_iter().next().map(|tree| (tree, last))
+    }
+
+    /// Parses the shortest prefix of the word derived from the start nonterminal,
+    /// like the first message of a stream. Returns the first parse tree of the prefix,
+    /// in the order of `parse`, and the number of characters in the prefix, or None
+    /// if no prefix is derived from the start nonterminal.
+    ///
+    /// This is the opposite of `parse_prefix`. The table is built one set at a time,
+    /// so the characters after the prefix are never read.
+    pub fn parse_earliest_complete(&self, s: &str) -> Option<(Rc<ParseNode<'_>>, usize)> {
+        let input: Vec<char> = s.chars().collect();
+        let mut table = self.build_table("");
+        for last in 0..=input.len() {
+            if last > 0 {
+                // The set before the new one is built again, to scan its character.
+                table.resize(last + 1);
+                self.fill_table(
+                    &mut table,
+                    &input[..last],
+                    last - 1,
+                    TableOptions::default(),
+                )
+                .expect("The table has no limit!");
+            }
+            if !self.accepting_states(&table).is_empty() {
+                let trees = self.parse_trees(&table, last);
+                return trees.into_iter().next().map(|tree| (tree, last));
             }
         }
+        None
+    }
+
+    /// Splits the word into lexemes: words derived from the nonterminal with
+    /// the given name. Each lexeme is the longest one that starts where the
+    /// previous one ends. Returns None if the lexemes can't cover the whole word,
+    /// or if there is no such nonterminal.
+    ///
+    /// Since the lexemes are chosen greedily, this can fail even if the word can
+    /// be split in another way, like `ab` `c` instead of `abc` when only `ab` and `c`
+    /// are lexemes and `abc` is the longest one.
+    pub fn tokenize(&self, s: &str, lexeme: &str) -> Option<Vec<String>> {
+        let lexeme = *self.nonterminals.get(lexeme)?;
+        let chars: Vec<char> = s.chars().collect();
 
-        for (position, c) in s.chars().enumerate() {
-            // Repeat prediction, scan, completion until no new states
-            // can be added to the current set.
-            loop {
-                let old_size = table.sets[position].len();
-                self.prediction(&mut table, position);
-                self.scan(&mut table, position, c);
-                self.complete(&mut table, position);
+        let mut result = Vec::new();
+        let mut position = 0;
+        while position < chars.len() {
+            let table = self
+                .build_table_from(lexeme, &chars[position..], TableOptions::default())
+                .expect("The table has no limit!");
+            // An empty lexeme would never move forward.
+            let length = self
+                .longest_derived_prefix(&table, lexeme)
+                .filter(|&length| length > 0)?;
+            result.push(chars[position..position + length].iter().collect());
+            position += length;
+        }
+        Some(result)
+    }
 
-                if table.sets[position].len() == old_size {
-                    break;
+    /// Returns the parts of the word covered by the nonterminal with the given name,
+    /// in any of the parse trees of the word.
+    pub fn matched_substrings(&self, s: &str, nonterminal: &str) -> HashSet<String> {
+        let mut spans = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut stack = self.parse(s);
+        while let Some(node) = stack.pop() {
+            // The trees share their subtrees, so each node is visited once.
+            if !visited.insert(Rc::as_ptr(&node)) {
+                continue;
+            }
+            if let Token::NT(n) = node.token {
+                if n.name == nonterminal {
+                    spans.insert((node.start, node.end));
                 }
             }
+            stack.extend(node.children.iter().cloned());
         }
 
-        let last = s.len();
-        loop {
-            let old_size = table.sets[last].len();
-            self.prediction(&mut table, last);
-            self.complete(&mut table, last);
+        let chars: Vec<char> = s.chars().collect();
+        spans
+            .into_iter()
+            .map(|(start, end)| chars[start..end].iter().collect())
+            .collect()
+    }
 
-            if table.sets[last].len() == old_size {
-                break;
+    /// Returns the spans covered by every nonterminal, in any of the parse trees of
+    /// the word, as `(nonterminal, start, end)` triples in characters. Each triple
+    /// is returned once, and they are sorted by start, then end, then name.
+    pub fn label_spans(&self, s: &str) -> Vec<(NonTerminal<'_>, usize, usize)> {
+        let mut spans = HashSet::new();
+        let mut visited = HashSet::new();
+        let mut stack = self.parse(s);
+        while let Some(node) = stack.pop() {
+            // The trees share their subtrees, so each node is visited once.
+            if !visited.insert(Rc::as_ptr(&node)) {
+                continue;
+            }
+            if let Token::NT(n) = node.token {
+                spans.insert((n, node.start, node.end));
             }
+            stack.extend(node.children.iter().cloned());
         }
 
-        println!("Earley table:");
-        println!("{}", table);
+        let mut spans: Vec<(NonTerminal, usize, usize)> = spans.into_iter().collect();
+        spans.sort_by_key(|&(n, start, end)| (start, end, n.name));
+        spans
+    }
+
+    /// Parses only the characters of the input in the given range, as a word
+    /// derived from the nonterminal `start` instead of the start symbol.
+    /// The spans of the nodes are positions in the whole input.
+    /// Returns no trees if `start` is not a nonterminal of the grammar.
+    ///
+    /// Panics if the range goes past the end of the input.
+    pub fn parse_range(
+        &self,
+        input: &str,
+        range: Range<usize>,
+        start: &str,
+    ) -> Vec<Rc<ParseNode<'_>>> {
+        let chars: Vec<char> = input.chars().skip(range.start).take(range.len()).collect();
+        assert_eq!(chars.len(), range.len(), "The range is out of the input!");
+
+        let Some(&start) = self.nonterminals.get(start) else {
+            return Vec::new();
+        };
+        let table = self
+            .build_table_from(start, &chars, TableOptions::default())
+            .expect("The table has no limit!");
+        self.parse_trees_of(&table, start, range.start, chars.len())
+    }
+
+    /// Parses the given word, but gives up once the earley table holds more than
+    /// `max_states` states, instead of running out of memory on ambiguous
+    /// grammars or long inputs.
+    pub fn parse_bounded(
+        &self,
+        s: &str,
+        max_states: usize,
+    ) -> Result<Vec<Rc<ParseNode<'_>>>, ParseLimitExceeded> {
+        let options = TableOptions {
+            max_states: Some(max_states),
+            ..TableOptions::default()
+        };
+        let input: Vec<char> = s.chars().collect();
+        self.parse_with(&input, options)
+    }
+
+    /// Parses the given word, comparing the terminals with the input while
+    /// ignoring case. The leaves of the parse trees hold the terminals of the
+    /// grammar, not the characters of the input, so a grammar spelling `s h e`
+    /// produces the same trees for `she` and `She`.
+    ///
+    /// Only ASCII letters are folded: non-ASCII characters must match exactly.
+    pub fn parse_case_insensitive(&self, s: &str) -> Vec<Rc<ParseNode<'_>>> {
+        let options = TableOptions {
+            case_insensitive: true,
+            ..TableOptions::default()
+        };
+        let input: Vec<char> = s.chars().collect();
+        self.parse_with(&input, options)
+            .expect("The table has no limit!")
+    }
+
+    /// Parses the given word and evaluates the semantic actions on each derivation,
+    /// without building the parse trees. Returns one value per derivation.
+    ///
+    /// Panics if a rule used in a derivation has no registered action.
+    pub fn parse_with_actions<T: Clone>(&self, s: &str, actions: &RuleActions<T>) -> Vec<T> {
+        let table = self.build_table(s);
+        let last = s.chars().count();
 
         let mut result = Vec::new();
 
-        for state in table.sets[last].iter() {
-            if state.rule.from == self.start && state.is_finished() && state.origin == 0 {
-                let tree = parse_tree::build_parse_tree(&state);
-                result.push(tree)
-            }
+        for state in self.accepting_states(&table) {
+            result.extend(actions::evaluate(self, state, last, actions));
         }
         result
     }
+
+    /// Replays a derivation given by rule indices: starting from the start
+    /// nonterminal, each rule rewrites the leftmost nonterminal of the sentential form.
+    /// Returns true if the final form has no nonterminals left and spells the target.
+    ///
+    /// Returns false if a rule index is out of range, or if the left side of a rule
+    /// isn't the leftmost nonterminal when it's applied.
+    pub fn derives(&self, steps: &[usize], target: &str) -> bool {
+        let mut form = vec![Token::NT(self.start)];
+        for &step in steps {
+            let Some(rule) = self.rules.get(step) else {
+                return false;
+            };
+            let Some(position) = form.iter().position(|t| matches!(t, Token::NT(_))) else {
+                return false;
+            };
+            if form[position] != Token::NT(rule.from) {
+                return false;
+            }
+            form.splice(position..position + 1, rule.to.iter().copied());
+        }
+
+        let target: Vec<char> = target.chars().collect();
+        form.len() == target.len()
+            && form
+                .iter()
+                .zip(target)
+                .all(|(token, c)| matches!(token, Token::T(t) if t.matches(c)))
+    }
 }
 
 impl fmt::Display for EarleyState<'_> {
@@ -231,6 +1187,10 @@ impl fmt::Display for EarleyTable<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for (i, states) in self.sets.iter().enumerate() {
             writeln!(f, "S{}", i)?;
+
+            // Sort the states, so that the same table is always printed the same way.
+            let mut states: Vec<_> = states.iter().collect();
+            states.sort_by_cached_key(|state| (state.rule.to_string(), state.dot, state.origin));
             for state in states {
                 writeln!(f, "{}", state)?;
             }
@@ -245,15 +1205,15 @@ mod test {
     use super::*;
 
     // Gets the string from the derivation by collecting the leaf nodes.
-    fn evaluate_parse_tree<'a>(root: &ParseNode<'a>) -> String {
-        if let Token::T(_) = root.token {
-            format!("{}", root.token)
-        } else {
-            let mut result = String::new();
-            for child in root.children.iter() {
-                result.push_str(&evaluate_parse_tree(&child));
-            }
-            result
+    fn evaluate_parse_tree(root: &ParseNode) -> String {
+        match root.as_terminal() {
+            Some(c) => c.to_string(),
+            // A nonterminal with no children derives the empty word.
+            None => root
+                .children
+                .iter()
+                .map(|child| evaluate_parse_tree(child))
+                .collect(),
         }
     }
 
@@ -266,7 +1226,7 @@ mod test {
         EXP -> EXP / EXP
         EXP -> ( EXP )
         EXP -> n";
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
 
         let trees = grammar.parse("(n+n+(n*n)-n/n)");
         assert_eq!(trees.len(), 14);
@@ -289,44 +1249,916 @@ mod test {
     }
 
     #[test]
-    fn test_palindrome_grammar() {
+    fn test_parse_tree_spans() {
         let grammar_string = "S
-        S -> a S a
-        S -> b S b 
-        S ->
-        S -> a
-        S -> b";
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
 
-        let trees = grammar.parse("abba");
+        let trees = grammar.parse("(()())");
         assert_eq!(trees.len(), 1);
-        assert_eq!(evaluate_parse_tree(&trees[0]), "abba");
 
-        let trees = grammar.parse("aabab");
-        assert_eq!(trees.len(), 0);
+        // The outermost S covers the whole input.
+        let root = &trees[0];
+        assert_eq!((root.start, root.end), (0, 6));
 
-        let trees = grammar.parse("aabaa");
-        assert_eq!(trees.len(), 1);
-        assert_eq!(evaluate_parse_tree(&trees[0]), "aabaa");
+        // S -> ( S ) S, where the inner S covers "()()".
+        let spans: Vec<(usize, usize)> = root.children.iter().map(|c| (c.start, c.end)).collect();
+        assert_eq!(spans, vec![(0, 1), (1, 5), (5, 6), (6, 6)]);
+
+        let inner = &root.children[1];
+        let spans: Vec<(usize, usize)> = inner.children.iter().map(|c| (c.start, c.end)).collect();
+        assert_eq!(spans, vec![(1, 2), (2, 2), (2, 3), (3, 5)]);
     }
 
     #[test]
-    fn test_paranthesis_grammar() {
+    fn test_node_accessors() {
         let grammar_string = "S
         S -> ( S ) S
         S -> ";
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("()");
+        let root = &trees[0];
+        assert_eq!(root.as_nonterminal(), Some("S"));
+        assert_eq!(root.as_terminal(), None);
+        assert!(!root.is_leaf());
+
+        let open = &root.children[0];
+        assert_eq!(open.as_nonterminal(), None);
+        assert_eq!(open.as_terminal(), Some('('));
+        assert!(open.is_leaf());
+
+        // A nonterminal that derives the empty word is a leaf too.
+        let empty = &root.children[1];
+        assert_eq!(empty.as_nonterminal(), Some("S"));
+        assert!(empty.is_leaf());
+    }
 
-        let trees = grammar.parse("(()()((()())))");
-        assert_eq!(trees.len(), 1);
-        assert_eq!(evaluate_parse_tree(&trees[0]), "(()()((()())))");
+    #[test]
+    fn test_node_rule() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> n
+        EXP -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let rule = |index: usize| Some(&grammar.rules[index].to[..]);
 
-        let trees = grammar.parse("(()(())()((()())))()()");
+        let trees = grammar.parse("n+n");
         assert_eq!(trees.len(), 1);
-        assert_eq!(evaluate_parse_tree(&trees[0]), "(()(())()((()())))()()");
+        let root = &trees[0];
+        assert!(root.rule == rule(0));
+        let production: Vec<String> = root.rule.unwrap().iter().map(|t| t.to_string()).collect();
+        assert_eq!(production, vec!["EXP", "+", "EXP"]);
+        assert!(root.children[0].rule == rule(1));
+        assert!(root.children[1].rule.is_none());
+
+        // The other parsers record the rules too.
+        let cyk = grammar.parse_cyk("n+n");
+        assert!(cyk[0].rule == rule(0));
+        assert!(cyk[0].children[2].rule == rule(1));
+        let best = grammar.parse_k_shortest("n+n", 1);
+        assert!(best[0].rule == rule(0));
+        assert!(grammar.parse("+")[0].children[0].rule == rule(2));
+    }
 
-        let trees = grammar.parse("(()(()))((()())))()()");
-        assert_eq!(trees.len(), 0);
+    #[test]
+    fn test_children_order() {
+        let grammar_string = "X
+        X -> a B c D
+        B -> b b
+        B -> b
+        D -> d D
+        D -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        for word in ["abbcdd", "abc", "abcd"] {
+            let trees = grammar.parse(word);
+            assert_eq!(trees.len(), 1);
+            let root = &trees[0];
+
+            // The children match the right side of the rule, from left to right.
+            let tokens: Vec<String> = root.children.iter().map(|c| c.token.to_string()).collect();
+            assert_eq!(tokens, vec!["a", "B", "c", "D"]);
+            assert_eq!(root.children[0].start, 0);
+            for pair in root.children.windows(2) {
+                assert_eq!(pair[0].end, pair[1].start);
+            }
+            assert_eq!(root.children[3].end, word.len());
+            assert_eq!(
+                evaluate_parse_tree(&root.children[1]),
+                word[1..word.find('c').unwrap()]
+            );
+        }
+    }
+
+    #[test]
+    fn test_span_text() {
+        let grammar_string = "S
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let input = "(()())";
+        let trees = grammar.parse(input);
+        let root = &trees[0];
+        assert_eq!(root.span_text(input), input);
+
+        let inner = &root.children[1];
+        assert_eq!(inner.span_text(input), "()()");
+        assert_eq!(inner.children[3].span_text(input), "()");
+        assert_eq!(inner.children[1].span_text(input), "");
+        assert_eq!(root.children[2].span_text(input), ")");
+
+        // The spans count characters, not bytes.
+        let grammar_string = "S
+        S -> \\xe9 S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let input = "\u{e9}\u{e9}a";
+        let trees = grammar.parse(input);
+        assert_eq!(trees[0].children[1].span_text(input), "\u{e9}a");
+        assert_eq!(trees[0].children[0].span_text(input), "\u{e9}");
+    }
+
+    #[test]
+    fn test_parse_into() {
+        struct Arithmetic;
+
+        impl Evaluator<i64> for Arithmetic {
+            fn leaf(&self, c: char) -> Result<i64, EvalError> {
+                match c {
+                    'n' => Ok(6),
+                    _ => Ok(0),
+                }
+            }
+
+            fn combine(&self, rule: usize, v: &[i64]) -> Result<i64, EvalError> {
+                match rule {
+                    0 => Ok(v[0] + v[2]),
+                    1 => Ok(v[0] * v[2]),
+                    2 => Ok(v[0] - v[2]),
+                    3 if v[2] == 0 => Err(EvalError::Invalid("division by zero".to_string())),
+                    3 => Ok(v[0] / v[2]),
+                    5 => Ok(v[0]),
+                    _ => Err(EvalError::UnsupportedRule(rule)),
+                }
+            }
+        }
+
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> EXP - EXP
+        EXP -> EXP / EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        assert_eq!(grammar.parse_into("n+n", &Arithmetic), Ok(vec![12]));
+        let mut values = grammar.parse_into("n+n*n", &Arithmetic).unwrap();
+        values.sort();
+        assert_eq!(values, vec![42, 72]);
+        assert_eq!(grammar.parse_into("n/n", &Arithmetic), Ok(vec![1]));
+        assert_eq!(grammar.parse_into("n+", &Arithmetic), Ok(vec![]));
+
+        assert_eq!(
+            grammar.parse_into("(n)", &Arithmetic),
+            Err(EvalError::UnsupportedRule(4))
+        );
+        // One of the two derivations divides by zero.
+        let error = grammar.parse_into("n/n-n", &Arithmetic).unwrap_err();
+        assert_eq!(error.to_string(), "division by zero");
+    }
+
+    #[test]
+    fn test_parse_with_actions() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> EXP - EXP
+        EXP -> EXP / EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        // Every n is evaluated to 2.
+        let mut actions = RuleActions::new(|c| if c == 'n' { 2.0 } else { 0.0 });
+        actions
+            .on_rule(0, |v: &[f64]| v[0] + v[2])
+            .on_rule(1, |v: &[f64]| v[0] * v[2])
+            .on_rule(2, |v: &[f64]| v[0] - v[2])
+            .on_rule(3, |v: &[f64]| v[0] / v[2])
+            .on_rule(4, |v: &[f64]| v[1])
+            .on_rule(5, |v: &[f64]| v[0]);
+
+        let mut values = grammar.parse_with_actions("n+n*n", &actions);
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(values, vec![6.0, 8.0]);
+
+        let values = grammar.parse_with_actions("(n+n)/(n*n)", &actions);
+        assert_eq!(values, vec![1.0]);
+
+        let values = grammar.parse_with_actions("(n+n", &actions);
+        assert!(values.is_empty());
+    }
+
+    #[test]
+    fn test_named_actions() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP @add
+        EXP -> EXP * EXP @mul
+        EXP -> ( EXP ) @group
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let mut actions = RuleActions::new(|c| if c == 'n' { 3 } else { 0 });
+        actions
+            .on_named("add", |v: &[i32]| v[0] + v[2])
+            .on_named("mul", |v: &[i32]| v[0] * v[2])
+            .on_named("group", |v: &[i32]| v[1])
+            .on_rule(3, |v: &[i32]| v[0]);
+        assert_eq!(grammar.parse_with_actions("(n+n)*n", &actions), vec![18]);
+
+        // An action registered by index takes precedence over the label.
+        actions.on_rule(0, |v: &[i32]| v[0] - v[2]);
+        assert_eq!(grammar.parse_with_actions("(n+n)*n", &actions), vec![0]);
+    }
+
+    #[test]
+    fn test_parse_arc() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> n",
+        )
+        .unwrap();
+        let trees = grammar.parse_arc("n+n+n");
+        assert_eq!(trees.len(), 2);
+
+        let tree = Arc::clone(&trees[0]);
+        let word = std::thread::scope(|scope| {
+            scope
+                .spawn(move || {
+                    tree.fold(&|token, children: Vec<String>| match token {
+                        Token::T(_) => format!("{}", token),
+                        Token::NT(_) => children.concat(),
+                    })
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(word, "n+n+n");
+    }
+
+    #[test]
+    fn test_parse_batch() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> n",
+        )
+        .unwrap();
+        let inputs = ["n+n*n", "n", "n+", "", "n*n*n+n"];
+        let batch = grammar.parse_batch(&inputs);
+        assert_eq!(batch.len(), inputs.len());
+        for (input, trees) in inputs.iter().zip(&batch) {
+            let sequential = grammar.parse(input);
+            assert_eq!(trees.len(), sequential.len());
+            for (tree, expected) in trees.iter().zip(&sequential) {
+                assert_eq!(format!("{:?}", tree), format!("{:?}", expected));
+            }
+        }
+        assert!(grammar.parse_batch(&[]).is_empty());
+
+        // More inputs than threads are split into chunks, keeping their order.
+        let words: Vec<String> = (0..100).map(|i| vec!["n"; i % 4 + 1].join("+")).collect();
+        let inputs: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+        let batch = grammar.parse_batch(&inputs);
+        assert_eq!(batch.len(), inputs.len());
+        for (input, trees) in inputs.iter().zip(&batch) {
+            assert_eq!(trees.len(), grammar.parse(input).len());
+        }
+    }
+
+    #[test]
+    fn test_parse_range() {
+        let grammar = Grammar::from_rules(
+            r"S
+        S -> l e t \s EXP
+        EXP -> EXP + EXP
+        EXP -> n",
+        )
+        .unwrap();
+        let input = "let n+n; ok";
+
+        let trees = grammar.parse_range(input, 4..7, "EXP");
+        assert_eq!(trees.len(), 1);
+        assert_eq!((trees[0].start, trees[0].end), (4, 7));
+        assert_eq!(trees[0].span_text(input), "n+n");
+        assert_eq!(trees[0].children[2].span_text(input), "n");
+        assert_eq!(evaluate_parse_tree(&trees[0]), "n+n");
+
+        assert_eq!(grammar.parse_range(input, 0..7, "S").len(), 1);
+        assert!(grammar.parse_range(input, 4..8, "EXP").is_empty());
+        assert!(grammar.parse_range(input, 4..7, "MISSING").is_empty());
+    }
+
+    #[test]
+    fn test_incremental_parser() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n",
+        )
+        .unwrap();
+        let mut parser = IncrementalParser::new(&grammar, "n+n*n");
+        assert_eq!(parser.parse(), grammar.parse("n+n*n"));
+
+        let edits = [
+            (1, 1, "*", "n*n*n"),
+            (4, 1, "(n+n)", "n*n*(n+n)"),
+            (0, 0, "n+", "n+n*n*(n+n)"),
+            (5, 6, "", "n+n*n"),
+            (5, 0, "+", "n+n*n+"),
+            (2, 4, "", "n+"),
+            (0, 2, "", ""),
+            (0, 0, "(n)", "(n)"),
+        ];
+        for (at, removed, inserted, word) in edits {
+            parser.edit(at, removed, inserted);
+            assert_eq!(parser.text(), word);
+            assert_eq!(parser.recognizes(), grammar.recognizes(word));
+            assert_eq!(parser.parse(), grammar.parse(word));
+        }
+    }
+
+    #[test]
+    fn test_parse_disambiguated() {
+        let grammar = Grammar::from_rules(
+            r"EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> EXP - EXP
+        EXP -> EXP / EXP
+        EXP -> EXP \x5e EXP
+        EXP -> ( EXP )
+        EXP -> n",
+        )
+        .unwrap();
+        let precedence = Precedence::new()
+            .left('+', 1)
+            .left('-', 1)
+            .left('*', 2)
+            .left('/', 2)
+            .right('^', 3);
+
+        assert_eq!(grammar.parse("(n+n+(n*n)-n/n)").len(), 14);
+        let trees = grammar.parse_disambiguated("(n+n+(n*n)-n/n)", &precedence);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(
+            to_ascii(&trees[0].children[1]),
+            to_ascii(&grammar.parse_disambiguated("n+n+(n*n)-n/n", &precedence)[0])
+        );
+
+        // The sum on the left is added first.
+        let trees = grammar.parse_disambiguated("n+n+n", &precedence);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0].children[0]), "n+n");
+
+        let trees = grammar.parse_disambiguated("n+n*n", &precedence);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0].children[2]), "n*n");
+
+        let trees = grammar.parse_disambiguated("n^n^n", &precedence);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0].children[2]), "n^n");
+
+        // Without a level, an operator keeps all its trees.
+        let trees = grammar.parse_disambiguated("n^n^n", &Precedence::new());
+        assert_eq!(trees.len(), 2);
+    }
+
+    #[test]
+    fn test_debug_tree() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> n
+        EXP -> ( S EXP )
+        S -> ",
+        )
+        .unwrap();
+        let trees = grammar.parse("n+(n)");
+        assert_eq!(
+            format!("{:?}", trees[0]),
+            "EXP[EXP[n] + EXP[( S[\u{03BB}] EXP[n] )]]"
+        );
+
+        // The trees can be compared with `assert_eq`.
+        assert_eq!(grammar.parse("n+n"), grammar.parse("n+n"));
+    }
+
+    #[test]
+    fn test_parse_associative() {
+        let grammar = Grammar::from_rules(
+            "E
+        E -> E + E
+        E -> E * E
+        E -> n",
+        )
+        .unwrap();
+        let left = grammar
+            .parse_associative("n+n+n", Associativity::Left)
+            .unwrap();
+        assert_eq!(evaluate_parse_tree(&left.children[0]), "n+n");
+        assert_eq!(evaluate_parse_tree(&left.children[2]), "n");
+
+        let right = grammar
+            .parse_associative("n+n+n", Associativity::Right)
+            .unwrap();
+        assert_eq!(evaluate_parse_tree(&right.children[0]), "n");
+        assert_eq!(evaluate_parse_tree(&right.children[2]), "n+n");
+
+        // All the operators have the same level.
+        let left = grammar
+            .parse_associative("n+n*n", Associativity::Left)
+            .unwrap();
+        assert_eq!(evaluate_parse_tree(&left.children[0]), "n+n");
+
+        assert!(grammar
+            .parse_associative("n+", Associativity::Left)
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_filtered() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> n",
+        )
+        .unwrap();
+        let all = grammar.parse("n+n+n+n");
+        assert_eq!(all.len(), 5);
+
+        // Only the balanced tree has height 4.
+        let trees = grammar.parse_filtered("n+n+n+n", |tree| tree.height() <= 4);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0].children[0]), "n+n");
+
+        let trees = grammar.parse_filtered("n+n+n+n", |tree| tree.height() <= 5);
+        assert_eq!(trees, all);
+        assert!(grammar
+            .parse_filtered("n+n+n+n", |tree| tree.height() <= 3)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_chart_to_json() {
+        let grammar = Grammar::from_rules(
+            r"S
+        S -> A \s
+        A -> a",
+        )
+        .unwrap();
+        assert_eq!(
+            grammar.chart_to_json("a "),
+            concat!(
+                r#"[[{"from":"S","to":["A"," "],"dot":0,"origin":0,"finished":false},"#,
+                r#"{"from":"A","to":["a"],"dot":0,"origin":0,"finished":false}],"#,
+                r#"[{"from":"S","to":["A"," "],"dot":1,"origin":0,"finished":false},"#,
+                r#"{"from":"A","to":["a"],"dot":1,"origin":0,"finished":true}],"#,
+                r#"[{"from":"S","to":["A"," "],"dot":2,"origin":0,"finished":true}]]"#
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_timed() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let word = "n+n*(n+n)*n+n+n*n";
+
+        let (trees, timings) = grammar.parse_timed(word);
+        assert!(trees == grammar.parse(word));
+
+        // The phases are disjoint parts of the parse, whatever their durations.
+        let phases = timings.prediction + timings.scan + timings.completion + timings.trees;
+        assert!(phases <= timings.total);
+
+        // The clock is not read when the table is not timed.
+        assert_eq!(grammar.build_table(word).timings, PhaseTimings::default());
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> n",
+        )
+        .unwrap();
+        let (trees, stats) = grammar.parse_stats("n+n+n");
+        assert_eq!(trees, grammar.parse("n+n+n"));
+
+        let (_, chart) = grammar.parse_chart("n+n+n");
+        let sizes: Vec<usize> = chart.sets().iter().map(|set| set.len()).collect();
+        assert_eq!(stats.items, sizes.iter().sum::<usize>());
+        assert_eq!(stats.max_set_size, *sizes.iter().max().unwrap());
+
+        // Every state but the two starting ones was added by one of the operations,
+        // and each state is processed once.
+        assert!(stats.predictions > 0 && stats.scans > 0 && stats.completions > 0);
+        assert!(stats.items - 2 <= stats.predictions + stats.scans + stats.completions);
+        assert!(stats.scans >= 3);
+        assert_eq!(stats.iterations, stats.items);
+        // So the rules of EXP are predicted once for each state waiting for EXP,
+        // which is EXP -> EXP + EXP before either EXP.
+        let waiting = chart
+            .sets()
+            .iter()
+            .flatten()
+            .filter(|item| item.rule == 0 && item.dot != 1 && !item.finished)
+            .count();
+        assert_eq!(stats.predictions, 2 * waiting);
+
+        // A chain of nullable nonterminals is advanced over the empty states
+        // without going over the set again.
+        let grammar = Grammar::from_rules(
+            "S
+        S -> A B C D x
+        A -> B
+        B -> C
+        C -> D
+        D ->",
+        )
+        .unwrap();
+        let (trees, stats) = grammar.parse_stats("x");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(stats.iterations, stats.items);
+        // The rule of S waits for each of A, B, C and D, and the rules of A, B
+        // and C for the next one, and each of them predicts a single rule once.
+        assert_eq!(stats.predictions, 4 + 3);
+    }
+
+    #[test]
+    fn test_caching_grammar() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut cache = CachingGrammar::new(&grammar);
+
+        let first = cache.parse("n+n*n");
+        assert_eq!(first.len(), 2);
+        assert_eq!(cache.hits(), 0);
+
+        // The second call returns the same trees, without parsing again.
+        let second = cache.parse("n+n*n");
+        assert_eq!(cache.hits(), 1);
+        assert!(first
+            .iter()
+            .zip(second.iter())
+            .all(|(a, b)| Rc::ptr_eq(a, b)));
+
+        assert!(cache.parse("n+").is_empty());
+        assert!(cache.parse("n+").is_empty());
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(cache.cache_len(), 2);
+
+        cache.clear_cache();
+        assert_eq!(cache.cache_len(), 0);
+        cache.parse("n+n*n");
+        assert_eq!(cache.hits(), 2);
+
+        // With a capacity, the oldest word is forgotten first.
+        let mut cache = CachingGrammar::with_capacity(&grammar, 2);
+        for word in ["n", "n+n", "n*n"] {
+            cache.parse(word);
+        }
+        assert_eq!(cache.cache_len(), 2);
+        cache.parse("n*n");
+        cache.parse("n+n");
+        assert_eq!(cache.hits(), 2);
+        cache.parse("n");
+        assert_eq!(cache.hits(), 2);
+        assert_eq!(cache.cache_len(), 2);
+
+        let mut cache = CachingGrammar::with_capacity(&grammar, 0);
+        assert_eq!(cache.parse("n").len(), 1);
+        assert_eq!(cache.cache_len(), 0);
+    }
+
+    #[test]
+    fn test_parse_best() {
+        let grammar_string = "S
+        S -> A
+        S -> B
+        A -> a X
+        B -> a X
+        X -> b";
+        let mut grammar = Grammar::from_rules(grammar_string).unwrap();
+        grammar.set_weights(&[0.0, -0.5, -1.0, -0.25, 0.0]);
+
+        let (tree, score) = grammar.parse_best("ab").unwrap();
+        assert_eq!(score, -0.75);
+        assert_eq!(evaluate_parse_tree(&tree), "ab");
+        assert_eq!(format!("{}", tree.children[0].token), "B");
+        assert_eq!((tree.start, tree.end), (0, 2));
+
+        assert!(grammar.parse_best("abb").is_none());
+    }
+
+    #[test]
+    fn test_parse_best_many_derivations() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let mut grammar = Grammar::from_rules(grammar_string).unwrap();
+        grammar.set_weights(&[-1.0, 0.0]);
+
+        // Every derivation uses S -> S S exactly 59 times, but there are far too
+        // many of them to enumerate.
+        let word = "a".repeat(60);
+        let (tree, score) = grammar.parse_best(&word).unwrap();
+        assert_eq!(score, -59.0);
+        assert_eq!(evaluate_parse_tree(&tree), word);
+    }
+
+    #[test]
+    #[should_panic(expected = "The weight 0.5 of rule 0 is not a finite log-probability!")]
+    fn test_set_weights_positive() {
+        // With a positive weight, S -> S would improve the score of a forever.
+        let mut grammar = Grammar::from_rules("S\nS -> S\nS -> a").unwrap();
+        grammar.set_weights(&[0.5, -1.0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "The weight NaN of rule 1 is not a finite log-probability!")]
+    fn test_set_weights_nan() {
+        let mut grammar = Grammar::from_rules("S\nS -> S\nS -> a").unwrap();
+        grammar.set_weights(&[-1.0, f64::NAN]);
+    }
+
+    #[test]
+    fn test_palindrome_grammar() {
+        let grammar_string = "S
+        S -> a S a
+        S -> b S b 
+        S ->
+        S -> a
+        S -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("abba");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "abba");
+
+        let trees = grammar.parse("aabab");
+        assert_eq!(trees.len(), 0);
+
+        let trees = grammar.parse("aabaa");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "aabaa");
+    }
+
+    #[test]
+    fn test_parse_chars() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        for word in ["n+n*n", "(n+n)*n+n", "n", "", "n+", "(n"] {
+            let streamed = grammar.parse_chars(word.chars());
+            let parsed = grammar.parse(word);
+            assert_eq!(streamed, parsed);
+        }
+
+        // A generated input, never held in a string.
+        let chars = (0..13).map(|i| if i % 2 == 0 { 'n' } else { '+' });
+        let trees = grammar.parse_chars(chars);
+        assert_eq!(trees.len(), 132);
+
+        // The rest of the input isn't read once the word can't be derived.
+        let chars = "n+)"
+            .chars()
+            .chain(std::iter::from_fn(|| panic!("Read too far!")));
+        assert!(grammar.parse_chars(chars).is_empty());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        let parens = Grammar::from_rules("S\nS -> ( S ) S\nS -> ").unwrap();
+        let palindromes =
+            Grammar::from_rules("S\nS -> a S a\nS -> b S b\nS ->\nS -> a\nS -> b").unwrap();
+        let expressions = Grammar::from_rules("EXP\nEXP -> EXP + EXP\nEXP -> n").unwrap();
+
+        // The table has a single set, which accepts iff the start is nullable.
+        for grammar in [&parens, &palindromes] {
+            assert_eq!(grammar.build_table("").sets.len(), 1);
+            assert!(grammar.recognizes(""));
+            let trees = grammar.parse("");
+            assert_eq!(trees.len(), 1);
+            assert_eq!(evaluate_parse_tree(&trees[0]), "");
+            assert_eq!((trees[0].start, trees[0].end), (0, 0));
+            assert!(trees[0].children.is_empty());
+
+            assert_eq!(grammar.parse_all("").count(), 1);
+            assert_eq!(grammar.parse_cyk("").len(), 1);
+            assert_eq!(grammar.parse_k_shortest("", 3).len(), 1);
+            assert_eq!(grammar.parse_best("").unwrap().1, 0.0);
+            assert_eq!(grammar.parse_prefix("").unwrap().1, 0);
+        }
+
+        assert_eq!(expressions.build_table("").sets.len(), 1);
+        assert!(!expressions.recognizes(""));
+        assert!(expressions.parse("").is_empty());
+        assert_eq!(expressions.parse_all("").count(), 0);
+        assert!(expressions.parse_cyk("").is_empty());
+        assert!(expressions.parse_k_shortest("", 3).is_empty());
+        assert!(expressions.parse_best("").is_none());
+        assert!(expressions.parse_prefix("").is_none());
+    }
+
+    #[test]
+    fn test_parse_earliest_complete() {
+        let grammar_string = "S
+        S -> ( S )
+        S -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let (tree, length) = grammar.parse_earliest_complete("(n)(n)").unwrap();
+        assert_eq!(length, 3);
+        assert_eq!(evaluate_parse_tree(&tree), "(n)");
+        assert_eq!((tree.start, tree.end), (0, 3));
+
+        // The rest of the input is never read, even if it can't be derived.
+        assert_eq!(grammar.parse_earliest_complete("((n))^^").unwrap().1, 5);
+        assert!(grammar.parse_earliest_complete("((n)").is_none());
+        assert!(grammar.parse_earliest_complete("").is_none());
+
+        // The shortest prefix, where parse_prefix finds the longest.
+        let grammar = Grammar::from_rules("S\nS -> S a\nS -> a").unwrap();
+        assert_eq!(grammar.parse_earliest_complete("aaa").unwrap().1, 1);
+        assert_eq!(grammar.parse_prefix("aaa").unwrap().1, 3);
+
+        // A nullable start is finished before any character is read.
+        let grammar = Grammar::from_rules("S\nS -> a S\nS -> ").unwrap();
+        let (tree, length) = grammar.parse_earliest_complete("aa").unwrap();
+        assert_eq!(length, 0);
+        assert!(tree.children.is_empty());
+    }
+
+    #[test]
+    fn test_label_spans() {
+        let grammar = Grammar::from_rules("S\nS -> ( S ) S\nS -> ").unwrap();
+        let spans: Vec<(&str, usize, usize)> = grammar
+            .label_spans("(())()")
+            .into_iter()
+            .map(|(n, start, end)| (n.name(), start, end))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                ("S", 0, 6),
+                ("S", 1, 3),
+                ("S", 2, 2),
+                ("S", 3, 3),
+                ("S", 4, 6),
+                ("S", 5, 5),
+                ("S", 6, 6),
+            ]
+        );
+
+        // The spans of all the derivations are merged.
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let spans: Vec<(usize, usize)> = grammar
+            .label_spans("n+n*n")
+            .into_iter()
+            .map(|(_, start, end)| (start, end))
+            .collect();
+        assert_eq!(spans, vec![(0, 1), (0, 3), (0, 5), (2, 3), (2, 5), (4, 5)]);
+        assert!(grammar.label_spans("n+").is_empty());
+    }
+
+    #[test]
+    fn test_paranthesis_grammar() {
+        let grammar_string = "S
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("(()()((()())))");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "(()()((()())))");
+
+        let trees = grammar.parse("(()(())()((()())))()()");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "(()(())()((()())))()()");
+
+        let trees = grammar.parse("(()(()))((()())))()()");
+        assert_eq!(trees.len(), 0);
+    }
+
+    #[test]
+    fn test_parse_cyk() {
+        let palindromes = Grammar::from_rules(
+            "S
+        S -> a S a
+        S -> b S b
+        S ->
+        S -> a
+        S -> b",
+        )
+        .unwrap();
+        let parentheses = Grammar::from_rules(
+            "S
+        S -> ( S ) S
+        S -> ",
+        )
+        .unwrap();
+        let expressions = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> T
+        T -> n",
+        )
+        .unwrap();
+        // A cyclic grammar, whose cycles are cut.
+        let cyclic = Grammar::from_rules(
+            "S
+        S -> C
+        C -> A
+        C -> B
+        A -> B
+        B -> A
+        A -> a",
+        )
+        .unwrap();
+        let epsilon_cycle = Grammar::from_rules(
+            "S
+        S -> S S
+        S -> a
+        S -> ",
+        )
+        .unwrap();
+
+        let cases = [
+            (&palindromes, vec!["abba", "aabab", "aabaa", "", "a", "ab"]),
+            (
+                &parentheses,
+                vec!["(()()((()())))", "(()(()))((()())))()()", "", "()"],
+            ),
+            (&expressions, vec!["n+n*n", "(n+n+(n*n)+n*n)", "n+", "n"]),
+            (&cyclic, vec!["a", "aa", ""]),
+            (&epsilon_cycle, vec!["", "a", "aa", "aaa"]),
+        ];
+        for (grammar, words) in cases {
+            for word in words {
+                let trees = grammar.parse_cyk(word);
+                assert_eq!(trees, grammar.parse(word));
+                for (tree, expected) in trees.iter().zip(grammar.parse(word)) {
+                    assert_eq!(to_ascii(tree), to_ascii(&expected));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_duplicate_trees() {
+        // The labels make these different rules, but their trees are the same.
+        let grammar_string = "S
+        S -> A A
+        A -> a A @more
+        A -> a A @again
+        A -> b
+        B -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.rule_count(), 5);
+
+        let trees = grammar.parse("bab");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "bab");
+        assert_eq!(grammar.parse_cyk("bab"), trees);
+        assert_eq!(grammar.parse_filtered("aabb", |_| true).len(), 1);
     }
 
     #[test]
@@ -338,7 +2170,7 @@ mod test {
         A -> a 
         B -> A
         B -> b";
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
 
         let trees = grammar.parse("bab");
         assert_eq!(trees.len(), 1);
@@ -353,7 +2185,7 @@ mod test {
         A -> b
         A -> a A
         B -> b";
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
 
         let trees = grammar.parse("bab");
         assert_eq!(trees.len(), 1);
@@ -367,7 +2199,7 @@ mod test {
         C -> D
         D -> E
         E -> ";
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
 
         let trees = grammar.parse("abde");
         assert_eq!(trees.len(), 1);
@@ -375,14 +2207,391 @@ mod test {
     }
 
     #[test]
-    fn test_grammar_many_derivations() {
+    fn test_parse_collapsed() {
         let grammar_string = "S
-        S -> S S 
-        S -> a";
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        S -> a b C d e
+        C -> D
+        D -> E
+        E -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let trees = grammar.parse("abde");
+        let collapsed = grammar.parse_collapsed("abde");
+        assert_eq!(collapsed.len(), 1);
+        // C -> D -> E is a single node.
+        assert_eq!(trees[0].node_count(), 8);
+        assert_eq!(collapsed[0].node_count(), 6);
+        assert_eq!(format!("{:?}", collapsed[0]), "S[a b C[\u{03BB}] d e]");
+        assert!(collapsed[0].children[2].children.is_empty());
+        assert!(collapsed[0].children[2].rule.unwrap().is_empty());
+
+        // NP -> PN -> she becomes NP[she].
+        let grammar = Grammar::from_rules(NLP_GRAMMAR).unwrap();
+        let input = "sheeatsafish";
+        let trees = grammar.parse(input);
+        let collapsed = collapse_unit_chains(&trees[0]);
+        assert_eq!(trees[0].node_count() - collapsed.node_count(), 1);
+        assert_eq!(evaluate_parse_tree(&collapsed), input);
+        let subject = &collapsed.children[0];
+        assert_eq!(subject.as_nonterminal(), Some("NP"));
+        assert_eq!(subject.span_text(input), "she");
+        assert!(subject.children.iter().all(|child| child.is_leaf()));
+
+        // A tree without chains is returned as it is.
+        let grammar = Grammar::from_rules("S\nS -> a S\nS -> a").unwrap();
+        let trees = grammar.parse("aa");
+        assert!(Rc::ptr_eq(&collapse_unit_chains(&trees[0]), &trees[0]));
+    }
 
-        let trees = grammar.parse("aaaaaa");
-        assert_eq!(trees.len(), 42);
+    #[test]
+    fn test_parse_pruned() {
+        let grammar_string = "S
+        S -> a b C d e
+        C -> D
+        D -> E
+        E -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("abde");
+        let pruned = grammar.parse_pruned("abde");
+        assert_eq!(pruned.len(), 1);
+        // C, D and E are removed.
+        assert_eq!(trees[0].node_count(), 8);
+        assert_eq!(pruned[0].node_count(), 5);
+        assert_eq!(format!("{:?}", pruned[0]), "S[a b d e]");
+        assert_eq!(evaluate_parse_tree(&pruned[0]), "abde");
+        assert!(pruned[0].rule == trees[0].rule);
+        assert!(grammar.parse_pruned("abd").is_empty());
+
+        // The trees that only differ by their empty parts are merged, and the
+        // root is kept for the empty word.
+        let grammar_string = "S
+        S -> A a
+        S -> B a
+        A -> 
+        B -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.parse("a").len(), 2);
+        let pruned = grammar.parse_pruned("a");
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(format!("{:?}", pruned[0]), "S[a]");
+
+        let grammar = Grammar::from_rules("S\nS -> A A\nA -> ").unwrap();
+        let pruned = grammar.parse_pruned("");
+        assert_eq!(pruned.len(), 1);
+        assert_eq!(pruned[0].node_count(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "A derivation of the state S -> a b has 1 children instead of 2!")]
+    fn test_malformed_derivation() {
+        let grammar = Grammar::from_rules("S\nS -> a b").unwrap();
+        let rule = &grammar.rules[0];
+
+        // The finished state claims to come from the state before `a`.
+        let start = Rc::new(EarleyState::new(rule, 0, 0));
+        let finished = Rc::new(EarleyState::new(rule, 2, 0));
+        finished
+            .derivations
+            .borrow_mut()
+            .push(Derivation::Scan(start, 'a'));
+        parse_tree::build_parse_trees(&finished, 0, 2);
+    }
+
+    #[test]
+    fn test_cyclic_grammar() {
+        let grammar_string = "S
+        S -> A
+        A -> A
+        A -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.has_epsilon_cycle());
+
+        for _ in 0..5 {
+            let trees = grammar.parse("a");
+            let trees: Vec<String> = trees.iter().map(|tree| format!("{:?}", tree)).collect();
+            // A cannot derive itself over the same symbols, so the cycle is cut at once.
+            assert_eq!(trees, vec!["S[A[a]]"]);
+        }
+        assert!(grammar.parse("aa").is_empty());
+
+        // The cycle goes through two nonterminals, both reached from C.
+        let grammar_string = "S
+        S -> C
+        C -> A
+        C -> B
+        A -> B
+        B -> A
+        A -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        for _ in 0..5 {
+            let trees = grammar.parse("a");
+            let trees: Vec<String> = trees.iter().map(|tree| format!("{:?}", tree)).collect();
+            assert_eq!(trees, vec!["S[C[A[a]]]", "S[C[B[A[a]]]]"]);
+        }
+    }
+
+    #[test]
+    fn test_parse_summary() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n",
+        )
+        .unwrap();
+        let plus = grammar.parse_summary("(n+n)");
+        let times = grammar.parse_summary("(n*n)");
+        assert_eq!(plus.derivations, 1);
+        assert!(plus.accepted);
+        assert_eq!(plus.longest_prefix, 5);
+        // Only the trees differ.
+        assert_eq!(
+            (plus.derivations, plus.accepted, plus.longest_prefix),
+            (times.derivations, times.accepted, times.longest_prefix)
+        );
+        assert_ne!(plus.trees_hash, times.trees_hash);
+        assert_ne!(plus, times);
+        assert_eq!(plus, grammar.parse_summary("(n+n)"));
+        // The hash is FNV-1a of the written tree, so it is the same in every run.
+        assert_eq!(plus.trees_hash, 12763377307671412185);
+
+        let ambiguous = grammar.parse_summary("n+n*n");
+        assert_eq!(ambiguous.derivations, 2);
+        // The trees are hashed as a set, whatever the order of the rules.
+        let reordered = Grammar::from_rules(
+            "EXP
+        EXP -> n
+        EXP -> ( EXP )
+        EXP -> EXP * EXP
+        EXP -> EXP + EXP",
+        )
+        .unwrap();
+        assert_eq!(reordered.parse_summary("n+n*n"), ambiguous);
+        let rejected = grammar.parse_summary("(n+)n");
+        assert_eq!(rejected.derivations, 0);
+        assert!(!rejected.accepted);
+        assert_eq!(rejected.longest_prefix, grammar.longest_prefix("(n+)n"));
+    }
+
+    #[test]
+    fn test_parse_max_ambiguity() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        assert_eq!(
+            grammar.parse_max_ambiguity("aaaaaa", 10).unwrap_err(),
+            TooAmbiguous { count: 42 }
+        );
+        assert_eq!(grammar.parse_max_ambiguity("aaaaaa", 42).unwrap().len(), 42);
+        assert_eq!(
+            grammar.parse_max_ambiguity("aaa", 10).unwrap(),
+            grammar.parse("aaa")
+        );
+        assert!(grammar.parse_max_ambiguity("b", 0).unwrap().is_empty());
+
+        // The trees are never built when there are too many of them.
+        let error = grammar
+            .parse_max_ambiguity(&"a".repeat(20), 1000)
+            .unwrap_err();
+        assert_eq!(error.count, 1_767_263_190);
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let result = grammar.parse_all("aaaaa");
+        assert_eq!(result.count(), 14);
+        assert_eq!(result.trees().count(), result.count());
+        let mut trees: Vec<String> = result.trees().map(|tree| to_ascii(&tree)).collect();
+        let mut sorted: Vec<String> = grammar
+            .parse("aaaaa")
+            .iter()
+            .map(|tree| to_ascii(tree))
+            .collect();
+        trees.sort();
+        sorted.sort();
+        assert_eq!(trees, sorted);
+
+        // The trees are built lazily, so the first ones come without building the others.
+        let first: Vec<_> = grammar.parse_all(&"a".repeat(20)).trees().take(3).collect();
+        assert_eq!(first.len(), 3);
+        assert!(first.iter().all(|tree| tree.end == 20));
+        assert!(first[0] != first[1] && first[1] != first[2]);
+
+        // The counts grow much faster than the trees could be built.
+        assert_eq!(grammar.parse_all(&"a".repeat(20)).count(), 1_767_263_190);
+        assert_eq!(grammar.parse_all("b").count(), 0);
+        assert_eq!(grammar.parse_all("b").trees().count(), 0);
+
+        // The cycles are cut the same way when counting.
+        let grammar_string = "S
+        S -> C
+        C -> A
+        C -> B
+        A -> B
+        B -> A
+        A -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let result = grammar.parse_all("a");
+        assert_eq!(result.count(), 2);
+        assert_eq!(result.trees().count(), 2);
+        let mut trees: Vec<String> = result.trees().map(|tree| to_ascii(&tree)).collect();
+        let mut sorted: Vec<String> = grammar
+            .parse("a")
+            .iter()
+            .map(|tree| to_ascii(tree))
+            .collect();
+        trees.sort();
+        sorted.sort();
+        assert_eq!(trees, sorted);
+    }
+
+    #[test]
+    fn test_parse_smallest() {
+        // All the trees have the same size, so the first one is returned.
+        let grammar = Grammar::from_rules("S\nS -> S S\nS -> a").unwrap();
+        let all = grammar.parse("aaaa");
+        let smallest = grammar.parse_smallest("aaaa").unwrap();
+        assert!(smallest == all[0]);
+        assert_eq!(smallest.node_count(), 11);
+
+        // The chain of unit rules makes the trees through A larger.
+        let grammar_string = "S
+        S -> A
+        S -> S + S
+        S -> B
+        A -> B
+        B -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        for word in ["n", "n+n", "n+n+n"] {
+            let all = grammar.parse(word);
+            let smallest = grammar.parse_smallest(word).unwrap();
+            let min = all.iter().map(|tree| tree.node_count()).min().unwrap();
+            assert_eq!(smallest.node_count(), min);
+            assert!(!format!("{:?}", smallest).contains('A'));
+        }
+        assert_eq!(
+            format!("{:?}", grammar.parse_smallest("n").unwrap()),
+            "S[B[n]]"
+        );
+        assert!(grammar.parse_smallest("n+").is_none());
+    }
+
+    #[test]
+    fn test_parse_best_n() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> EXP - EXP
+        EXP -> EXP / EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let costs = [1.0, 10.0, 1.0, 10.0, 0.0, 0.0];
+
+        let best = grammar.parse_best_n("n+n*n", &costs, 1);
+        assert_eq!(best.len(), 1);
+        assert_eq!(
+            format!("{:?}", best[0].0),
+            "EXP[EXP[n] + EXP[EXP[n] * EXP[n]]]"
+        );
+        assert_eq!(best[0].1, 11.0);
+
+        // Every tree of the word uses the same operators, so they all cost the same.
+        let best = grammar.parse_best_n("n*n/n-n", &costs, 100);
+        assert_eq!(best.len(), grammar.parse("n*n/n-n").len());
+        assert!(best.iter().all(|(_, cost)| *cost == 21.0));
+
+        // A cheaper parenthesized word.
+        let best = grammar.parse_best_n("(n*n)", &costs, 3);
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].1, 10.0);
+
+        assert!(grammar.parse_best_n("n+n", &costs, 0).is_empty());
+        assert!(grammar.parse_best_n("n+", &costs, 3).is_empty());
+
+        // The longer chain of rules is cheaper.
+        let grammar_string = "S
+        S -> A
+        S -> B
+        A -> B
+        B -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let best = grammar.parse_best_n("n", &[1.0, 5.0, 1.0, 0.0], 2);
+        let trees: Vec<String> = best.iter().map(|(tree, _)| format!("{:?}", tree)).collect();
+        assert_eq!(trees, vec!["S[A[B[n]]]", "S[B[n]]"]);
+        assert_eq!(best[0].1, 2.0);
+        assert_eq!(best[1].1, 5.0);
+
+        // The cycle is cut, even when it would lower the cost.
+        let grammar = Grammar::from_rules("S\nS -> A\nA -> A\nA -> a").unwrap();
+        let best = grammar.parse_best_n("a", &[1.0, 1.0, 1.0], 3);
+        assert_eq!(best.len(), 1);
+        assert_eq!(format!("{:?}", best[0].0), "S[A[a]]");
+        assert_eq!(best[0].1, 2.0);
+        let best = grammar.parse_best_n("a", &[1.0, -1.0, 1.0], 3);
+        assert_eq!(best.len(), 1);
+        assert_eq!(best[0].1, 2.0);
+    }
+
+    #[test]
+    fn test_parse_limited() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let all = grammar.parse("aaaaaa");
+        assert_eq!(all.len(), 42);
+
+        let (trees, truncated) = grammar.parse_limited("aaaaaa", 5);
+        assert!(truncated);
+        assert_eq!(trees, all[..5]);
+
+        let (trees, truncated) = grammar.parse_limited("aaaaaa", 42);
+        assert!(!truncated);
+        assert_eq!(trees, all);
+
+        assert_eq!(grammar.parse_limited("aaaaaa", 0), (Vec::new(), true));
+        assert_eq!(grammar.parse_limited("b", 5), (Vec::new(), false));
+
+        // The cycles are cut like in `parse`.
+        let grammar_string = "S
+        S -> C
+        C -> A
+        C -> B
+        A -> B
+        B -> A
+        A -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let all = grammar.parse("a");
+        assert_eq!(all.len(), 2);
+        assert_eq!(grammar.parse_limited("a", 1), (all[..1].to_vec(), true));
+        assert_eq!(grammar.parse_limited("a", 5), (all.clone(), false));
+        assert!(grammar.parse_smallest("a").unwrap() == all[0]);
+
+        let grammar = Grammar::from_rules("S\nS -> S S\nS -> a\nS -> ").unwrap();
+        let all = grammar.parse("aa");
+        let (trees, truncated) = grammar.parse_limited("aa", all.len());
+        assert!(!truncated);
+        assert_eq!(trees, all);
+    }
+
+    #[test]
+    fn test_grammar_many_derivations() {
+        let grammar_string = "S
+        S -> S S 
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("aaaaaa");
+        assert_eq!(trees.len(), 42);
         trees
             .iter()
             .for_each(|root| assert_eq!(evaluate_parse_tree(root), "aaaaaa"));
@@ -395,17 +2604,90 @@ mod test {
     }
 
     #[test]
-    fn test_grammar_empty() {
-        let grammar_string = "S";
+    fn test_states_are_unique() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        // The last set holds S -> S . S for every origin in 0..7, S -> S S . for
+        // every origin in 0..6, S -> a . from origin 6 and the two predicted states.
+        // The 132 derivations are stored as derivations of these states.
+        let table = grammar.build_table("aaaaaaa");
+        assert_eq!(table.sets[7].states.len(), 16);
+        assert_eq!(grammar.parse("aaaaaaa").len(), 132);
+    }
+
+    #[test]
+    fn test_long_input() {
+        let grammar_string = "S
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        // Completion only looks at the states waiting for the completed nonterminal,
+        // instead of every state in the origin set.
+        let word = "(()(()))".repeat(100);
+        let trees = grammar.parse(&word);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), word);
+
+        let trees = grammar.parse(&(word + ")"));
+        assert_eq!(trees.len(), 0);
+    }
+
+    #[test]
+    fn test_grammar_many_rules() {
+        // A word is a sequence of letters, and every letter has its own rule.
+        let mut grammar_string = String::from("S\nS -> W\nS -> W - S\nW -> L W\nW -> L");
+        for letter in 'a'..='z' {
+            grammar_string.push_str(&format!("\nL -> {}", letter));
+        }
         let grammar = Grammar::from_rules(&grammar_string).unwrap();
+        assert_eq!(grammar.rules_for(grammar.start), &[0, 1]);
+        assert_eq!(grammar.rules_for(NonTerminal::new("L")).len(), 26);
 
-        let trees = grammar.parse("aaaaaa");
+        let trees = grammar.parse("the-quick-brown-fox");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "the-quick-brown-fox");
+
+        let trees = grammar.parse("the--fox");
         assert_eq!(trees.len(), 0);
     }
 
     #[test]
-    fn test_grammar_nlp() {
+    fn test_case_insensitive() {
         let grammar_string = "S
+        S -> PN V
+        PN -> s h e
+        PN -> h e
+        V -> e a t s";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        assert_eq!(grammar.parse("SheEats").len(), 0);
+
+        let trees = grammar.parse_case_insensitive("SheEATS");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "sheeats");
+
+        let trees = grammar.parse_case_insensitive("heeats");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "heeats");
+
+        assert_eq!(grammar.parse_case_insensitive("SheEat").len(), 0);
+    }
+
+    #[test]
+    fn test_grammar_empty() {
+        let grammar_string = "S";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("aaaaaa");
+        assert_eq!(trees.len(), 0);
+    }
+
+    // A small english grammar, where the prepositional phrases attach to verb phrases.
+    const NLP_GRAMMAR: &str = "S
         S -> NP VP
         VP -> VP PP 
         VP -> V NP 
@@ -435,9 +2717,101 @@ mod test {
         DET -> t h e 
         P -> w i t h";
 
-        let grammar = Grammar::from_rules(&grammar_string).unwrap();
+    #[test]
+    fn test_accepting_items() {
+        // A single start rule, with an ambiguity inside it.
+        let grammar_string = "S
+        S -> EXP
+        EXP -> EXP + EXP
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.accepting_items("n+n+n"), 1);
+        assert_eq!(grammar.parse_all("n+n+n").count(), 2);
+
+        // Two start rules that both derive the word.
+        let grammar_string = "S
+        S -> S + S
+        S -> A + S
+        S -> n
+        A -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.accepting_items("n+n"), 2);
+        assert_eq!(grammar.accepting_items("n"), 1);
+        assert_eq!(grammar.accepting_items("n+"), 0);
+    }
+
+    #[test]
+    fn test_coverage() {
+        let grammar = Grammar::from_rules(NLP_GRAMMAR).unwrap();
+        let with = grammar.rules.len() - 1;
+        assert_eq!(grammar.rules[with].to_string(), "P -> w i t h ");
+
+        let coverage = grammar.coverage(&["sheeatsafishwithafork", "sheeats", "sheeatsa"]);
+        assert_eq!(
+            coverage.used,
+            vec![0, 1, 2, 3, 4, 5, 7, 15, 20, 21, 23, 24, with]
+        );
+        assert!(coverage.unused.contains(&13));
+        assert_eq!(
+            coverage.used.len() + coverage.unused.len(),
+            grammar.rules.len()
+        );
+
+        // The rules of every derivation are used, not only the ones of the first tree.
+        let grammar_string = "S
+        S -> A
+        S -> B
+        A -> a
+        B -> a
+        C -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let coverage = grammar.coverage(&["a"]);
+        assert_eq!(coverage.used, vec![0, 1, 2, 3]);
+        assert_eq!(coverage.unused, vec![4]);
+
+        let coverage = grammar.coverage(&[]);
+        assert!(coverage.used.is_empty());
+        assert_eq!(coverage.unused.len(), 5);
+    }
+
+    #[test]
+    fn test_find_nodes() {
+        let grammar = Grammar::from_rules(NLP_GRAMMAR).unwrap();
+        let input = "sheeatsafishwithasilverfork";
+        let trees = grammar.parse(input);
+        assert_eq!(trees.len(), 1);
+
+        let phrases: Vec<&str> = trees[0]
+            .find_all("NP")
+            .iter()
+            .map(|node| node.span_text(input))
+            .collect();
+        assert_eq!(phrases, vec!["she", "afish", "asilverfork"]);
+
+        let first = trees[0].find_first("NP").unwrap();
+        assert!(std::ptr::eq(first, trees[0].find_all("NP")[0]));
+        assert_eq!(
+            trees[0].find_first("PP").unwrap().span_text(input),
+            "withasilverfork"
+        );
+        // The root is found too, and terminals are never found.
+        assert!(std::ptr::eq(trees[0].find_first("S").unwrap(), &*trees[0]));
+        assert!(trees[0].find_all("a").is_empty());
+        assert!(trees[0].find_first("ADV").is_none());
+
+        // A nonterminal that derives the empty word has a node without children.
+        let grammar = Grammar::from_rules("S\nS -> ( S ) S\nS -> ").unwrap();
+        let trees = grammar.parse("()");
+        let nodes = trees[0].find_all("S");
+        assert_eq!(nodes.len(), 3);
+        assert!(nodes[1..].iter().all(|node| node.children.is_empty()));
+    }
 
-        let sentences = vec![
+    #[test]
+    fn test_grammar_nlp() {
+        let grammar = Grammar::from_rules(NLP_GRAMMAR).unwrap();
+
+        let sentences = [
             "sheeats",
             "sheeatsanapple",
             "sheeatsfreshtastyapple",
@@ -447,7 +2821,7 @@ mod test {
             "sheeatsaquitefreshfishwithasilverfork",
         ];
 
-        let num_trees = vec![1, 1, 2, 1, 1, 1, 1];
+        let num_trees = [1, 1, 2, 1, 1, 1, 1];
 
         for (&sentence, &num_trees) in sentences.iter().zip(num_trees.iter()) {
             let trees = grammar.parse(sentence);
@@ -457,4 +2831,1246 @@ mod test {
                 .for_each(|tree| assert_eq!(evaluate_parse_tree(tree), sentence));
         }
     }
+
+    #[test]
+    fn test_parse_prefix() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let (tree, length) = grammar.parse_prefix("(n)extra").unwrap();
+        assert_eq!(length, 3);
+        assert_eq!(tree, grammar.parse("(n)")[0]);
+
+        let (tree, length) = grammar.parse_prefix("n+n+").unwrap();
+        assert_eq!(length, 3);
+        assert_eq!(evaluate_parse_tree(&tree), "n+n");
+
+        let (_, length) = grammar.parse_prefix("n+(n)").unwrap();
+        assert_eq!(length, 5);
+
+        assert!(grammar.parse_prefix("+n").is_none());
+        assert!(grammar.parse_prefix("").is_none());
+
+        // The empty prefix is derived from a nullable start.
+        let grammar = Grammar::from_rules("S\nS -> a S\nS -> ").unwrap();
+        let (tree, length) = grammar.parse_prefix("bab").unwrap();
+        assert_eq!(length, 0);
+        assert!(tree.children.is_empty());
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let grammar_string = "TOKEN
+        TOKEN -> NUMBER
+        TOKEN -> WORD
+        TOKEN -> \\s
+        TOKEN -> +
+        NUMBER -> \\d NUMBER
+        NUMBER -> \\d
+        WORD -> [a-z] WORD
+        WORD -> [a-z]";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let lexemes = grammar.tokenize("abc 12+x7", "TOKEN").unwrap();
+        assert_eq!(lexemes, vec!["abc", " ", "12", "+", "x", "7"]);
+        assert_eq!(grammar.tokenize("42", "NUMBER").unwrap(), vec!["42"]);
+        assert_eq!(grammar.tokenize("", "TOKEN").unwrap(), Vec::<String>::new());
+
+        assert!(grammar.tokenize("ab-c", "TOKEN").is_none());
+        assert!(grammar.tokenize("ab1", "WORD").is_none());
+        assert!(grammar.tokenize("ab", "LEXEME").is_none());
+
+        // The lexemes are the longest ones, even if that leaves the rest uncovered.
+        let grammar = Grammar::from_rules("L\nL -> a b\nL -> a b c\nL -> c d").unwrap();
+        assert!(grammar.tokenize("abcd", "L").is_none());
+        assert_eq!(grammar.tokenize("abcab", "L").unwrap(), vec!["abc", "ab"]);
+    }
+
+    #[test]
+    fn test_matched_substrings() {
+        let grammar = Grammar::from_rules(NLP_GRAMMAR).unwrap();
+        let sentence = "sheeatsafishwithafork";
+
+        let strings = |matched: HashSet<String>| {
+            let mut matched: Vec<String> = matched.into_iter().collect();
+            matched.sort();
+            matched
+        };
+        assert_eq!(
+            strings(grammar.matched_substrings(sentence, "VP")),
+            vec!["eatsafish", "eatsafishwithafork"]
+        );
+        assert_eq!(
+            strings(grammar.matched_substrings(sentence, "NP")),
+            vec!["afish", "afork", "she"]
+        );
+        assert_eq!(
+            strings(grammar.matched_substrings(sentence, "PP")),
+            vec!["withafork"]
+        );
+        assert!(grammar.matched_substrings(sentence, "ADV").is_empty());
+        assert!(grammar.matched_substrings("sheeatsa", "NP").is_empty());
+    }
+
+    #[test]
+    fn test_to_ascii() {
+        let grammar_string = "S
+        S -> A b
+        A -> a A
+        A -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("ab");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(
+            to_ascii(&trees[0]),
+            "S\n  A\n    'a'\n    A\n      \u{03BB}\n  'b'\n"
+        );
+    }
+
+    #[test]
+    fn test_to_tree_string() {
+        let grammar_string = "S
+        S -> A b
+        A -> a A
+        A -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("ab");
+        assert_eq!(
+            to_tree_string(&trees[0], TreeStyle::Ascii),
+            "S\n|-- A\n|   |-- 'a'\n|   `-- A\n|       `-- \u{03B5}\n`-- 'b'\n"
+        );
+        let unicode = to_tree_string(&trees[0], TreeStyle::default());
+        assert!(unicode.contains("\u{251C}\u{2500}\u{2500} A\n"));
+        assert!(unicode.contains("\u{2502}   \u{2514}\u{2500}\u{2500} A\n"));
+        assert!(unicode.ends_with("\u{2514}\u{2500}\u{2500} 'b'\n"));
+        assert_eq!(unicode.lines().count(), 6);
+    }
+
+    #[test]
+    fn test_parse_bounded() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let word = "a".repeat(12);
+        let trees = grammar.parse_bounded(&word, 10000).unwrap();
+        assert_eq!(trees.len(), grammar.parse(&word).len());
+
+        let error = grammar.parse_bounded(&word, 20).err().unwrap();
+        assert_eq!(error.max_states, 20);
+        assert!(error.position < word.len());
+
+        let table = grammar.build_table(&word);
+        assert!(grammar.parse_bounded(&word, table.state_count).is_ok());
+        assert!(grammar.parse_bounded(&word, table.state_count - 1).is_err());
+    }
+
+    #[test]
+    fn test_parse_order() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let first = grammar.parse("aaaaa");
+        let second = grammar.parse("aaaaa");
+        assert_eq!(first.len(), 14);
+
+        let first: Vec<String> = first.iter().map(|tree| to_ascii(tree)).collect();
+        let second: Vec<String> = second.iter().map(|tree| to_ascii(tree)).collect();
+        assert_eq!(first, second);
+
+        // The first tree always uses the rule S -> S S as soon as possible.
+        let trees = grammar.parse("aaa");
+        assert_eq!(grammar.leftmost_derivation(&trees[0]), vec![0, 0, 1, 1, 1]);
+
+        // Declaring the multiplication first puts the trees that start with it first.
+        let plus_first = Grammar::from_rules("E\nE -> E + E\nE -> E * E\nE -> n").unwrap();
+        let times_first = Grammar::from_rules("E\nE -> E * E\nE -> E + E\nE -> n").unwrap();
+        let order = |grammar: &Grammar| -> Vec<String> {
+            let trees = grammar.parse("n+n*n+n");
+            let derivations: Vec<Vec<usize>> = trees
+                .iter()
+                .map(|tree| grammar.leftmost_derivation(tree))
+                .collect();
+            assert!(derivations.windows(2).all(|pair| pair[0] < pair[1]));
+            trees.iter().map(|tree| format!("{:?}", tree)).collect()
+        };
+        let (plus_order, times_order) = (order(&plus_first), order(&times_first));
+        assert_eq!(plus_order.len(), 5);
+        assert_eq!(times_order[0], "E[E[E[n] + E[n]] * E[E[n] + E[n]]]");
+        assert_eq!(plus_order[0], "E[E[E[n] + E[E[n] * E[n]]] + E[n]]");
+        assert_ne!(plus_order, times_order);
+
+        // The same trees are returned, only in another order.
+        let (mut plus_sorted, mut times_sorted) = (plus_order.clone(), times_order.clone());
+        plus_sorted.sort();
+        times_sorted.sort();
+        assert_eq!(plus_sorted, times_sorted);
+    }
+
+    #[test]
+    fn test_table_display() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let first = grammar.build_table("n+n*n").to_string();
+        let second = grammar.build_table("n+n*n").to_string();
+        assert_eq!(first, second);
+
+        let expected = "S0
+Rule: EXP -> .EXP*EXP  Origin: 0, Dot: 0
+Rule: EXP -> .EXP+EXP  Origin: 0, Dot: 0
+Rule: EXP -> .n  Origin: 0, Dot: 0
+S1
+";
+        assert!(first.starts_with(expected));
+    }
+
+    #[test]
+    fn test_chart_item_to_string() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let (_, chart) = grammar.parse_chart("n+n");
+        let set = chart.set(1);
+        let index = set
+            .iter()
+            .position(|item| item.rule == 0 && item.dot == 1)
+            .unwrap();
+        assert_eq!(
+            chart.item_to_string(1, index).unwrap(),
+            "Rule: EXP -> EXP.+EXP  Origin: 0, Dot: 1"
+        );
+        assert_eq!(
+            chart.item_to_string(0, 0).unwrap(),
+            "Rule: EXP -> .EXP+EXP  Origin: 0, Dot: 0"
+        );
+        assert_eq!(
+            chart.item_to_string(3, chart.set(3).len() - 1).unwrap(),
+            "Rule: EXP -> n.  Origin: 2, Dot: 1"
+        );
+
+        assert!(chart.item_to_string(1, set.len()).is_none());
+        assert!(chart.item_to_string(4, 0).is_none());
+    }
+
+    #[test]
+    fn test_chart_format_range() {
+        let grammar_string = "S
+        S -> a S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let (_, chart) = grammar.parse_chart("aa");
+        let expected = "Sets 1..3 of 3
+S1
+Rule: S -> .aS  Origin: 1, Dot: 0
+Rule: S -> a.S  Origin: 0, Dot: 1
+Rule: S -> aS.  Origin: 0, Dot: 2
+Rule: S -> .  Origin: 1, Dot: 0
+S2
+Rule: S -> .aS  Origin: 2, Dot: 0
+Rule: S -> a.S  Origin: 1, Dot: 1
+Rule: S -> aS.  Origin: 0, Dot: 2
+Rule: S -> aS.  Origin: 1, Dot: 2
+Rule: S -> .  Origin: 2, Dot: 0
+";
+        assert_eq!(chart.format_range(1, 3), expected);
+
+        // The range stops at the last set.
+        assert_eq!(chart.format_range(1, 10), expected);
+        assert_eq!(chart.format_range(3, 5), "Sets 3..3 of 3\n");
+    }
+
+    #[test]
+    fn test_dot_style() {
+        let grammar_string = "S
+        S -> A b
+        A -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let trees = grammar.parse("b");
+
+        let mut plain = String::new();
+        write_tree_to_dot(&mut plain, &trees[0]).unwrap();
+        assert_eq!(
+            plain,
+            "digraph G{\n0 [label=\"\u{03BB}\"]\n1 [label=\"A\"]\n1 -> 0\n2 [label=\"b\"]\n3 [label=\"S\"]\n3 -> 1\n3 -> 2\n}"
+        );
+
+        let mut styled = String::new();
+        write_tree_to_dot_styled(&mut styled, &trees[0], &DotStyle::default()).unwrap();
+        assert!(styled.contains("2 [label=\"b\", shape=box, color=\"black\", fontcolor=\"black\"]"));
+        assert!(styled.contains("1 [label=\"A\", shape=ellipse"));
+        assert!(styled.contains("0 [label=\"\u{03BB}\", shape=plaintext, color=\"gray\""));
+        assert!(styled.ends_with("3 -> 1\n3 -> 2\n}"));
+    }
+
+    #[test]
+    fn test_dot_without_binary() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let trees = grammar.parse("n+n*n");
+
+        for tree in &trees {
+            let mut dot = String::new();
+            write_tree_to_dot(&mut dot, tree).unwrap();
+            assert!(dot.starts_with("digraph G{\n"));
+            assert!(dot.ends_with("}"));
+
+            // Every edge connects two declared nodes, and ids are unique.
+            let mut ids = HashSet::new();
+            for line in dot.lines().skip(1).filter(|line| *line != "}") {
+                if let Some((from, to)) = line.split_once(" -> ") {
+                    assert!(ids.contains(from) && ids.contains(to));
+                } else {
+                    let (id, label) = line.split_once(' ').unwrap();
+                    assert!(label.starts_with("[label=\"") && label.ends_with("\"]"));
+                    assert!(ids.insert(id.to_string()));
+                }
+            }
+            assert_eq!(ids.len(), 10);
+        }
+
+        // The name is unique to this test and process, so that parallel runs
+        // don't write the same file.
+        let path = std::env::temp_dir().join(format!(
+            "earley_parser_{}_test_dot_without_binary.dot",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        write_tree_to_dot_file(&trees[0], path).unwrap();
+        let written = std::fs::read_to_string(path);
+        std::fs::remove_file(path).unwrap();
+
+        let mut dot = String::new();
+        write_tree_to_dot(&mut dot, &trees[0]).unwrap();
+        assert_eq!(written.unwrap(), dot);
+    }
+
+    #[test]
+    fn test_forest_to_dot() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let trees = grammar.parse("n+n*n+n");
+        assert_eq!(trees.len(), 5);
+
+        let mut dot = String::new();
+        write_forest_to_dot(&mut dot, &trees).unwrap();
+        assert!(dot.starts_with("digraph G{\nsubgraph cluster_0 {\nlabel=\"Tree 0\"\n"));
+        assert_eq!(dot.matches("subgraph cluster_").count(), 5);
+        assert!(dot.contains("subgraph cluster_4 {"));
+
+        // Every tree has 14 nodes, and every node is declared once.
+        let declared: Vec<&str> = dot
+            .lines()
+            .filter(|line| line.contains("[label="))
+            .map(|line| line.split(' ').next().unwrap())
+            .collect();
+        let unique: HashSet<&str> = declared.iter().copied().collect();
+        assert_eq!(declared.len(), 70);
+        assert_eq!(unique.len(), 70);
+    }
+
+    #[test]
+    fn test_forest_dag_to_dot() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let trees = grammar.parse("aaaa");
+        assert_eq!(trees.len(), 5);
+
+        let count_nodes = |dot: &str| dot.lines().filter(|line| line.contains("[label=")).count();
+
+        let mut forest = String::new();
+        write_forest_to_dot(&mut forest, &trees).unwrap();
+        let mut dag = String::new();
+        write_forest_dag_to_dot(&mut dag, &trees).unwrap();
+
+        // Each tree has 4 terminals, 4 nodes for S -> a and 3 nodes for S -> S S.
+        assert_eq!(count_nodes(&forest), 55);
+        // The DAG has one node for a, one for S -> a, and one for each way
+        // of building 2, 3 and 4 a's with S -> S S: 1 + 2 + 5.
+        assert_eq!(count_nodes(&dag), 10);
+        assert!(dag.starts_with("digraph G{\n0 [label=\"a\"]\n1 [label=\"S\"]\n1 -> 0\n"));
+    }
+
+    #[test]
+    fn test_forest_to_text() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> n",
+        )
+        .unwrap();
+        let text = forest_to_text(&grammar.parse("n+n*n"));
+        let lines: Vec<&str> = text.lines().collect();
+        // The two trees share the root, which has two alternatives.
+        assert_eq!(lines[0], "#0 EXP => (#1 + #2) | (#5 * #4)");
+        assert_eq!(lines.iter().filter(|line| line.contains(" | ")).count(), 1);
+        // EXP over each n is written once: n, n+n, n*n, and the three n's.
+        assert_eq!(lines.len(), 1 + 2 + 3);
+        assert!(lines.contains(&"#1 EXP => (n)"));
+
+        let grammar = Grammar::from_rules("S\nS -> a S\nS ->").unwrap();
+        assert_eq!(
+            forest_to_text(&grammar.parse("a")),
+            "#0 S => (a #1)\n#1 S => ()\n"
+        );
+        assert_eq!(forest_to_text(&grammar.parse("b")), "");
+    }
+
+    #[test]
+    fn test_dag_parallel_edges() {
+        let grammar = Grammar::from_rules("S\nS -> S S\nS -> a").unwrap();
+        let trees = grammar.parse("aaaa");
+        let dag = |edges| {
+            let mut dot = String::new();
+            write_forest_dag_to_dot_with(&mut dot, &trees, edges).unwrap();
+            dot
+        };
+        let edges = |dot: &str| -> Vec<String> {
+            dot.lines()
+                .filter(|line| line.contains(" -> "))
+                .map(String::from)
+                .collect()
+        };
+
+        // S[S[a] S[a]] has two edges to the same child.
+        let kept = dag(ParallelEdges::Keep);
+        let mut distinct = edges(&kept);
+        distinct.sort();
+        distinct.dedup();
+        assert!(distinct.len() < edges(&kept).len());
+        let mut plain = String::new();
+        write_forest_dag_to_dot(&mut plain, &trees).unwrap();
+        assert_eq!(plain, kept);
+
+        let merged = dag(ParallelEdges::Merge);
+        // No two edge lines are the same.
+        let mut merged_edges = edges(&merged);
+        merged_edges.sort();
+        assert_eq!(merged_edges, distinct);
+        assert!(merged.contains("2 -> 1\n"));
+
+        let counted = dag(ParallelEdges::Count);
+        assert!(counted.contains("2 -> 1 [label=\"2\"]\n"));
+        assert_eq!(edges(&counted).len(), distinct.len());
+        for dot in [kept, merged, counted] {
+            assert!(dot.starts_with("digraph G{\n") && dot.ends_with("}"));
+        }
+    }
+
+    #[test]
+    fn test_tree_metrics() {
+        let grammar_string = "S
+        S -> a S a
+        S -> b S b
+        S -> a
+        S -> b
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("abbba");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].height(), 4);
+        assert_eq!(trees[0].node_count(), 8);
+        assert_eq!(trees[0].leaf_count(), 5);
+
+        // The lambda under the innermost S is not counted.
+        let trees = grammar.parse("abba");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(trees[0].height(), 3);
+        assert_eq!(trees[0].node_count(), 7);
+        assert_eq!(trees[0].leaf_count(), 4);
+
+        let trees = grammar.parse("");
+        assert_eq!(trees[0].height(), 1);
+        assert_eq!(trees[0].node_count(), 1);
+        assert_eq!(trees[0].leaf_count(), 0);
+    }
+
+    #[test]
+    fn test_tree_equality() {
+        let grammar_string = "S
+        S -> A
+        S -> B
+        A -> a
+        B -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let trees = grammar.parse("a");
+        assert_eq!(trees.len(), 2);
+        assert!(trees[0] != trees[1]);
+        assert!(trees[0] == grammar.parse("a")[0]);
+
+        // Parsing the same word twice gives equal trees.
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut trees = grammar.parse("aaa");
+        trees.extend(grammar.parse("aaa"));
+        assert_eq!(trees.len(), 4);
+        let unique: HashSet<_> = trees.into_iter().collect();
+        assert_eq!(unique.len(), 2);
+
+        // Comparing deep trees does not overflow the stack.
+        let deep_tree = |depth: usize, leaf: char| {
+            let mut node = Rc::new(ParseNode {
+                token: Token::T(Terminal::Symbol(leaf)),
+                children: Vec::new(),
+                start: 0,
+                end: 1,
+                rule: None,
+            });
+            for _ in 0..depth {
+                node = Rc::new(ParseNode {
+                    token: Token::NT(NonTerminal::new("S")),
+                    children: vec![node],
+                    start: 0,
+                    end: 1,
+                    rule: None,
+                });
+            }
+            node
+        };
+        assert!(deep_tree(1000, 'a') == deep_tree(1000, 'a'));
+        assert!(deep_tree(1000, 'a') != deep_tree(1000, 'b'));
+        assert!(deep_tree(1000, 'a') != deep_tree(999, 'a'));
+    }
+
+    #[test]
+    fn test_parse_k_shortest() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        // All the trees have the same size, so the first ones are returned.
+        let trees = grammar.parse_k_shortest("aaaaa", 3);
+        let all = grammar.parse("aaaaa");
+        assert_eq!(trees.len(), 3);
+        for (tree, expected) in trees.iter().zip(all.iter()) {
+            assert_eq!(evaluate_parse_tree(tree), "aaaaa");
+            assert_eq!(tree.node_count(), 14);
+            assert!(tree == expected);
+        }
+        assert_eq!(grammar.parse_k_shortest("aaaaa", 100).len(), 14);
+
+        // A chain of unit rules makes some trees larger.
+        let grammar_string = "S
+        S -> A
+        S -> B
+        S -> S + S
+        A -> B
+        B -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut all = grammar.parse("n+n");
+        let trees = grammar.parse_k_shortest("n+n", 3);
+        assert_eq!(all.len(), 4);
+        assert_eq!(trees.len(), 3);
+
+        all.sort_by_key(|tree| tree.node_count());
+        for (tree, expected) in trees.iter().zip(all.iter()) {
+            assert_eq!(tree.node_count(), expected.node_count());
+        }
+        assert_eq!(trees[0].node_count(), 8);
+        assert_eq!(trees[2].node_count(), 9);
+
+        // The cycles are cut like in `parse`.
+        let grammar = Grammar::from_rules("S\nS -> A\nA -> A\nA -> a").unwrap();
+        let trees = grammar.parse_k_shortest("a", 5);
+        assert_eq!(trees.len(), 1);
+        assert_eq!(format!("{:?}", trees[0]), "S[A[a]]");
+
+        let grammar = Grammar::from_rules("S\nS -> S S\nS -> a\nS -> ").unwrap();
+        for word in ["", "a", "aa"] {
+            let all = grammar.parse(word);
+            let trees = grammar.parse_k_shortest(word, 1000);
+            assert!(!trees.is_empty());
+            assert_eq!(trees.len(), all.len());
+            assert_eq!(
+                trees[0].node_count(),
+                all.iter().map(|tree| tree.node_count()).min().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_recognizes() {
+        let grammar_string = "S
+        S -> S S
+        S -> a";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.recognizes("a"));
+        assert!(grammar.recognizes(&"a".repeat(40)));
+        assert!(!grammar.recognizes(""));
+        assert!(!grammar.recognizes("aab"));
+    }
+
+    #[test]
+    fn test_parse_best_effort() {
+        let grammar = Grammar::from_rules(
+            "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> n",
+        )
+        .unwrap();
+        let word = |tree: &ParseNode| {
+            tree.fold(&|token, children: Vec<String>| match token {
+                Token::T(_) => token.to_string(),
+                Token::NT(_) => children.concat(),
+            })
+        };
+
+        let input = "(n)+n-(n)";
+        match grammar.parse_best_effort(input) {
+            BestEffort::Partial { trees, failed_at } => {
+                assert_eq!(trees.len(), 1);
+                assert_eq!(word(&trees[0]), "(n)+n");
+                assert!(input.starts_with(&word(&trees[0])));
+                assert_eq!(failed_at, 5);
+                assert_eq!(failed_at, grammar.longest_prefix(input));
+            }
+            BestEffort::Complete(_) => panic!("The word is rejected!"),
+        }
+
+        // No prefix of this word is derived, since its first parenthesis
+        // is never closed, so the trees are partial.
+        let input = "((n)+n-)";
+        match grammar.parse_best_effort(input) {
+            BestEffort::Partial { trees, failed_at } => {
+                assert_eq!(failed_at, 6);
+                assert_eq!(trees.len(), 3);
+                for tree in trees.iter() {
+                    assert_eq!(word(tree), "((n)+n");
+                    assert!(input.starts_with(&word(tree)));
+                    assert_eq!((tree.start, tree.end), (0, failed_at));
+                }
+                // The rule `EXP -> ( EXP )` stops before its closing parenthesis.
+                assert!(trees.iter().any(|tree| {
+                    tree.children.len() == 2 && word(&tree.children[1]) == "(n)+n"
+                }));
+            }
+            BestEffort::Complete(_) => panic!("The word is rejected!"),
+        }
+
+        // Nothing was matched.
+        match grammar.parse_best_effort(")") {
+            BestEffort::Partial { trees, failed_at } => {
+                assert!(trees.is_empty());
+                assert_eq!(failed_at, 0);
+            }
+            BestEffort::Complete(_) => panic!("The word is rejected!"),
+        }
+
+        match grammar.parse_best_effort("n+(n)") {
+            BestEffort::Complete(trees) => assert_eq!(trees, grammar.parse("n+(n)")),
+            BestEffort::Partial { .. } => panic!("The word is accepted!"),
+        };
+    }
+
+    #[test]
+    fn test_scan_error() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP - EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let error = grammar.scan_error("((n)+n-)").unwrap();
+        assert_eq!(
+            error,
+            ScanError {
+                position: 7,
+                found: ')',
+                expected: vec!['(', 'n'],
+                expected_predicates: Vec::new(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Unexpected ')' at position 7, expected one of: '(', 'n'"
+        );
+
+        let error = grammar.scan_error("n+n)").unwrap();
+        assert_eq!(error.position, 3);
+        assert_eq!(error.expected, vec!['+', '-']);
+
+        let error = grammar.scan_error("x").unwrap();
+        assert_eq!(error.position, 0);
+        assert_eq!(error.expected, vec!['(', 'n']);
+
+        assert!(grammar.scan_error("(n+n)").is_none());
+        // An unfinished word has no scan error.
+        assert!(grammar.scan_error("(n+").is_none());
+
+        // The predicates are listed apart from the characters.
+        let config = GrammarConfig::default().terminal_pred("space", char::is_whitespace);
+        let grammar_string = "S
+        S -> a \\p{space}
+        S -> a b";
+        let grammar = Grammar::from_rules_with_config(grammar_string, &config).unwrap();
+        let error = grammar.scan_error("ac").unwrap();
+        assert_eq!(error.expected, vec!['b']);
+        assert_eq!(
+            error
+                .expected_predicates
+                .iter()
+                .map(|p| p.name())
+                .collect::<Vec<_>>(),
+            vec!["space"]
+        );
+        assert_eq!(
+            error.to_string(),
+            "Unexpected 'c' at position 1, expected one of: 'b', \\p{space}"
+        );
+    }
+
+    #[test]
+    fn test_longest_prefix() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP - EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        assert_eq!(grammar.longest_prefix("((n)+n-)"), 7);
+        assert_eq!(grammar.longest_prefix("(n+n)-n"), 7);
+        assert_eq!(grammar.longest_prefix("(n+"), 3);
+        assert_eq!(grammar.longest_prefix("n)"), 1);
+        assert_eq!(grammar.longest_prefix(")"), 0);
+        assert_eq!(grammar.longest_prefix(""), 0);
+    }
+
+    #[test]
+    fn test_viable_prefix() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        assert!(grammar.is_viable_prefix("(n+n"));
+        assert!(!grammar.is_complete("(n+n"));
+        assert!(grammar.is_viable_prefix("(n+n)"));
+        assert!(grammar.is_complete("(n+n)"));
+        assert!(grammar.is_viable_prefix(""));
+        assert!(!grammar.is_complete(""));
+        assert!(!grammar.is_viable_prefix("(n+n))"));
+        assert!(!grammar.is_viable_prefix("n+)"));
+
+        // The rules that use an unproductive nonterminal can't complete a prefix.
+        let grammar_string = "S
+        S -> a b
+        S -> a c A
+        A -> d A";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.is_viable_prefix("a"));
+        assert!(!grammar.is_viable_prefix("ac"));
+        assert!(!grammar.is_viable_prefix("acd"));
+        assert_eq!(grammar.longest_prefix("acd"), 3);
+
+        let grammar = Grammar::from_rules("S\nS -> a S").unwrap();
+        assert!(!grammar.is_viable_prefix(""));
+    }
+
+    #[test]
+    fn test_next_terminals() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP - EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let next = |prefix| {
+            let mut next: Vec<char> = grammar
+                .next_terminals(prefix)
+                .unwrap()
+                .into_iter()
+                .collect();
+            next.sort();
+            next
+        };
+        assert_eq!(next("(n+"), vec!['(', 'n']);
+        assert_eq!(next(""), vec!['(', 'n']);
+        assert_eq!(next("(n"), vec![')', '+', '-']);
+        assert_eq!(next("n+n"), vec!['+', '-']);
+        assert!(next("n)").is_empty());
+
+        let grammar_string = "S
+        S -> a b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.next_terminals("ab"), Some(HashSet::new()));
+        assert_eq!(grammar.next_terminals("a"), Some(HashSet::from(['b'])));
+
+        // The characters of a predicate can't be listed.
+        let config = GrammarConfig::default().terminal_pred("space", char::is_whitespace);
+        let grammar_string = "S
+        S -> a \\p{space}
+        S -> b";
+        let grammar = Grammar::from_rules_with_config(grammar_string, &config).unwrap();
+        assert_eq!(grammar.next_terminals(""), Some(HashSet::from(['a', 'b'])));
+        assert_eq!(grammar.next_terminals("a"), None);
+    }
+
+    #[test]
+    fn test_parse_chart() {
+        let grammar_string = "S
+        S -> a S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let (trees, chart) = grammar.parse_chart("a");
+        assert_eq!(trees.len(), 1);
+        assert!(trees[0] == grammar.parse("a")[0]);
+        assert_eq!(chart.len(), 2);
+
+        let item = |rule: usize, production: &str, dot, origin, finished| ChartItem {
+            rule,
+            production: production.to_string(),
+            dot,
+            origin,
+            finished,
+        };
+        assert_eq!(
+            chart.set(0),
+            &[
+                item(0, "S -> a S", 0, 0, false),
+                item(1, "S ->", 0, 0, true)
+            ]
+        );
+        assert_eq!(
+            chart.set(1),
+            &[
+                item(0, "S -> a S", 0, 1, false),
+                item(0, "S -> a S", 1, 0, false),
+                item(0, "S -> a S", 2, 0, true),
+                item(1, "S ->", 0, 1, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> N
+        N -> D N
+        N -> D
+        D -> 1
+        D -> 2";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        enum Ast {
+            Symbol(char),
+            Number(String),
+            Add(Box<Ast>, Box<Ast>),
+            Mul(Box<Ast>, Box<Ast>),
+        }
+
+        fn build(token: Token, mut children: Vec<Ast>) -> Ast {
+            let name = match token {
+                Token::T(t) => return Ast::Symbol(t.content().unwrap()),
+                Token::NT(n) => n.name,
+            };
+            match (name, children.len()) {
+                (_, 1) => match children.pop().unwrap() {
+                    Ast::Symbol(c) => Ast::Number(c.to_string()),
+                    other => other,
+                },
+                ("N", 2) => match (children.remove(0), children.remove(0)) {
+                    (Ast::Number(a), Ast::Number(b)) => Ast::Number(a + &b),
+                    _ => unreachable!(),
+                },
+                ("EXP", 3) => {
+                    let right = children.pop().unwrap();
+                    let middle = children.pop().unwrap();
+                    let left = children.pop().unwrap();
+                    match middle {
+                        Ast::Symbol('+') => Ast::Add(Box::new(left), Box::new(right)),
+                        Ast::Symbol('*') => Ast::Mul(Box::new(left), Box::new(right)),
+                        inner => inner,
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        fn evaluate(ast: &Ast) -> u32 {
+            match ast {
+                Ast::Number(n) => n.parse().unwrap(),
+                Ast::Add(a, b) => evaluate(a) + evaluate(b),
+                Ast::Mul(a, b) => evaluate(a) * evaluate(b),
+                Ast::Symbol(_) => unreachable!(),
+            }
+        }
+
+        let trees = grammar.parse("(12+21)*2");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate(&trees[0].fold(&build)), 66);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "(12+21)*2");
+
+        // The nonterminals that derive the empty word get no values.
+        let grammar_string = "S
+        S -> a S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let trees = grammar.parse("aa");
+        let empty = trees[0].fold(&|token, children: Vec<usize>| match token {
+            Token::NT(_) if children.is_empty() => 1,
+            _ => children.iter().sum(),
+        });
+        assert_eq!(empty, 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "aa");
+    }
+
+    #[test]
+    fn test_leftmost_derivation() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n
+        EXP -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("n+(n*n)");
+        assert_eq!(trees.len(), 1);
+        let steps = leftmost_derivation(&trees[0]);
+        assert_eq!(
+            steps,
+            vec![
+                "EXP -> EXP + EXP",
+                "EXP -> n",
+                "EXP -> ( EXP )",
+                "EXP -> EXP * EXP",
+                "EXP -> n",
+                "EXP -> n",
+            ]
+        );
+
+        // Replay the derivations, rewriting the leftmost nonterminal each time.
+        for word in ["n+(n*n)", "n+n*n+n", "()+n", "(n+)*(n)"] {
+            for tree in grammar.parse(word) {
+                let mut form = vec!["EXP".to_string()];
+                for step in leftmost_derivation(&tree) {
+                    let mut symbols = step.split_whitespace();
+                    let from = symbols.next().unwrap();
+                    symbols.next();
+
+                    let position = form
+                        .iter()
+                        .position(|symbol| symbol.chars().all(|c| c.is_ascii_uppercase()))
+                        .unwrap();
+                    assert_eq!(form[position], from);
+                    form.splice(position..position + 1, symbols.map(|s| s.to_string()));
+                }
+                assert_eq!(form.concat(), word);
+            }
+        }
+    }
+
+    #[test]
+    fn test_derives() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> \\d";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        for word in ["1+(2*3)", "1+2*3", "(4)"] {
+            for tree in grammar.parse(word) {
+                let steps = grammar.leftmost_derivation(&tree);
+                assert!(grammar.derives(&steps, word));
+                assert!(!grammar.derives(&steps, "1"));
+                assert!(!grammar.derives(&steps[..steps.len() - 1], word));
+            }
+        }
+
+        assert!(grammar.derives(&[3], "7"));
+        // Nonterminals are left.
+        assert!(!grammar.derives(&[0, 3], "1+"));
+        // No nonterminal to rewrite.
+        assert!(!grammar.derives(&[3, 3], "7"));
+        // No such rule.
+        assert!(!grammar.derives(&[4], "7"));
+    }
+
+    #[test]
+    fn test_deep_derivation() {
+        let grammar_string = "S
+        S -> ( S )
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        // The trees are walked on a stack far too small for a recursive walk.
+        let small_stack = |test: &(dyn Fn() + Sync)| {
+            std::thread::scope(|scope| {
+                std::thread::Builder::new()
+                    .stack_size(64 * 1024)
+                    .spawn_scoped(scope, test)
+                    .unwrap()
+                    .join()
+                    .unwrap()
+            })
+        };
+
+        small_stack(&|| {
+            let depth = 20_000;
+            let word = format!("{}{}", "(".repeat(depth), ")".repeat(depth));
+            let trees = grammar.parse(&word);
+            assert_eq!(trees.len(), 1);
+            let tree = &trees[0];
+            assert_eq!(tree.height(), depth + 1);
+            assert_eq!(tree.node_count(), 3 * depth + 1);
+            assert_eq!(tree.leaf_count(), 2 * depth);
+            assert_eq!(tree.fold(&|_, children: Vec<usize>| children.len()), 3);
+            assert_eq!(tree.find_all("S").len(), depth + 1);
+            assert!(tree.find_first("T").is_none());
+            assert_eq!(format!("{:?}", tree).len(), 7 * depth + 5);
+            assert_eq!(leftmost_derivation(tree).len(), depth + 1);
+            assert_eq!(grammar.leftmost_derivation(tree).len(), depth + 1);
+            assert_eq!(parse_tree::prune_empty(tree).node_count(), 3 * depth);
+            assert!(Rc::ptr_eq(&collapse_unit_chains(tree), tree));
+            assert_eq!(forest_to_text(&trees).lines().count(), depth + 1);
+            let sync = SyncParseNode::from_trees(&trees);
+            assert!(SyncParseNode::to_trees(&sync) == trees);
+            assert!(grammar.parse_all(&word).trees().next().unwrap() == *tree);
+        });
+
+        // The text of a tree grows with the square of its depth, and the cheapest
+        // trees take longer to find, so a smaller tree is used.
+        small_stack(&|| {
+            let depth = 2000;
+            let word = format!("{}{}", "(".repeat(depth), ")".repeat(depth));
+            let tree = grammar.parse(&word).pop().unwrap();
+            assert!(grammar.parse_smallest(&word).unwrap() == tree);
+            assert!(grammar.parse_best(&word).unwrap().0 == tree);
+            assert_eq!(to_ascii(&tree).lines().count(), 3 * depth + 2);
+            let lines = to_tree_string(&tree, TreeStyle::Ascii);
+            assert_eq!(lines.lines().count(), 3 * depth + 2);
+        });
+    }
+
+    #[test]
+    fn test_render_tree_to_writer() {
+        let grammar = Grammar::from_rules("S\nS -> ( S ) S\nS -> ").unwrap();
+        let trees = grammar.parse(&"()".repeat(200));
+
+        let mut image = Vec::new();
+        match render_tree_to_writer(&trees[0], "svg", &mut image) {
+            // The dot program is not installed.
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            result => result.unwrap(),
+        }
+        let image = String::from_utf8(image).unwrap();
+        assert!(image.contains("<svg"));
+        assert!(image.trim_end().ends_with("</svg>"));
+
+        // A failing output stops dot instead of waiting for it forever.
+        struct Failing;
+        impl std::io::Write for Failing {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("full"))
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let error = render_tree_to_writer(&trees[0], "svg", &mut Failing).unwrap_err();
+        assert_eq!(error.to_string(), "full");
+    }
+
+    #[test]
+    fn test_deep_tree_to_dot() {
+        let grammar_string = "S
+        S -> ( S )
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let depth = 2000;
+        let word = format!("{}{}", "(".repeat(depth), ")".repeat(depth));
+        let trees = grammar.parse(&word);
+
+        let mut dot = String::new();
+        write_tree_to_dot(&mut dot, &trees[0]).unwrap();
+        // Every S has three children, except the innermost one, which has a lambda.
+        let nodes = 3 * depth + 2;
+        assert_eq!(
+            dot.lines().filter(|line| line.contains("[label=")).count(),
+            nodes
+        );
+        assert_eq!(
+            dot.lines().filter(|line| line.contains("->")).count(),
+            nodes - 1
+        );
+        assert!(dot.starts_with("digraph G{\n0 [label=\"(\"]\n1 [label=\"(\"]\n"));
+        assert!(dot.ends_with(&format!(
+            "{} [label=\"S\"]\n{} -> 0\n{} -> {}\n{} -> {}\n}}",
+            nodes - 1,
+            nodes - 1,
+            nodes - 1,
+            nodes - 3,
+            nodes - 1,
+            nodes - 2
+        )));
+    }
+
+    #[test]
+    fn test_character_classes() {
+        let grammar_string = "S
+        S -> n NUM
+        S -> ID
+        NUM -> \\d NUM
+        NUM -> \\d
+        ID -> [a-f] ID
+        ID -> [a-f]";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse("n123");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "n123");
+        assert_eq!(trees[0].leaf_count(), 4);
+
+        let trees = grammar.parse("cafe");
+        assert_eq!(trees.len(), 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "cafe");
+
+        // The trees are sorted even when the leaves differ from the terminals.
+        let grammar_string = "S
+        S -> S S
+        S -> [a-c]";
+        let ambiguous = Grammar::from_rules(grammar_string).unwrap();
+        let trees = ambiguous.parse("abc");
+        assert_eq!(trees.len(), 2);
+        assert_eq!(leftmost_derivation(&trees[0])[0], "S -> S S");
+        assert_eq!(evaluate_parse_tree(&trees[1]), "abc");
+        assert!(grammar.parse("n12a").is_empty());
+        assert!(grammar.parse("n").is_empty());
+        assert!(grammar.parse("cafes").is_empty());
+
+        // The leaves hold the matched characters in every kind of parse.
+        let (tree, _) = grammar.parse_best("n907").unwrap();
+        assert_eq!(evaluate_parse_tree(&tree), "n907");
+        let trees = grammar.parse_k_shortest("bad", 1);
+        assert_eq!(evaluate_parse_tree(&trees[0]), "bad");
+        assert_eq!(grammar.parse_case_insensitive("BEEF").len(), 1);
+
+        let error = grammar.scan_error("n1x").unwrap();
+        assert_eq!(error.expected, ('0'..='9').collect::<Vec<char>>());
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        // A message is a length-prefixed list of bytes, ended by a zero byte.
+        let grammar_string = "MSG
+        MSG -> \\x02 BYTE BYTE END
+        MSG -> \\x01 BYTE END
+        BYTE -> [a-z]
+        BYTE -> \\xff
+        BYTE -> \\x80
+        END -> \\x00";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let trees = grammar.parse_bytes(&[0x02, b'a', 0xff, 0x00]);
+        assert_eq!(trees.len(), 1);
+        let leaves: Vec<u32> = evaluate_parse_tree(&trees[0])
+            .chars()
+            .map(|c| c as u32)
+            .collect();
+        assert_eq!(leaves, vec![0x02, 0x61, 0xff, 0x00]);
+
+        assert_eq!(grammar.parse_bytes(&[0x01, 0x80, 0x00]).len(), 1);
+        assert!(grammar.parse_bytes(&[0x01, 0x80]).is_empty());
+        assert!(grammar.parse_bytes(&[0x02, 0x80, 0x00]).is_empty());
+        assert!(grammar.parse_bytes(&[]).is_empty());
+
+        // Non-ASCII characters count as one position each.
+        assert_eq!(grammar.parse("\u{1}\u{ff}\u{0}").len(), 1);
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    enum Lexeme {
+        Num,
+        Plus,
+        Times,
+        Open,
+        Close,
+    }
+
+    impl fmt::Display for Lexeme {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl Symbol for Lexeme {}
+
+    #[test]
+    fn test_generic_symbols() {
+        let exp = NonTerminal::new("EXP");
+        let symbol = |lexeme| Token::T(Terminal::Symbol(lexeme));
+        let productions = vec![
+            (
+                exp,
+                vec![Token::NT(exp), symbol(Lexeme::Plus), Token::NT(exp)],
+            ),
+            (
+                exp,
+                vec![Token::NT(exp), symbol(Lexeme::Times), Token::NT(exp)],
+            ),
+            (
+                exp,
+                vec![symbol(Lexeme::Open), Token::NT(exp), symbol(Lexeme::Close)],
+            ),
+            (exp, vec![symbol(Lexeme::Num)]),
+            (exp, vec![symbol(Lexeme::Num)]),
+        ];
+        let grammar: Grammar<Lexeme> = Grammar::from_productions(exp, productions);
+        assert_eq!(grammar.rule_count(), 4);
+        assert_eq!(grammar.terminal_count(), 5);
+
+        use Lexeme::*;
+        let trees = grammar.parse_symbols(&[Num, Plus, Num, Times, Num]);
+        assert_eq!(trees.len(), 2);
+        let leaves = trees[0].fold(&|token, children: Vec<Vec<Lexeme>>| match token {
+            Token::T(t) => vec![t.content().unwrap()],
+            Token::NT(_) => children.concat(),
+        });
+        assert_eq!(leaves, vec![Num, Plus, Num, Times, Num]);
+        assert_eq!(leftmost_derivation(&trees[0])[0], "EXP -> EXP Plus EXP");
+        assert!(to_ascii(&trees[1]).contains("  'Times'\n"));
+
+        assert_eq!(grammar.parse_symbols(&[Open, Num, Close]).len(), 1);
+        assert!(grammar.recognizes_symbols(&[Open, Num, Plus, Num, Close]));
+        assert!(!grammar.recognizes_symbols(&[Num, Plus]));
+        assert!(!grammar.recognizes_symbols(&[]));
+    }
 }
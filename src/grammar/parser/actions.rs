@@ -0,0 +1,151 @@
+use super::*;
+
+type RuleAction<T> = Box<dyn Fn(&[T]) -> T>;
+
+/// Semantic actions that are evaluated on the derivations of a parse.
+///
+/// Each rule is identified by its index in the grammar, which is the order
+/// in which the rules appear in the grammar text (the start line is not counted,
+/// and neither are the duplicated rules, which are only kept once).
+/// Labelled rules can also be identified by their label.
+/// A rule action receives the values of the symbols in the right side of the
+/// production, in order. Terminals are turned into values by the terminal action.
+pub struct RuleActions<T> {
+    terminal: Box<dyn Fn(char) -> T>,
+    rules: HashMap<usize, RuleAction<T>>,
+    named_rules: HashMap<String, RuleAction<T>>,
+}
+
+impl<T> RuleActions<T> {
+    /// Creates a set of actions with no rule actions, where terminals
+    /// are evaluated with the given function.
+    pub fn new<F>(terminal: F) -> Self
+    where
+        F: Fn(char) -> T + 'static,
+    {
+        RuleActions {
+            terminal: Box::new(terminal),
+            rules: HashMap::new(),
+            named_rules: HashMap::new(),
+        }
+    }
+
+    /// Registers the action for the rule with the given index.
+    /// A previously registered action for the same rule is replaced.
+    pub fn on_rule<F>(&mut self, rule: usize, action: F) -> &mut Self
+    where
+        F: Fn(&[T]) -> T + 'static,
+    {
+        self.rules.insert(rule, Box::new(action));
+        self
+    }
+
+    /// Registers the action for the rules with the given label, like `add` for
+    /// a rule ending with `@add`. An action registered by index takes precedence.
+    pub fn on_named<F>(&mut self, name: &str, action: F) -> &mut Self
+    where
+        F: Fn(&[T]) -> T + 'static,
+    {
+        self.named_rules.insert(name.to_string(), Box::new(action));
+        self
+    }
+
+    /// Returns the action of the rule with the given index and label.
+    fn action(&self, index: usize, name: Option<&str>) -> Option<&RuleAction<T>> {
+        self.rules
+            .get(&index)
+            .or_else(|| self.named_rules.get(name?))
+    }
+}
+
+/// Evaluates all the derivations of a finished state that belongs
+/// to the set at index `end`, using the given actions.
+///
+/// Panics if a rule used in a derivation has no registered action.
+pub fn evaluate<T: Clone>(
+    grammar: &Grammar,
+    state: &Rc<EarleyState>,
+    end: usize,
+    actions: &RuleActions<T>,
+) -> Vec<T> {
+    let leaf = |token, _| match token {
+        Token::T(Terminal::Symbol(c)) => (actions.terminal)(c),
+        Token::T(Terminal::Class(_)) => unreachable!("Leaves hold the scanned characters!"),
+        Token::NT(_) => unreachable!("Only terminals are scanned!"),
+    };
+    let node = |state: &EarleyState, values: Vec<T>, _| {
+        let index = grammar.rule_index(state.rule);
+        match actions.action(index, state.rule.name) {
+            Some(action) => action(&values),
+            None => panic!("No action registered for rule {}: {}", index, state.rule),
+        }
+    };
+
+    forest::Folder::new(leaf, node).fold(state, end)
+}
+
+/// Supplies the values of the derivations evaluated by `Grammar::parse_into`.
+/// Unlike `RuleActions`, the evaluation can fail, like for a division by zero.
+pub trait Evaluator<T> {
+    /// Returns the value of a leaf that holds the given character.
+    fn leaf(&self, c: char) -> Result<T, EvalError>;
+
+    /// Combines the values of the symbols in the right side of the rule with
+    /// the given index, in order.
+    fn combine(&self, rule: usize, values: &[T]) -> Result<T, EvalError>;
+}
+
+/// The reason why a derivation couldn't be evaluated by an `Evaluator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalError {
+    /// The evaluator has no value for the rule with the given index.
+    UnsupportedRule(usize),
+    /// The evaluator has no value for the character.
+    UnsupportedTerminal(char),
+    /// The values can't be combined, for the given reason.
+    Invalid(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::UnsupportedRule(index) => write!(f, "unsupported rule {}", index),
+            EvalError::UnsupportedTerminal(c) => write!(f, "unsupported terminal {:?}", c),
+            EvalError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl<'a> Grammar<'a> {
+    /// Parses the word and evaluates each of its derivations with the evaluator,
+    /// without building the parse trees. Returns one value per derivation, or the
+    /// first error met. This is `parse_with_actions` for evaluations that can fail.
+    pub fn parse_into<T, A>(&self, s: &str, eval: &A) -> Result<Vec<T>, EvalError>
+    where
+        T: Clone,
+        A: Evaluator<T>,
+    {
+        let table = self.build_table(s);
+        let last = s.chars().count();
+
+        let leaf = |token, _| match token {
+            Token::T(Terminal::Symbol(c)) => eval.leaf(c),
+            Token::T(Terminal::Class(_)) => unreachable!("Leaves hold the scanned characters!"),
+            Token::NT(_) => unreachable!("Only terminals are scanned!"),
+        };
+        let node = |state: &EarleyState, values: Vec<Result<T, EvalError>>, _| {
+            let values = values.into_iter().collect::<Result<Vec<T>, EvalError>>()?;
+            eval.combine(self.rule_index(state.rule), &values)
+        };
+
+        let mut result = Vec::new();
+        for state in self.accepting_states(&table) {
+            for value in forest::Folder::new(&leaf, &node).fold(state, last) {
+                result.push(value?);
+            }
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,75 @@
+use super::*;
+use std::collections::VecDeque;
+
+/// Wraps a grammar and remembers the parse trees of the words it parsed,
+/// so that parsing the same word again returns the same trees without
+/// building the earley table. The trees are shared, so handing them back is cheap.
+///
+/// By default the cache grows without bound. With a capacity, the cache holds
+/// at most that many words, and forgets the word that was parsed first
+/// when a new word doesn't fit.
+pub struct CachingGrammar<'a> {
+    grammar: &'a Grammar<'a>,
+    capacity: Option<usize>,
+    trees: HashMap<String, Vec<Rc<ParseNode<'a>>>>,
+    // The cached words, from the oldest to the newest.
+    order: VecDeque<String>,
+    hits: usize,
+}
+
+impl<'a> CachingGrammar<'a> {
+    /// Creates a cache that never forgets a word.
+    pub fn new(grammar: &'a Grammar<'a>) -> Self {
+        CachingGrammar {
+            grammar,
+            capacity: None,
+            trees: HashMap::new(),
+            order: VecDeque::new(),
+            hits: 0,
+        }
+    }
+
+    /// Creates a cache that holds the trees of at most `capacity` words.
+    pub fn with_capacity(grammar: &'a Grammar<'a>, capacity: usize) -> Self {
+        CachingGrammar {
+            capacity: Some(capacity),
+            ..CachingGrammar::new(grammar)
+        }
+    }
+
+    /// Returns the parse trees of the word, like `Grammar::parse`.
+    pub fn parse(&mut self, s: &str) -> Vec<Rc<ParseNode<'a>>> {
+        if let Some(trees) = self.trees.get(s) {
+            self.hits += 1;
+            return trees.clone();
+        }
+
+        let trees = self.grammar.parse(s);
+        if self.capacity == Some(0) {
+            return trees;
+        }
+        if Some(self.trees.len()) == self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.trees.remove(&oldest);
+        }
+        self.order.push_back(s.to_string());
+        self.trees.insert(s.to_string(), trees.clone());
+        trees
+    }
+
+    /// Forgets all the cached words.
+    pub fn clear_cache(&mut self) {
+        self.trees.clear();
+        self.order.clear();
+    }
+
+    /// Returns the number of cached words.
+    pub fn cache_len(&self) -> usize {
+        self.trees.len()
+    }
+
+    /// Returns the number of calls to `parse` that were answered from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+}
@@ -0,0 +1,230 @@
+use super::*;
+
+/// A state of an earley set, copied out of the earley table.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChartItem {
+    /// The index of the rule in the grammar.
+    pub rule: usize,
+    /// The rule, written like `A -> x Y z`.
+    pub production: String,
+    /// The number of symbols of the rule that were matched.
+    pub dot: usize,
+    /// The index of the set in which the matching began.
+    pub origin: usize,
+    /// True if the whole rule was matched.
+    pub finished: bool,
+}
+
+/// Counts of the work done to build the earley table of a word.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChartStats {
+    /// The number of states in all the sets.
+    pub items: usize,
+    /// The number of states in the largest set.
+    pub max_set_size: usize,
+    /// The number of states produced by prediction, including the ones already in their set.
+    pub predictions: usize,
+    /// The number of states produced by scanning, including the ones already in their set.
+    pub scans: usize,
+    /// The number of states produced by completion, including the ones already in their set.
+    pub completions: usize,
+    /// The number of states processed over all the sets. Each state is
+    /// processed once, so this is the same as `items`.
+    pub iterations: usize,
+}
+
+/// The time spent building the parse trees of a word, returned by `parse_timed`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PhaseTimings {
+    /// The time spent predicting states.
+    pub prediction: Duration,
+    /// The time spent scanning characters.
+    pub scan: Duration,
+    /// The time spent completing states.
+    pub completion: Duration,
+    /// The time spent building the trees from the table.
+    pub trees: Duration,
+    /// The time spent in the whole parse, including the work between the phases.
+    pub total: Duration,
+}
+
+/// A read-only copy of the earley table built for a word.
+/// The set at index k holds the states at position k of the word,
+/// sorted by rule, then by dot, then by origin.
+pub struct EarleyChart {
+    sets: Vec<Vec<ChartItem>>,
+    // The states of each set written like the sets of the earley table,
+    // in the same order as the items.
+    lines: Vec<Vec<String>>,
+}
+
+impl EarleyChart {
+    /// Returns the number of sets, which is one more than the length of the word.
+    pub fn len(&self) -> usize {
+        self.sets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sets.is_empty()
+    }
+
+    /// Returns the states of the set at index k.
+    pub fn set(&self, k: usize) -> &[ChartItem] {
+        &self.sets[k]
+    }
+
+    pub fn sets(&self) -> &[Vec<ChartItem>] {
+        &self.sets
+    }
+
+    /// Returns the item at the given index of the set at index `set`, written
+    /// with a dot before the symbols that are left to match, like
+    /// `Rule: EXP -> EXP.+EXP  Origin: 0, Dot: 1`. Returns None if there is no such item.
+    pub fn item_to_string(&self, set: usize, index: usize) -> Option<String> {
+        self.lines.get(set)?.get(index).cloned()
+    }
+
+    /// Writes the sets with indices in `from..to`, with one line per state,
+    /// like the earley table is printed. The first line tells which sets are shown.
+    /// The range stops at the last set.
+    pub fn format_range(&self, from: usize, to: usize) -> String {
+        let to = to.min(self.sets.len());
+        let mut result = format!("Sets {}..{} of {}\n", from, to, self.sets.len());
+        for (k, lines) in self.lines.iter().enumerate().take(to).skip(from) {
+            result.push_str(&format!("S{}\n", k));
+            for line in lines {
+                result.push_str(line);
+                result.push('\n');
+            }
+        }
+        result
+    }
+}
+
+/// Writes the string as a JSON string literal.
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+impl<'a> Grammar<'a> {
+    fn chart_of(&self, table: &EarleyTable) -> EarleyChart {
+        let (sets, lines) = table
+            .sets
+            .iter()
+            .map(|set| {
+                let mut items: Vec<(ChartItem, String)> = set
+                    .iter()
+                    .map(|state| {
+                        let item = ChartItem {
+                            rule: self.rule_index(state.rule),
+                            production: state.rule.to_string().trim_end().to_string(),
+                            dot: state.dot,
+                            origin: state.origin,
+                            finished: state.is_finished(),
+                        };
+                        (item, state.to_string())
+                    })
+                    .collect();
+                items.sort_by_key(|(item, _)| (item.rule, item.dot, item.origin));
+                items.into_iter().unzip()
+            })
+            .unzip();
+        EarleyChart { sets, lines }
+    }
+
+    /// Parses the word like `parse`, and also returns the earley table
+    /// that was built, for inspection.
+    pub fn parse_chart(&self, s: &str) -> (Vec<Rc<ParseNode<'_>>>, EarleyChart) {
+        let table = self.build_table(s);
+        let trees = self.parse_trees(&table, s.chars().count());
+        (trees, self.chart_of(&table))
+    }
+
+    /// Parses the word like `parse`, and also returns the time spent in each phase.
+    /// The clock is only read by this method, so `parse` doesn't pay for it.
+    pub fn parse_timed(&self, s: &str) -> (Vec<Rc<ParseNode<'_>>>, PhaseTimings) {
+        let started = Instant::now();
+        let input: Vec<char> = s.chars().collect();
+        let options = TableOptions {
+            timed: true,
+            ..TableOptions::default()
+        };
+        let table = self
+            .build_table_with(&input, options)
+            .expect("The table has no limit!");
+
+        let built = Instant::now();
+        let trees = self.parse_trees(&table, input.len());
+        let timings = PhaseTimings {
+            trees: built.elapsed(),
+            total: started.elapsed(),
+            ..table.timings
+        };
+        (trees, timings)
+    }
+
+    /// Parses the word like `parse`, and also returns counts of the work done
+    /// to build the earley table, to find out why a grammar is slow.
+    pub fn parse_stats(&self, s: &str) -> (Vec<Rc<ParseNode<'_>>>, ChartStats) {
+        let table = self.build_table(s);
+        let trees = self.parse_trees(&table, s.chars().count());
+        let stats = ChartStats {
+            items: table.state_count,
+            max_set_size: table
+                .sets
+                .iter()
+                .map(|set| set.states.len())
+                .max()
+                .unwrap_or(0),
+            ..table.stats
+        };
+        (trees, stats)
+    }
+
+    /// Returns the earley table built for the word as JSON, for visualizers.
+    /// The table is an array holding an array of items for each set, ordered
+    /// like the sets of `parse_chart`. An item looks like:
+    /// `{"from":"EXP","to":["EXP","+","EXP"],"dot":1,"origin":0,"finished":false}`.
+    pub fn chart_to_json(&self, s: &str) -> String {
+        let table = self.build_table(s);
+        let sets: Vec<String> = table
+            .sets
+            .iter()
+            .map(|set| {
+                let mut states: Vec<_> = set.iter().collect();
+                states.sort_by_key(|state| (self.rule_index(state.rule), state.dot, state.origin));
+                let items: Vec<String> = states
+                    .iter()
+                    .map(|state| {
+                        let to: Vec<String> = state
+                            .rule
+                            .to
+                            .iter()
+                            .map(|token| json_string(&token.to_string()))
+                            .collect();
+                        format!(
+                            "{{\"from\":{},\"to\":[{}],\"dot\":{},\"origin\":{},\"finished\":{}}}",
+                            json_string(state.rule.from.name),
+                            to.join(","),
+                            state.dot,
+                            state.origin,
+                            state.is_finished()
+                        )
+                    })
+                    .collect();
+                format!("[{}]", items.join(","))
+            })
+            .collect();
+        format!("[{}]", sets.join(","))
+    }
+}
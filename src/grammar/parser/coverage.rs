@@ -0,0 +1,48 @@
+use super::*;
+
+/// The rules of a grammar used by the derivations of a set of words.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleCoverage {
+    /// The indices of the rules used by at least one derivation, in increasing order.
+    pub used: Vec<usize>,
+    /// The indices of the rules never used, in increasing order.
+    pub unused: Vec<usize>,
+}
+
+impl<'a> Grammar<'a> {
+    /// Parses every word and returns the rules used by at least one of their
+    /// derivations. Words that can't be derived use no rules.
+    ///
+    /// The states of the derivations are visited once each, instead of building
+    /// every tree, so this stays fast when the words have many derivations.
+    pub fn coverage(&self, inputs: &[&str]) -> RuleCoverage {
+        let mut is_used = vec![false; self.rules.len()];
+
+        for input in inputs {
+            let table = self.build_table(input);
+            let mut visited = HashSet::new();
+            let mut stack: Vec<Rc<EarleyState>> =
+                self.accepting_states(&table).into_iter().cloned().collect();
+
+            while let Some(state) = stack.pop() {
+                if !visited.insert(Rc::as_ptr(&state)) {
+                    continue;
+                }
+                is_used[self.rule_index(state.rule)] = true;
+
+                for derivation in state.derivations.borrow().iter() {
+                    match derivation {
+                        Derivation::Scan(previous, _) => stack.push(previous.clone()),
+                        Derivation::Complete { previous, child } => {
+                            stack.push(previous.clone());
+                            stack.push(child.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        let (used, unused) = (0..self.rules.len()).partition(|&index| is_used[index]);
+        RuleCoverage { used, unused }
+    }
+}
@@ -0,0 +1,211 @@
+use super::*;
+use std::collections::HashMap;
+
+/// The CYK recognizer of a word, over a grammar in Chomsky Normal Form.
+/// Every nonterminal of the original grammar keeps its name and its language
+/// (without the empty word) in the CNF grammar, so the table also tells
+/// which parts of the word each original nonterminal derives.
+struct CykTable<'a> {
+    // The nonterminals that derive the characters start..end,
+    // stored at index [start][end - start - 1].
+    spans: Vec<Vec<HashSet<NonTerminal<'a>>>>,
+}
+
+impl<'a> CykTable<'a> {
+    fn new(cnf: &Grammar<'a>, input: &[char]) -> Self {
+        let n = input.len();
+        let mut spans: Vec<Vec<HashSet<NonTerminal<'a>>>> = (0..n)
+            .map(|start| vec![HashSet::new(); n - start])
+            .collect();
+
+        for (start, &c) in input.iter().enumerate() {
+            for rule in cnf.rules.iter() {
+                if let [Token::T(terminal)] = rule.to[..] {
+                    if terminal.matches(c) {
+                        spans[start][0].insert(rule.from);
+                    }
+                }
+            }
+        }
+
+        for length in 2..=n {
+            for start in 0..=n - length {
+                let mut found = HashSet::new();
+                for split in 1..length {
+                    let left = &spans[start][split - 1];
+                    let right = &spans[start + split][length - split - 1];
+                    for rule in cnf.rules.iter() {
+                        if let [Token::NT(b), Token::NT(c)] = rule.to[..] {
+                            if left.contains(&b) && right.contains(&c) {
+                                found.insert(rule.from);
+                            }
+                        }
+                    }
+                }
+                spans[start][length - 1] = found;
+            }
+        }
+
+        CykTable { spans }
+    }
+
+    fn derives(&self, nonterminal: NonTerminal<'a>, start: usize, end: usize) -> bool {
+        self.spans[start][end - start - 1].contains(&nonterminal)
+    }
+}
+
+/// Builds the trees of the original grammar, using the CYK table to only
+/// try the splits of the word that can be derived.
+///
+/// With a cyclic grammar, a nonterminal can derive itself over the same span.
+/// The trees are cut there, like in `parse`: the trees of a nonterminal are built
+/// in the context of its ancestors that span the same characters, from the outermost
+/// one, and a nonterminal that is already in its context has no trees.
+struct TreeBuilder<'i, 'a> {
+    grammar: &'a Grammar<'a>,
+    input: &'i [char],
+    // The table of the grammar in Chomsky Normal Form, whose new
+    // nonterminals live as long as the input.
+    table: CykTable<'i>,
+    nullable: HashSet<NonTerminal<'a>>,
+    trees: HashMap<Key<'a>, Vec<Rc<ParseNode<'a>>>>,
+}
+
+/// A nonterminal, the start and the end of its span, and its context.
+type Key<'a> = (NonTerminal<'a>, usize, usize, forest::Context<'a>);
+
+impl<'a> TreeBuilder<'_, 'a> {
+    fn derives(&self, token: Token<'a>, start: usize, end: usize) -> bool {
+        match token {
+            Token::T(terminal) => end == start + 1 && terminal.matches(self.input[start]),
+            Token::NT(n) if start == end => self.nullable.contains(&n),
+            Token::NT(n) => self.table.derives(n, start, end),
+        }
+    }
+
+    fn trees_of_token(
+        &mut self,
+        token: Token<'a>,
+        start: usize,
+        end: usize,
+        context: &[NonTerminal<'a>],
+    ) -> Vec<Rc<ParseNode<'a>>> {
+        match token {
+            Token::T(_) => vec![Rc::new(ParseNode {
+                token: leaf_token(token, self.input[start]),
+                children: Vec::new(),
+                start,
+                end,
+                rule: None,
+            })],
+            Token::NT(n) => self.trees_of(n, start, end, context),
+        }
+    }
+
+    fn trees_of(
+        &mut self,
+        nonterminal: NonTerminal<'a>,
+        start: usize,
+        end: usize,
+        context: &[NonTerminal<'a>],
+    ) -> Vec<Rc<ParseNode<'a>>> {
+        // The nonterminal derives itself over the same span: the cycle is cut.
+        if context.contains(&nonterminal) {
+            return Vec::new();
+        }
+        let key = (nonterminal, start, end, context.to_vec());
+        if let Some(trees) = self.trees.get(&key) {
+            return trees.clone();
+        }
+
+        let mut inner = context.to_vec();
+        inner.push(nonterminal);
+        let grammar = self.grammar;
+        let mut trees = Vec::new();
+        for &index in grammar.rules_for(nonterminal) {
+            let symbols = &grammar.rules[index].to;
+            for children in self.sequences(symbols, start, end, (start, end), &inner) {
+                trees.push(Rc::new(ParseNode {
+                    token: Token::NT(nonterminal),
+                    children,
+                    start,
+                    end,
+                    rule: Some(&grammar.rules[index].to[..]),
+                }));
+            }
+        }
+
+        self.trees.insert(key, trees.clone());
+        trees
+    }
+
+    /// Returns the lists of trees of the symbols, in order, that derive the characters start..end.
+    /// The symbols that span the characters of their parent, given by `span`, are built in
+    /// the `inner` context.
+    fn sequences(
+        &mut self,
+        symbols: &[Token<'a>],
+        start: usize,
+        end: usize,
+        span: (usize, usize),
+        inner: &[NonTerminal<'a>],
+    ) -> Vec<Vec<Rc<ParseNode<'a>>>> {
+        let Some((&first, rest)) = symbols.split_first() else {
+            return if start == end {
+                vec![Vec::new()]
+            } else {
+                Vec::new()
+            };
+        };
+
+        let mut result = Vec::new();
+        for middle in start..=end {
+            if !self.derives(first, start, middle) {
+                continue;
+            }
+            let tails = self.sequences(rest, middle, end, span, inner);
+            if tails.is_empty() {
+                continue;
+            }
+            let context = if (start, middle) == span { inner } else { &[] };
+            for tree in self.trees_of_token(first, start, middle, context) {
+                for tail in tails.iter() {
+                    let mut children = vec![tree.clone()];
+                    children.extend(tail.iter().cloned());
+                    result.push(children);
+                }
+            }
+        }
+        result
+    }
+}
+
+impl<'a> Grammar<'a> {
+    /// Parses the word with the CYK algorithm instead of the earley algorithm,
+    /// and returns the same trees as `parse`, in the same order.
+    ///
+    /// The word is recognized with the grammar converted to Chomsky Normal Form.
+    /// The trees are then built over the rules of this grammar, trying only
+    /// the splits of the word that the CYK table allows, so they don't show
+    /// the nonterminals added by the conversion. Building the table takes
+    /// O(n³) time for a word of length n, whatever the ambiguity of the grammar.
+    /// The cycles of a cyclic grammar are cut like in `parse`.
+    pub fn parse_cyk(&self, s: &str) -> Vec<Rc<ParseNode<'_>>> {
+        let input: Vec<char> = s.chars().collect();
+        let mut names = String::new();
+        let mut builder = TreeBuilder {
+            grammar: self,
+            input: &input,
+            table: CykTable::new(&self.to_cnf(&mut names), &input),
+            nullable: self.nullable(),
+            trees: HashMap::new(),
+        };
+
+        if !builder.derives(Token::NT(self.start), 0, input.len()) {
+            return Vec::new();
+        }
+        let mut result = builder.trees_of(self.start, 0, input.len(), &[]);
+        self.sort_trees(&mut result);
+        result
+    }
+}
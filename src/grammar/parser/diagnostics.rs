@@ -0,0 +1,263 @@
+use super::*;
+
+/// Describes where a word stopped matching the grammar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanError {
+    /// The position (in characters, starting from 0) of the character that could not be scanned.
+    pub position: usize,
+    /// The character at that position.
+    pub found: char,
+    /// The terminals that could have been scanned at that position, sorted.
+    pub expected: Vec<char>,
+    /// The predicates that could have been scanned at that position, sorted.
+    /// Their characters can't be listed in `expected`.
+    pub expected_predicates: Vec<CharPredicate>,
+}
+
+/// The trees of a word, or of its longest prefix if the word is rejected,
+/// returned by `Grammar::parse_best_effort`.
+#[derive(Debug)]
+pub enum BestEffort<'a> {
+    /// The word is accepted, with these trees, in the order of `parse`.
+    Complete(Vec<Rc<ParseNode<'a>>>),
+    /// The word is rejected.
+    Partial {
+        /// The trees of the input up to `failed_at`. If that prefix is derived
+        /// from the start nonterminal, these are its trees. Otherwise, the trees
+        /// are partial: the nodes on their right edge stop at the dot of their rule,
+        /// with only the children matched so far. There are no trees if nothing
+        /// was matched.
+        trees: Vec<Rc<ParseNode<'a>>>,
+        /// The position where the word stopped matching the grammar, like
+        /// `Grammar::longest_prefix`: the character at that position could not
+        /// be scanned, or the word ended there.
+        failed_at: usize,
+    },
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unexpected '{}' at position {}, expected one of: ",
+            self.found, self.position
+        )?;
+        let chars = self.expected.iter().map(|c| format!("'{}'", c));
+        let predicates = self.expected_predicates.iter().map(|p| format!("{:?}", p));
+        for (index, expected) in chars.chain(predicates).enumerate() {
+            if index > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", expected)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Grammar<'a> {
+    /// Returns the characters of the terminals awaited by the states of the set
+    /// at index k, and the awaited predicates, whose characters can't be listed.
+    fn expected_terminals(&self, table: &EarleyTable, k: usize) -> (Vec<char>, Vec<CharPredicate>) {
+        let mut expected = Vec::new();
+        let mut predicates = Vec::new();
+        for state in table.sets[k].iter().filter(|state| !state.is_finished()) {
+            match state.current_token() {
+                Token::T(Terminal::Class(CharClass::Predicate(predicate))) => {
+                    predicates.push(predicate)
+                }
+                Token::T(t) => {
+                    expected.extend(t.chars().expect("Only predicates can't be listed!"))
+                }
+                Token::NT(_) => {}
+            }
+        }
+        expected.sort();
+        expected.dedup();
+        predicates.sort();
+        predicates.dedup();
+        (expected, predicates)
+    }
+
+    /// Returns the length (in characters) of the longest prefix of the word that
+    /// is also the beginning of a word of the language. This is the length
+    /// of the word if it is accepted.
+    pub fn longest_prefix(&self, s: &str) -> usize {
+        let table = self.build_table(s);
+        self.furthest_position(&table)
+    }
+
+    /// Returns true if the whole word is derived from the start nonterminal.
+    /// This is the same as `recognizes`, and the counterpart of `is_viable_prefix`.
+    pub fn is_complete(&self, s: &str) -> bool {
+        self.recognizes(s)
+    }
+
+    /// Returns true if the word is the beginning of a word of the language,
+    /// so that it can still be completed. Complete words are viable prefixes too.
+    ///
+    /// The table is built with the rules whose symbols are all productive: with
+    /// the other rules, a state could wait for a symbol that never derives anything.
+    pub fn is_viable_prefix(&self, s: &str) -> bool {
+        let productive = self.productive();
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| {
+                rule.to.iter().all(|token| match token {
+                    Token::NT(n) => productive.contains(n),
+                    Token::T(_) => true,
+                })
+            })
+            .cloned()
+            .collect();
+        let grammar = Grammar::with_symbols(self.start, rules);
+
+        // Every state of the table can be completed, so the word is a viable
+        // prefix if the last set holds any state.
+        let table = grammar.build_table(s);
+        let viable = table.sets[table.sets.len() - 1].iter().next().is_some();
+        viable
+    }
+
+    /// Returns the characters that can follow the prefix in a word of the language,
+    /// for autocompletion. The set is empty if the prefix can't be extended,
+    /// either because it is not the beginning of any word, or because every word
+    /// that starts with it ends there.
+    /// Returns None if a predicate can follow the prefix, since its characters
+    /// can't be listed.
+    pub fn next_terminals(&self, prefix: &str) -> Option<HashSet<char>> {
+        let table = self.build_table(prefix);
+        let last = table.sets.len() - 1;
+        // The states of the last set were already predicted, so the terminals
+        // awaited through the nonterminals are awaited directly by some state.
+        let (expected, predicates) = self.expected_terminals(&table, last);
+        if !predicates.is_empty() {
+            return None;
+        }
+        Some(expected.into_iter().collect())
+    }
+
+    /// Returns the first character of the word that could not be scanned,
+    /// along with the terminals that were expected instead.
+    ///
+    /// Returns None if every character was scanned: the word is either accepted,
+    /// or it is the beginning of a longer word of the language.
+    pub fn scan_error(&self, s: &str) -> Option<ScanError> {
+        let table = self.build_table(s);
+        let position = self.furthest_position(&table);
+        let found = s.chars().nth(position)?;
+
+        let (expected, expected_predicates) = self.expected_terminals(&table, position);
+        Some(ScanError {
+            position,
+            found,
+            expected,
+            expected_predicates,
+        })
+    }
+
+    /// Returns the trees of the word if it is accepted. Otherwise, returns
+    /// where the word stopped matching, like `longest_prefix`, and the trees of
+    /// the input up to there: the trees of `parse_prefix` if that prefix is a word
+    /// of the language, or else the partial trees built from the unfinished states
+    /// of the set at the failure.
+    ///
+    /// A partial tree stops at the dot of each rule on its right edge, like
+    /// `EXP[( EXP[(n)+n]]` for `((n)+n-)`, where `EXP -> ( EXP )` is missing
+    /// its closing parenthesis. A rule whose first symbol is its own nonterminal,
+    /// like `EXP -> EXP + EXP`, is not used to wrap a node before its first child
+    /// is matched, since the wrapping could be repeated forever.
+    pub fn parse_best_effort(&self, s: &str) -> BestEffort<'_> {
+        let mut table = self.build_table(s);
+        let last = s.chars().count();
+        if !self.accepting_states(&table).is_empty() {
+            return BestEffort::Complete(self.parse_trees(&table, last));
+        }
+
+        let failed_at = self.furthest_position(&table);
+        let trees = if self.longest_derived_prefix(&table, self.start) == Some(failed_at) {
+            table.resize(failed_at + 1);
+            self.parse_trees_of(&table, self.start, 0, failed_at)
+        } else {
+            self.partial_trees(&table, failed_at)
+        };
+        BestEffort::Partial { trees, failed_at }
+    }
+
+    /// Returns the partial trees of the input up to `end`, which start
+    /// with an unfinished state of the set at `end` that matched at least one symbol.
+    /// Each node is wrapped in the nodes of the states that wait for it, until
+    /// a state of the start nonterminal that starts at 0 is reached.
+    fn partial_trees<'g>(&self, table: &EarleyTable<'g>, end: usize) -> Vec<Rc<ParseNode<'g>>> {
+        let mut folder = parse_tree::tree_folder(0);
+        let node = |state: &EarleyState<'g>, children| {
+            Rc::new(ParseNode {
+                token: Token::NT(state.rule.from),
+                children,
+                start: state.origin,
+                end,
+                rule: Some(&state.rule.to[..]),
+            })
+        };
+
+        // The nodes to wrap, with the states already used on their right edge,
+        // which are never used again, to cut the cycles.
+        let mut stack = Vec::new();
+        for state in table.sets[end].iter() {
+            if state.dot > 0 && !state.is_finished() {
+                for children in folder.fold_prefix(state, end) {
+                    stack.push((
+                        state.clone(),
+                        node(state, children),
+                        vec![Rc::as_ptr(state)],
+                    ));
+                }
+            }
+        }
+
+        let mut result = Vec::new();
+        while let Some((state, tree, used)) = stack.pop() {
+            if state.rule.from == self.start && state.origin == 0 {
+                result.push(tree);
+                continue;
+            }
+
+            let nonterminal = state.rule.from;
+            for parent in table.sets[state.origin].waiting_for(nonterminal) {
+                let wraps_itself = parent.dot == 0 && parent.rule.from == nonterminal;
+                if wraps_itself || used.contains(&Rc::as_ptr(parent)) {
+                    continue;
+                }
+                for mut children in folder.fold_prefix(parent, state.origin) {
+                    children.push(tree.clone());
+                    let mut used = used.clone();
+                    used.push(Rc::as_ptr(parent));
+                    stack.push((parent.clone(), node(parent, children), used));
+                }
+            }
+        }
+
+        result.sort_by_cached_key(|tree| self.partial_derivation(tree));
+        result
+    }
+
+    /// Returns the rules of the nodes of a partial tree, in the order of
+    /// a leftmost derivation, with the number of children of each node.
+    fn partial_derivation(&self, tree: &ParseNode) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        let mut stack = vec![tree];
+        while let Some(node) = stack.pop() {
+            if let (Token::NT(nonterminal), Some(to)) = (node.token, node.rule) {
+                let index = self
+                    .rules_for(nonterminal)
+                    .iter()
+                    .copied()
+                    .find(|&index| std::ptr::eq(&self.rules[index].to[..], to))
+                    .expect("The tree does not belong to this grammar!");
+                result.push((index, node.children.len()));
+            }
+            stack.extend(node.children.iter().rev().map(|child| child.as_ref()));
+        }
+        result
+    }
+}
@@ -0,0 +1,433 @@
+use super::*;
+
+/// The derivations stored in an earley table form a shared forest:
+/// a finished state can be the child of many other states, and it can
+/// have many derivations itself.
+///
+/// A `Folder` enumerates all the derivations of a finished state, turning each
+/// one of them into a value of type V. Terminals are turned into values by the
+/// `leaf` function, which receives the terminal and its position in the input.
+/// Finished states are turned into values by the `node` function, which receives
+/// the state, the values of the symbols in its production and the end of its span.
+///
+/// The forest is walked with an explicit stack instead of recursion, so that
+/// very deep derivations don't overflow the native stack.
+///
+/// With a cyclic grammar, a derivation can contain itself, so there are infinitely
+/// many of them. A derivation is cut where a nonterminal would derive itself over
+/// the same span, which leaves a finite set of derivations: the ones in which no
+/// nonterminal derives itself over the same span.
+pub struct Folder<'a, T, V, L, N> {
+    leaf: L,
+    node: N,
+    // The values of the finished states, and the values of the symbols
+    // before the dot of the states, keyed by the address of the state
+    // and by the context of the task.
+    memo: HashMap<(*const EarleyState<'a, T>, Context<'a>), Vec<V>>,
+    prefix_memo: HashMap<(*const EarleyState<'a, T>, Context<'a>), Vec<Vec<V>>>,
+}
+
+/// The nonterminals of the ancestors of a node that span the same symbols as the node,
+/// from the outermost one. A node whose nonterminal is in its context is cut.
+pub(super) type Context<'a> = Vec<NonTerminal<'a>>;
+
+/// A value to compute: the values of a finished state, or the values of the symbols
+/// before the dot of a state. The state belongs to the set at the given index.
+///
+/// The context of a `Fold` is the context of the node of the state. The context
+/// of a `Prefix` is the context of the children that span the whole prefix.
+pub(super) enum Task<'a, T> {
+    Fold(Rc<EarleyState<'a, T>>, usize, Context<'a>),
+    Prefix(Rc<EarleyState<'a, T>>, usize, Context<'a>),
+}
+
+impl<'a, T> Task<'a, T> {
+    pub(super) fn key(&self) -> (bool, *const EarleyState<'a, T>, &Context<'a>) {
+        match self {
+            Task::Fold(state, _, context) => (true, Rc::as_ptr(state), context),
+            Task::Prefix(state, _, context) => (false, Rc::as_ptr(state), context),
+        }
+    }
+}
+
+/// Returns the context of the children of the finished state that span the same
+/// symbols as the state, or None if the state is cut in the given context.
+pub(super) fn inner_context<'a, T>(
+    state: &EarleyState<'a, T>,
+    context: &Context<'a>,
+) -> Option<Context<'a>> {
+    if context.contains(&state.rule.from) {
+        return None;
+    }
+    let mut inner = context.clone();
+    inner.push(state.rule.from);
+    Some(inner)
+}
+
+impl<'a, T> Derivation<'a, T> {
+    /// Returns the state before the dot was moved, and the index of the set it belongs to,
+    /// given the index of the set of the derived state.
+    pub(super) fn previous(&self, end: usize) -> (&Rc<EarleyState<'a, T>>, usize) {
+        match self {
+            Derivation::Scan(previous, _) => (previous, end - 1),
+            Derivation::Complete { previous, child } => (previous, child.origin),
+        }
+    }
+
+    /// Returns a key that orders the derivations of a state the same way on every
+    /// run, unlike the order in which they were found. The derivations of a state
+    /// differ by the split and, for the completions, by the rule of the child.
+    fn order_key(&self, end: usize) -> (usize, *const Rule<'a, T>) {
+        match self {
+            Derivation::Scan(..) => (end - 1, std::ptr::null()),
+            Derivation::Complete { child, .. } => (child.origin, child.rule),
+        }
+    }
+
+    /// Returns the contexts of the state before the dot was moved and of the child,
+    /// given the state, the index of its set and the context of its prefix.
+    /// Only the symbols that span the whole prefix keep the context.
+    pub(super) fn contexts(
+        &self,
+        state: &EarleyState<'a, T>,
+        end: usize,
+        context: &Context<'a>,
+    ) -> (Context<'a>, Context<'a>) {
+        let (_, split) = self.previous(end);
+        let previous = if split == end {
+            context.clone()
+        } else {
+            Vec::new()
+        };
+        let child = match self {
+            Derivation::Complete { child, .. } if child.origin == state.origin => context.clone(),
+            _ => Vec::new(),
+        };
+        (previous, child)
+    }
+}
+
+impl<'a, T, V, L, N> Folder<'a, T, V, L, N>
+where
+    T: Symbol,
+    V: Clone,
+    L: Fn(Token<'a, T>, usize) -> V,
+    N: Fn(&EarleyState<'a, T>, Vec<V>, usize) -> V,
+{
+    pub fn new(leaf: L, node: N) -> Self {
+        Folder {
+            leaf,
+            node,
+            memo: HashMap::new(),
+            prefix_memo: HashMap::new(),
+        }
+    }
+
+    /// Returns one value for each derivation of the finished state,
+    /// which belongs to the set at index `end`.
+    pub fn fold(&mut self, state: &Rc<EarleyState<'a, T>>, end: usize) -> Vec<V> {
+        evaluate(self, Task::Fold(state.clone(), end, Vec::new()));
+        self.memo[&(Rc::as_ptr(state), Vec::new())].clone()
+    }
+
+    /// Returns the values of the symbols before the dot of the state, one list
+    /// for each derivation of the state, which belongs to the set at index `end`.
+    /// The state does not have to be finished.
+    pub fn fold_prefix(&mut self, state: &Rc<EarleyState<'a, T>>, end: usize) -> Vec<Vec<V>> {
+        evaluate(self, Task::Prefix(state.clone(), end, Vec::new()));
+        self.prefix_memo[&(Rc::as_ptr(state), Vec::new())].clone()
+    }
+}
+
+impl<'a, T, V, L, N> Evaluator<'a, T> for Folder<'a, T, V, L, N>
+where
+    T: Symbol,
+    V: Clone,
+    L: Fn(Token<'a, T>, usize) -> V,
+    N: Fn(&EarleyState<'a, T>, Vec<V>, usize) -> V,
+{
+    fn is_done(&self, task: &Task<'a, T>) -> bool {
+        match task.key() {
+            (true, state, context) => self.memo.contains_key(&(state, context.clone())),
+            (false, state, context) => self.prefix_memo.contains_key(&(state, context.clone())),
+        }
+    }
+
+    /// Computes the values of the task, once all its dependencies are done.
+    fn run(&mut self, task: &Task<'a, T>) {
+        match task {
+            Task::Fold(state, end, context) => {
+                let values = inner_context(state, context)
+                    .and_then(|inner| self.prefix_memo.get(&(Rc::as_ptr(state), inner)))
+                    .into_iter()
+                    .flatten()
+                    .map(|children| {
+                        // Each derivation moves the dot over one symbol, so a derivation
+                        // that skips a symbol would misplace all the following children.
+                        if children.len() != state.rule.to.len() {
+                            panic!(
+                                "A derivation of the state {} has {} children instead of {}!",
+                                state.rule.to_string().trim_end(),
+                                children.len(),
+                                state.rule.to.len()
+                            );
+                        }
+                        (self.node)(state, children.clone(), *end)
+                    })
+                    .collect();
+                self.memo
+                    .insert((Rc::as_ptr(state), context.clone()), values);
+            }
+            Task::Prefix(state, end, context) => {
+                let mut result = Vec::new();
+                if state.dot == 0 {
+                    result.push(Vec::new());
+                }
+
+                for derivation in state.derivations.borrow().iter() {
+                    let (previous, _) = derivation.previous(*end);
+                    let (previous_context, child_context) =
+                        derivation.contexts(state, *end, context);
+                    let values = match derivation {
+                        Derivation::Scan(previous, c) => {
+                            vec![(self.leaf)(previous.scanned_token(*c), end - 1)]
+                        }
+                        Derivation::Complete { child, .. } => {
+                            self.memo[&(Rc::as_ptr(child), child_context)].clone()
+                        }
+                    };
+
+                    let prefixes = &self.prefix_memo[&(Rc::as_ptr(previous), previous_context)];
+                    for children in prefixes {
+                        for value in values.iter() {
+                            let mut children = children.clone();
+                            children.push(value.clone());
+                            result.push(children);
+                        }
+                    }
+                }
+                self.prefix_memo
+                    .insert((Rc::as_ptr(state), context.clone()), result);
+            }
+        }
+    }
+}
+
+/// Counts the derivations of a finished state, like the number of values
+/// returned by a `Folder`, without building them. The counts saturate at `usize::MAX`.
+/// The cycles are cut like in a `Folder`.
+///
+/// Once counted, the derivations can be built one at a time by their index,
+/// without building the ones before them.
+pub struct Counter<'a, T> {
+    memo: HashMap<(*const EarleyState<'a, T>, Context<'a>), usize>,
+    prefix_memo: HashMap<(*const EarleyState<'a, T>, Context<'a>), usize>,
+}
+
+/// A step of building a derivation by its index.
+enum Step<'a, T> {
+    /// Builds the derivation of the finished state with the given index.
+    Build(Rc<EarleyState<'a, T>>, usize, usize, Context<'a>),
+    /// Builds the values of the symbols before the dot of the state,
+    /// for the prefix with the given index.
+    Prefix(Rc<EarleyState<'a, T>>, usize, usize, Context<'a>),
+    /// Builds the leaf of a scanned terminal, at the given position.
+    Leaf(Token<'a, T>, usize),
+    /// Builds the node of the finished state from the values built since
+    /// the given number of values.
+    Finish(Rc<EarleyState<'a, T>>, usize, usize),
+}
+
+impl<'a, T: Symbol> Counter<'a, T> {
+    pub fn new() -> Self {
+        Counter {
+            memo: HashMap::new(),
+            prefix_memo: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of derivations of the finished state, which belongs
+    /// to the set at index `end`.
+    pub fn count(&mut self, state: &Rc<EarleyState<'a, T>>, end: usize) -> usize {
+        evaluate(self, Task::Fold(state.clone(), end, Vec::new()));
+        self.memo[&(Rc::as_ptr(state), Vec::new())]
+    }
+
+    /// Builds the derivation with the given index of the finished state, which
+    /// belongs to the set at index `end` and must have been counted. The index
+    /// is below the count, and the derivations of a state are numbered by their
+    /// split and the rule of their child, like in `Folder::fold`.
+    pub fn build<V, L, N>(
+        &self,
+        state: &Rc<EarleyState<'a, T>>,
+        end: usize,
+        index: usize,
+        leaf: L,
+        node: N,
+    ) -> V
+    where
+        L: Fn(Token<'a, T>, usize) -> V,
+        N: Fn(&EarleyState<'a, T>, Vec<V>, usize) -> V,
+    {
+        // The steps are done from the top of the stack, so the steps that must
+        // be done first are pushed last.
+        let mut steps = vec![Step::Build(state.clone(), end, index, Vec::new())];
+        let mut values = Vec::new();
+        while let Some(step) = steps.pop() {
+            match step {
+                Step::Build(state, end, index, context) => {
+                    // A state that is cut has no derivations, so it is never built.
+                    let inner = inner_context(&state, &context)
+                        .expect("The index is above the number of derivations!");
+                    steps.push(Step::Finish(state.clone(), end, values.len()));
+                    steps.push(Step::Prefix(state, end, index, inner));
+                }
+                Step::Prefix(state, ..) if state.dot == 0 => {}
+                Step::Prefix(state, end, mut index, context) => {
+                    let derivations = state.derivations.borrow();
+                    let mut derivations: Vec<&Derivation<'a, T>> = derivations.iter().collect();
+                    derivations.sort_by_key(|derivation| derivation.order_key(end));
+
+                    for derivation in derivations {
+                        let (previous, split) = derivation.previous(end);
+                        let (previous_context, child_context) =
+                            derivation.contexts(&state, end, &context);
+                        let values = match derivation {
+                            Derivation::Scan(..) => 1,
+                            Derivation::Complete { child, .. } => {
+                                self.memo[&(Rc::as_ptr(child), child_context.clone())]
+                            }
+                        };
+                        let count = self.prefix_memo
+                            [&(Rc::as_ptr(previous), previous_context.clone())]
+                            .saturating_mul(values);
+                        if index >= count {
+                            index -= count;
+                            continue;
+                        }
+
+                        steps.push(match derivation {
+                            Derivation::Scan(previous, c) => {
+                                Step::Leaf(previous.scanned_token(*c), end - 1)
+                            }
+                            Derivation::Complete { child, .. } => {
+                                Step::Build(child.clone(), end, index % values, child_context)
+                            }
+                        });
+                        steps.push(Step::Prefix(
+                            previous.clone(),
+                            split,
+                            index / values,
+                            previous_context,
+                        ));
+                        break;
+                    }
+                }
+                Step::Leaf(token, position) => values.push(leaf(token, position)),
+                Step::Finish(state, end, start) => {
+                    let children = values.split_off(start);
+                    values.push(node(&state, children, end));
+                }
+            }
+        }
+        values
+            .pop()
+            .expect("The index is above the number of derivations!")
+    }
+}
+
+impl<'a, T: Symbol> Evaluator<'a, T> for Counter<'a, T> {
+    fn is_done(&self, task: &Task<'a, T>) -> bool {
+        match task.key() {
+            (true, state, context) => self.memo.contains_key(&(state, context.clone())),
+            (false, state, context) => self.prefix_memo.contains_key(&(state, context.clone())),
+        }
+    }
+
+    fn run(&mut self, task: &Task<'a, T>) {
+        match task {
+            Task::Fold(state, _, context) => {
+                let count = inner_context(state, context)
+                    .and_then(|inner| self.prefix_memo.get(&(Rc::as_ptr(state), inner)))
+                    .copied();
+                self.memo
+                    .insert((Rc::as_ptr(state), context.clone()), count.unwrap_or(0));
+            }
+            Task::Prefix(state, end, context) => {
+                let mut count: usize = if state.dot == 0 { 1 } else { 0 };
+                for derivation in state.derivations.borrow().iter() {
+                    let (previous, _) = derivation.previous(*end);
+                    let (previous_context, child_context) =
+                        derivation.contexts(state, *end, context);
+                    let values = match derivation {
+                        Derivation::Scan(..) => 1,
+                        Derivation::Complete { child, .. } => {
+                            self.memo[&(Rc::as_ptr(child), child_context)]
+                        }
+                    };
+                    let prefixes = self.prefix_memo[&(Rc::as_ptr(previous), previous_context)];
+                    count = count.saturating_add(prefixes.saturating_mul(values));
+                }
+                self.prefix_memo
+                    .insert((Rc::as_ptr(state), context.clone()), count);
+            }
+        }
+    }
+}
+
+/// Computes the values of the tasks, keeping the ones already computed.
+pub(super) trait Evaluator<'a, T> {
+    fn is_done(&self, task: &Task<'a, T>) -> bool;
+
+    /// Computes the values of the task, once all its dependencies are done.
+    fn run(&mut self, task: &Task<'a, T>);
+}
+
+/// Returns the tasks that must be done before the given one.
+///
+/// The dependencies never loop back to the task: a child that spans the same
+/// symbols as its parent has a longer context, and a cut state has no dependencies.
+fn dependencies<'a, T>(task: &Task<'a, T>) -> Vec<Task<'a, T>> {
+    match task {
+        Task::Fold(state, end, context) => match inner_context(state, context) {
+            Some(inner) => vec![Task::Prefix(state.clone(), *end, inner)],
+            None => Vec::new(),
+        },
+        Task::Prefix(state, ..) if state.dot == 0 => Vec::new(),
+        Task::Prefix(state, end, context) => {
+            let mut result = Vec::new();
+            for derivation in state.derivations.borrow().iter() {
+                let (previous, split) = derivation.previous(*end);
+                let (previous_context, child_context) = derivation.contexts(state, *end, context);
+                result.push(Task::Prefix(previous.clone(), split, previous_context));
+                if let Derivation::Complete { child, .. } = derivation {
+                    result.push(Task::Fold(child.clone(), *end, child_context));
+                }
+            }
+            result
+        }
+    }
+}
+
+/// Computes the values of the task, after the values it depends on.
+pub(super) fn evaluate<'a, T, E: Evaluator<'a, T>>(evaluator: &mut E, task: Task<'a, T>) {
+    // Each task is pushed with a flag that tells whether its dependencies
+    // were already pushed.
+    let mut stack = vec![(task, false)];
+
+    while let Some((task, expanded)) = stack.pop() {
+        if evaluator.is_done(&task) {
+            continue;
+        }
+        if expanded {
+            evaluator.run(&task);
+            continue;
+        }
+
+        let dependencies = dependencies(&task);
+        stack.push((task, true));
+        for dependency in dependencies.into_iter().rev() {
+            stack.push((dependency, false));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::*;
+
+/// Parses a word that is edited in place, like the text of an editor.
+///
+/// The parser keeps the earley table of the current word. Each set of the table
+/// depends only on the characters before its position, so after an edit only
+/// the sets from the position of the edit onwards are built again.
+pub struct IncrementalParser<'a> {
+    grammar: &'a Grammar<'a>,
+    input: Vec<char>,
+    table: EarleyTable<'a>,
+}
+
+impl<'a> IncrementalParser<'a> {
+    /// Builds the table of the given word.
+    pub fn new(grammar: &'a Grammar<'a>, s: &str) -> Self {
+        IncrementalParser {
+            grammar,
+            input: s.chars().collect(),
+            table: grammar.build_table(s),
+        }
+    }
+
+    /// Replaces the `removed` characters starting at character position `at`
+    /// with the inserted string, and updates the table.
+    ///
+    /// Panics if the removed characters go past the end of the word.
+    pub fn edit(&mut self, at: usize, removed: usize, inserted: &str) {
+        assert!(
+            at + removed <= self.input.len(),
+            "The edit is out of the word!"
+        );
+        self.input.splice(at..at + removed, inserted.chars());
+
+        // The set at `at` is kept: the states scanned into it come from the
+        // character before the edit, which didn't change.
+        self.table.resize(at + 1);
+        self.table.resize(self.input.len() + 1);
+        self.grammar
+            .fill_table(&mut self.table, &self.input, at, TableOptions::default())
+            .expect("The table has no limit!");
+    }
+
+    /// Returns the current word.
+    pub fn text(&self) -> String {
+        self.input.iter().collect()
+    }
+
+    /// Returns true if the current word belongs to the language of the grammar.
+    pub fn recognizes(&self) -> bool {
+        !self.grammar.accepting_states(&self.table).is_empty()
+    }
+
+    /// Returns the parse trees of the current word, like `Grammar::parse`.
+    pub fn parse(&self) -> Vec<Rc<ParseNode<'a>>> {
+        self.grammar.parse_trees(&self.table, self.input.len())
+    }
+}
@@ -0,0 +1,255 @@
+use super::*;
+use std::cmp::Ordering;
+
+/// A derivation of a sequence of symbols, along with its cost and the indices
+/// of the rules used by its leftmost derivation, which break the ties between
+/// derivations of equal cost.
+#[derive(Clone)]
+struct Scored<'a> {
+    cost: f64,
+    derivation: Vec<usize>,
+    trees: Vec<Rc<ParseNode<'a>>>,
+}
+
+impl Scored<'_> {
+    fn compare(&self, other: &Self) -> Ordering {
+        self.cost
+            .total_cmp(&other.cost)
+            .then_with(|| self.derivation.cmp(&other.derivation))
+    }
+}
+
+/// Finds the k cheapest derivations of the finished states of an earley table.
+/// The cost of a derivation is the sum of the costs of the rules it uses
+/// and of the costs of its terminals.
+///
+/// The cost of a derivation only grows when one of its parts is replaced with
+/// a more expensive one, so only the k cheapest derivations of every state are
+/// needed to build the k cheapest derivations of the states that use it.
+/// Each state keeps at most k derivations, instead of all of them.
+///
+/// The forest is walked and its cycles are cut like in a `forest::Folder`.
+pub struct KBest<'g, 'a, C> {
+    grammar: &'g Grammar<'a>,
+    k: usize,
+    rule_cost: C,
+    leaf_cost: f64,
+    // The derivations of the finished states, and the derivations of the
+    // symbols before the dot of all the states, keyed by the address of the state
+    // and by the context of the task.
+    memo: HashMap<(*const EarleyState<'a>, forest::Context<'a>), Vec<Scored<'a>>>,
+    prefix_memo: HashMap<(*const EarleyState<'a>, forest::Context<'a>), Vec<Scored<'a>>>,
+}
+
+impl<'g, 'a, C> KBest<'g, 'a, C>
+where
+    C: Fn(usize) -> f64,
+{
+    /// `rule_cost` returns the cost of the rule with the given index.
+    pub fn new(grammar: &'g Grammar<'a>, k: usize, rule_cost: C, leaf_cost: f64) -> Self {
+        KBest {
+            grammar,
+            k,
+            rule_cost,
+            leaf_cost,
+            memo: HashMap::new(),
+            prefix_memo: HashMap::new(),
+        }
+    }
+
+    /// Sorts the derivations and keeps the k cheapest ones.
+    fn keep_best(&self, mut values: Vec<Scored<'a>>) -> Vec<Scored<'a>> {
+        values.sort_by(|a, b| a.compare(b));
+        values.truncate(self.k);
+        values
+    }
+
+    /// Returns the k cheapest derivations of the finished states of the table that
+    /// belong to the set at index `end`, along with their costs, from the cheapest.
+    pub fn best(
+        &mut self,
+        states: &[&Rc<EarleyState<'a>>],
+        end: usize,
+    ) -> Vec<(Rc<ParseNode<'a>>, f64)> {
+        let mut values = Vec::new();
+        for state in states {
+            forest::evaluate(self, forest::Task::Fold((*state).clone(), end, Vec::new()));
+            values.extend(self.memo[&(Rc::as_ptr(state), Vec::new())].iter().cloned());
+        }
+
+        self.keep_best(values)
+            .into_iter()
+            .map(|mut value| (value.trees.pop().unwrap(), value.cost))
+            .collect()
+    }
+}
+
+impl<'a, C> forest::Evaluator<'a, char> for KBest<'_, 'a, C>
+where
+    C: Fn(usize) -> f64,
+{
+    fn is_done(&self, task: &forest::Task<'a, char>) -> bool {
+        match task.key() {
+            (true, state, context) => self.memo.contains_key(&(state, context.clone())),
+            (false, state, context) => self.prefix_memo.contains_key(&(state, context.clone())),
+        }
+    }
+
+    /// Keeps the k cheapest derivations of the finished state, each one holding
+    /// a single tree, or the k cheapest derivations of the symbols before the dot.
+    fn run(&mut self, task: &forest::Task<'a, char>) {
+        match task {
+            forest::Task::Fold(state, end, context) => {
+                let index = self.grammar.rule_index(state.rule);
+                let cost = (self.rule_cost)(index);
+                let values: Vec<Scored<'a>> = forest::inner_context(state, context)
+                    .and_then(|inner| self.prefix_memo.get(&(Rc::as_ptr(state), inner)))
+                    .into_iter()
+                    .flatten()
+                    .map(|children| {
+                        let mut derivation = vec![index];
+                        derivation.extend(children.derivation.iter().copied());
+                        let tree = Rc::new(ParseNode {
+                            token: Token::NT(state.rule.from),
+                            children: children.trees.clone(),
+                            start: state.origin,
+                            end: *end,
+                            rule: Some(&state.rule.to[..]),
+                        });
+                        Scored {
+                            cost: cost + children.cost,
+                            derivation,
+                            trees: vec![tree],
+                        }
+                    })
+                    .collect();
+
+                // Adding the same cost to every derivation keeps them sorted.
+                self.memo
+                    .insert((Rc::as_ptr(state), context.clone()), values);
+            }
+            forest::Task::Prefix(state, end, context) => {
+                let mut result = Vec::new();
+                if state.dot == 0 {
+                    result.push(Scored {
+                        cost: 0.0,
+                        derivation: Vec::new(),
+                        trees: Vec::new(),
+                    });
+                }
+
+                for derivation in state.derivations.borrow().iter() {
+                    let (previous, _) = derivation.previous(*end);
+                    let (previous_context, child_context) =
+                        derivation.contexts(state, *end, context);
+                    let values = match derivation {
+                        Derivation::Scan(previous, c) => {
+                            let leaf = Rc::new(ParseNode {
+                                token: previous.scanned_token(*c),
+                                children: Vec::new(),
+                                start: end - 1,
+                                end: *end,
+                                rule: None,
+                            });
+                            vec![Scored {
+                                cost: self.leaf_cost,
+                                derivation: Vec::new(),
+                                trees: vec![leaf],
+                            }]
+                        }
+                        Derivation::Complete { child, .. } => {
+                            self.memo[&(Rc::as_ptr(child), child_context)].clone()
+                        }
+                    };
+
+                    for children in &self.prefix_memo[&(Rc::as_ptr(previous), previous_context)] {
+                        for value in values.iter() {
+                            let mut derivation = children.derivation.clone();
+                            derivation.extend(value.derivation.iter().copied());
+                            let mut trees = children.trees.clone();
+                            trees.extend(value.trees.iter().cloned());
+                            result.push(Scored {
+                                cost: children.cost + value.cost,
+                                derivation,
+                                trees,
+                            });
+                        }
+                    }
+                }
+
+                let result = self.keep_best(result);
+                self.prefix_memo
+                    .insert((Rc::as_ptr(state), context.clone()), result);
+            }
+        }
+    }
+}
+
+impl<'a> Grammar<'a> {
+    /// Returns at most k derivation trees of the word, with the fewest nodes first.
+    /// Trees with the same number of nodes are ordered like in `parse`.
+    ///
+    /// Only the k smallest derivations of every state are built, so this stays fast
+    /// on very ambiguous grammars when k is small.
+    pub fn parse_k_shortest(&self, s: &str, k: usize) -> Vec<Rc<ParseNode<'_>>> {
+        let table = self.build_table(s);
+        let states = self.accepting_states(&table);
+
+        KBest::new(self, k, |_| 1.0, 1.0)
+            .best(&states, s.chars().count())
+            .into_iter()
+            .map(|(tree, _)| tree)
+            .collect()
+    }
+
+    /// Returns the derivation tree of the word with the fewest nodes, or None if the
+    /// word can't be derived. Among the smallest trees, the first one of `parse` is returned.
+    ///
+    /// This is `parse_k_shortest` for a single tree, so the other trees are never built.
+    pub fn parse_smallest(&self, s: &str) -> Option<Rc<ParseNode<'_>>> {
+        self.parse_k_shortest(s, 1).pop()
+    }
+
+    /// Returns the `n` cheapest derivation trees of the word, along with their costs,
+    /// from the cheapest. The cost of a tree is the sum of the costs of the rules it
+    /// uses, where `costs[i]` is the cost of `self.rules[i]`. Trees of equal cost
+    /// are ordered like in `parse`.
+    ///
+    /// Like `parse_k_shortest`, only the `n` cheapest derivations of every state
+    /// are built. With a cyclic grammar, the trees are the ones of `parse`, whose
+    /// cycles are cut, so the costs can be negative.
+    ///
+    /// # Panics
+    /// Panics if there is not exactly one cost for every rule.
+    pub fn parse_best_n(&self, s: &str, costs: &[f64], n: usize) -> Vec<(Rc<ParseNode<'_>>, f64)> {
+        assert_eq!(
+            costs.len(),
+            self.rules.len(),
+            "there must be one cost for every rule"
+        );
+        let table = self.build_table(s);
+        let states = self.accepting_states(&table);
+
+        KBest::new(self, n, |index| costs[index], 0.0).best(&states, s.chars().count())
+    }
+
+    /// Returns the first `max_trees` trees that `parse` would return, and whether
+    /// the word has more trees than that.
+    ///
+    /// With every derivation costing the same, the cheapest derivations are the first
+    /// ones in the order of `parse`, so only `max_trees + 1` trees are built for each state.
+    pub fn parse_limited(&self, s: &str, max_trees: usize) -> (Vec<Rc<ParseNode<'_>>>, bool) {
+        let table = self.build_table(s);
+        let states = self.accepting_states(&table);
+
+        let mut trees: Vec<Rc<ParseNode>> =
+            KBest::new(self, max_trees.saturating_add(1), |_| 0.0, 0.0)
+                .best(&states, s.chars().count())
+                .into_iter()
+                .map(|(tree, _)| tree)
+                .collect();
+        let truncated = trees.len() > max_trees;
+        trees.truncate(max_trees);
+        (trees, truncated)
+    }
+}
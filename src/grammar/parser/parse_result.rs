@@ -0,0 +1,120 @@
+use super::*;
+
+/// The earley table built for a word, from which the derivations of the word
+/// can be counted or built, without building the table again.
+pub struct ParseResult<'a> {
+    grammar: &'a Grammar<'a>,
+    table: EarleyTable<'a>,
+    last: usize,
+}
+
+impl<'a> ParseResult<'a> {
+    /// Returns the number of derivations of the word, without building them.
+    /// This is the number of trees returned by `trees`. The count saturates
+    /// at `usize::MAX`.
+    pub fn count(&self) -> usize {
+        self.grammar
+            .accepting_states(&self.table)
+            .into_iter()
+            .fold(0, |total, state| {
+                total.saturating_add(forest::Counter::new().count(state, self.last))
+            })
+    }
+
+    /// Returns an iterator over the parse trees of the word, which builds
+    /// each tree only when it is reached, so the first trees of a very ambiguous
+    /// word can be read without building all of them.
+    ///
+    /// These are the trees of `Grammar::parse`, but they are not sorted:
+    /// they come in the order of their derivations in the table, which is always
+    /// the same. Rules that differ only by their labels give one tree each.
+    pub fn trees(&self) -> impl Iterator<Item = Rc<ParseNode<'a>>> + '_ {
+        let mut states = self.grammar.accepting_states(&self.table);
+        states.sort_by_key(|state| self.grammar.rule_index(state.rule));
+
+        // Each accepting state is counted on its own, like in `count`.
+        states.into_iter().flat_map(move |state| {
+            let mut counter = forest::Counter::new();
+            let count = counter.count(state, self.last);
+            (0..count).map(move |index| {
+                parse_tree::build_nth_parse_tree(&counter, state, 0, self.last, index)
+            })
+        })
+    }
+}
+
+/// The error returned when a word has more derivations than allowed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooAmbiguous {
+    /// The number of derivations of the word, counted like `ParseResult::count`.
+    pub count: usize,
+}
+
+/// What a word parses to, returned by `Grammar::parse_summary`. The summaries
+/// of two words are equal if the words are parsed the same way, so a change
+/// of a word or a grammar that changes its parses can be found by comparing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParseSummary {
+    /// The number of derivations of the word, counted like `ParseResult::count`.
+    pub derivations: usize,
+    /// True if the word belongs to the language of the grammar.
+    pub accepted: bool,
+    /// The length of the longest prefix of the word that is the beginning of
+    /// a word of the language, like `Grammar::longest_prefix`.
+    pub longest_prefix: usize,
+    /// A hash of the set of parse trees. It is computed with FNV-1a over the
+    /// trees written like their `Debug` output, sorted, so it only depends on
+    /// the names and characters of the trees, and can be saved and compared
+    /// with the summaries of later runs.
+    pub trees_hash: u64,
+}
+
+/// Hashes the bytes with the 64-bit FNV-1a algorithm.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    })
+}
+
+impl<'a> Grammar<'a> {
+    /// Returns a summary of how the word is parsed, from a single earley table.
+    pub fn parse_summary(&self, s: &str) -> ParseSummary {
+        let result = self.parse_all(s);
+        let mut written: Vec<String> = result.trees().map(|tree| format!("{:?}", tree)).collect();
+        written.sort();
+        written.dedup();
+        ParseSummary {
+            derivations: result.count(),
+            accepted: !written.is_empty(),
+            longest_prefix: self.furthest_position(&result.table),
+            trees_hash: fnv1a(written.join("\n").as_bytes()),
+        }
+    }
+
+    /// Returns the parse trees of the word, like `parse`, or an error if the word
+    /// has more than `max` derivations. The derivations are counted before any
+    /// tree is built, so a very ambiguous word costs only the earley table.
+    pub fn parse_max_ambiguity(
+        &self,
+        s: &str,
+        max: usize,
+    ) -> Result<Vec<Rc<ParseNode<'_>>>, TooAmbiguous> {
+        let result = self.parse_all(s);
+        match result.count() {
+            count if count > max => Err(TooAmbiguous { count }),
+            _ => Ok(self.parse_trees(&result.table, result.last)),
+        }
+    }
+
+    /// Builds the earley table for the word once, so that its derivations
+    /// can be both counted and built, like for a message such as "Got 14 derivation trees".
+    pub fn parse_all(&self, s: &str) -> ParseResult<'_> {
+        ParseResult {
+            grammar: self,
+            table: self.build_table(s),
+            last: s.chars().count(),
+        }
+    }
+}
@@ -1,51 +1,613 @@
 use super::*;
 use std::{
-    fmt,
-    io::{Error, ErrorKind, Write},
+    collections::HashMap,
+    fmt, fs,
+    io::{Error, Write},
+    ops::Deref,
     process::{Command, Stdio},
+    sync::Arc,
+    thread,
 };
 
 /// A parse node consists of a token and a list of child nodes.
 /// For leaf nodes, the token is a Terminal.
 /// For non-leaf nodes, the token is a Nonterminal.
-pub struct ParseNode<'a> {
-    pub token: Token<'a>,
-    pub children: Vec<Rc<ParseNode<'a>>>,
+///
+/// Each node also records the span of the input it covers: the characters
+/// in the range `start..end`. Offsets are character positions, not bytes.
+/// The children are in the order of the right side of the rule, so their spans
+/// follow each other from the start of the node to its end.
+///
+/// The nonterminal nodes also hold the right side of the rule that derived them,
+/// which tells apart the rules of a nonterminal without looking at the children.
+/// Two trees are equal if they have the same tokens, whatever their rules.
+pub struct ParseNode<'a, T = char> {
+    pub token: Token<'a, T>,
+    pub children: Vec<Rc<ParseNode<'a, T>>>,
+    pub start: usize,
+    pub end: usize,
+    pub rule: Option<&'a [Token<'a, T>]>,
 }
 
-pub fn build_parse_tree<'a>(state: &EarleyState<'a>) -> Rc<ParseNode<'a>> {
-    let node_token = Token::NT(state.rule.from);
+/// The λ drawn under the nonterminals that derive the empty word is not a node:
+/// such a nonterminal has height 1 and no leaves, like a terminal with no content.
+impl<'a, T: Symbol> ParseNode<'a, T> {
+    /// Returns the name of the nonterminal of the node, or None for a leaf
+    /// that holds a terminal.
+    pub fn as_nonterminal(&self) -> Option<&'a str> {
+        match self.token {
+            Token::NT(n) => Some(n.name),
+            Token::T(_) => None,
+        }
+    }
+
+    /// Returns the symbol of the input matched by the node, or None for a nonterminal.
+    pub fn as_terminal(&self) -> Option<T> {
+        match self.token {
+            Token::T(t) => t.content(),
+            Token::NT(_) => None,
+        }
+    }
+
+    /// Returns true if the node has no children. Besides the terminals, this is
+    /// the case of the nonterminals that derive the empty word.
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
 
-    let mut node_children = Vec::new();
-    let mut state_children = state.children.iter();
+    /// Returns the number of nodes on the longest path from this node to a leaf.
+    pub fn height(&self) -> usize {
+        fold_bottom_up(self, children_of, |_, children: Vec<usize>| {
+            1 + children.into_iter().max().unwrap_or(0)
+        })
+    }
 
-    // For each terminal symbol in the production, create a new leaf parse node.
-    // For each nonterminal symbol in the production, get the corresponding state
-    // by advancing the state_children iterator.
-    for token in state.rule.to.iter() {
-        let node_child = match token {
-            terminal @ Token::T(_) => Rc::new(ParseNode {
-                token: *terminal,
-                children: Vec::new(),
-            }),
+    /// Returns the number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        fold_bottom_up(self, children_of, |_, children: Vec<usize>| {
+            1 + children.into_iter().sum::<usize>()
+        })
+    }
+
+    /// Folds the tree bottom-up: `f` receives the token of each node and the
+    /// values of its children, already folded, and returns the value of the node.
+    /// Terminals and the nonterminals that derive the empty word both receive
+    /// no values, and can be told apart by their token.
+    pub fn fold<R, F>(&self, f: &F) -> R
+    where
+        F: Fn(Token<'a, T>, Vec<R>) -> R,
+    {
+        fold_bottom_up(self, children_of, |node, children| f(node.token, children))
+    }
+
+    /// Returns the nodes of the nonterminal with the given name, in pre-order:
+    /// each node comes before the nodes in its subtree, which come before the
+    /// nodes of its right siblings. Terminals never match.
+    pub fn find_all(&self, symbol: &str) -> Vec<&ParseNode<'a, T>> {
+        self.preorder()
+            .filter(|node| node.as_nonterminal() == Some(symbol))
+            .collect()
+    }
+
+    /// Returns the first node of the nonterminal with the given name, in pre-order.
+    pub fn find_first(&self, symbol: &str) -> Option<&ParseNode<'a, T>> {
+        self.preorder()
+            .find(|node| node.as_nonterminal() == Some(symbol))
+    }
 
-            Token::NT(_) => build_parse_tree(state_children.next().unwrap()),
+    /// Returns the nodes of the tree in pre-order.
+    pub(super) fn preorder(&self) -> impl Iterator<Item = &ParseNode<'a, T>> {
+        let mut stack = vec![self];
+        std::iter::from_fn(move || {
+            let node = stack.pop()?;
+            stack.extend(node.children.iter().rev().map(|child| &**child));
+            Some(node)
+        })
+    }
+
+    /// Returns the number of terminals in the tree, which is the length
+    /// of the word it derives.
+    pub fn leaf_count(&self) -> usize {
+        self.preorder()
+            .filter(|node| matches!(node.token, Token::T(_)))
+            .count()
+    }
+}
+
+fn children_of<'n, 'a, T>(node: &'n ParseNode<'a, T>) -> &'n [Rc<ParseNode<'a, T>>] {
+    &node.children
+}
+
+/// Folds the tree bottom-up: `f` receives each node and the values of its children,
+/// in order, and returns the value of the node. `children` returns the children of
+/// a node, so that trees with `Rc` and `Arc` children can both be folded.
+///
+/// The tree is walked with an explicit stack instead of recursion, so that
+/// very deep trees don't overflow the native stack.
+fn fold_bottom_up<'n, N, P, R>(
+    root: &'n N,
+    children: fn(&'n N) -> &'n [P],
+    mut f: impl FnMut(&'n N, Vec<R>) -> R,
+) -> R
+where
+    P: Deref<Target = N> + 'n,
+{
+    // Each node is pushed with a flag that tells whether its children were pushed.
+    // The values of the children are on top of `values` once they are all folded.
+    let mut stack = vec![(root, false)];
+    let mut values = Vec::new();
+    while let Some((node, expanded)) = stack.pop() {
+        let nodes = children(node);
+        if expanded {
+            let children = values.split_off(values.len() - nodes.len());
+            values.push(f(node, children));
+        } else {
+            stack.push((node, true));
+            stack.extend(nodes.iter().rev().map(|child| (&**child, false)));
+        }
+    }
+    values.pop().unwrap()
+}
+
+/// The default drop would recurse once for each level of the tree. Instead, the
+/// children that no other tree shares are moved to a list, and dropped one at a time.
+impl<T> Drop for ParseNode<'_, T> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(child) = stack.pop() {
+            if let Some(mut child) = Rc::into_inner(child) {
+                stack.append(&mut child.children);
+            }
+        }
+    }
+}
+
+/// Returns the tree without the nonterminals that derive the empty word, except
+/// for the root. The nodes keep the rules that derived them, so the children of a
+/// node can be fewer than the symbols of its rule. Unchanged subtrees are shared.
+pub fn prune_empty<'a, T: Symbol>(root: &Rc<ParseNode<'a, T>>) -> Rc<ParseNode<'a, T>> {
+    // The value of a node is its new subtree, or None if the subtree is unchanged.
+    let pruned = fold_bottom_up(&**root, children_of, |node, pruned: Vec<Option<_>>| {
+        let unchanged = pruned.iter().all(Option::is_none)
+            && node.children.iter().all(|child| child.start < child.end);
+        if unchanged {
+            return None;
+        }
+        // Terminals always cover a character, so the empty subtrees are the empty nonterminals.
+        let children = node
+            .children
+            .iter()
+            .zip(pruned)
+            .filter(|(child, _)| child.start < child.end)
+            .map(|(child, pruned)| pruned.unwrap_or_else(|| child.clone()))
+            .collect();
+        Some(Rc::new(ParseNode {
+            token: node.token,
+            children,
+            start: node.start,
+            end: node.end,
+            rule: node.rule,
+        }))
+    });
+    pruned.unwrap_or_else(|| root.clone())
+}
+
+/// Returns the tree where each chain of nonterminals with a single nonterminal
+/// child, like `NP -> N -> fish`, is collapsed into one node. The node keeps the
+/// label of the first nonterminal of the chain, and the children and the rule of
+/// the last one, like `NP[fish]`. Unchanged subtrees are shared.
+pub fn collapse_unit_chains<'a, T: Symbol>(root: &Rc<ParseNode<'a, T>>) -> Rc<ParseNode<'a, T>> {
+    // The value of a node is its new subtree, or None if the subtree is unchanged.
+    let collapsed = fold_bottom_up(&**root, children_of, |node, collapsed: Vec<Option<_>>| {
+        let new = |child: &Rc<ParseNode<'a, T>>, collapsed: Option<Rc<ParseNode<'a, T>>>| {
+            collapsed.unwrap_or_else(|| child.clone())
+        };
+        // The single child is already collapsed, so it holds the children
+        // and the rule of the last nonterminal of the chain.
+        let (children, rule) = match &node.children[..] {
+            [child] if child.as_nonterminal().is_some() => {
+                let last = new(child, collapsed.into_iter().next().unwrap());
+                (last.children.clone(), last.rule)
+            }
+            _ if collapsed.iter().all(Option::is_none) => return None,
+            _ => {
+                let children = node.children.iter().zip(collapsed);
+                (
+                    children
+                        .map(|(child, collapsed)| new(child, collapsed))
+                        .collect(),
+                    node.rule,
+                )
+            }
         };
+        Some(Rc::new(ParseNode {
+            token: node.token,
+            children,
+            start: node.start,
+            end: node.end,
+            rule,
+        }))
+    });
+    collapsed.unwrap_or_else(|| root.clone())
+}
 
-        node_children.push(node_child);
+impl ParseNode<'_> {
+    /// Returns the part of the input covered by the tree, given the input it was parsed from.
+    /// The spans count characters, so they are converted to byte offsets first.
+    pub fn span_text<'s>(&self, input: &'s str) -> &'s str {
+        let offset = |position| {
+            input
+                .char_indices()
+                .nth(position)
+                .map_or(input.len(), |(offset, _)| offset)
+        };
+        &input[offset(self.start)..offset(self.end)]
     }
+}
 
-    Rc::new(ParseNode {
-        token: node_token,
-        children: node_children,
-    })
+/// A parse node that can be sent to other threads: the same as [`ParseNode`],
+/// but the children are held by `Arc` instead of `Rc`.
+///
+/// Cloning and dropping an `Arc` updates its count with atomic operations,
+/// which are slower than the plain increments of `Rc`. The chart is still built
+/// with `Rc`, and the trees are only converted at the end, so this costs
+/// a copy of every node of the trees.
+pub struct SyncParseNode<'a, T = char> {
+    pub token: Token<'a, T>,
+    pub children: Vec<Arc<SyncParseNode<'a, T>>>,
+    pub start: usize,
+    pub end: usize,
+    pub rule: Option<&'a [Token<'a, T>]>,
+}
+
+impl<'a, T: Symbol> SyncParseNode<'a, T> {
+    /// Converts the trees, keeping the subtrees they share shared.
+    pub fn from_trees(trees: &[Rc<ParseNode<'a, T>>]) -> Vec<Arc<SyncParseNode<'a, T>>> {
+        let mut converted = HashMap::new();
+        trees
+            .iter()
+            .map(|tree| {
+                convert_shared(&**tree, children_of, &mut converted, |node, children| {
+                    Arc::new(SyncParseNode {
+                        token: node.token,
+                        children,
+                        start: node.start,
+                        end: node.end,
+                        rule: node.rule,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    /// Converts the trees back to [`ParseNode`] trees, the inverse of
+    /// [`SyncParseNode::from_trees`]. The shared subtrees stay shared.
+    pub fn to_trees(trees: &[Arc<SyncParseNode<'a, T>>]) -> Vec<Rc<ParseNode<'a, T>>> {
+        let mut converted = HashMap::new();
+        trees
+            .iter()
+            .map(|tree| {
+                convert_shared(
+                    &**tree,
+                    sync_children_of,
+                    &mut converted,
+                    |node, children| {
+                        Rc::new(ParseNode {
+                            token: node.token,
+                            children,
+                            start: node.start,
+                            end: node.end,
+                            rule: node.rule,
+                        })
+                    },
+                )
+            })
+            .collect()
+    }
+
+    /// Folds the tree bottom-up, like [`ParseNode::fold`].
+    pub fn fold<R, F>(&self, f: &F) -> R
+    where
+        F: Fn(Token<'a, T>, Vec<R>) -> R,
+    {
+        fold_bottom_up(self, sync_children_of, |node, children| {
+            f(node.token, children)
+        })
+    }
+}
+
+fn sync_children_of<'n, 'a, T>(node: &'n SyncParseNode<'a, T>) -> &'n [Arc<SyncParseNode<'a, T>>] {
+    &node.children
+}
+
+/// Converts the tree bottom-up like `fold_bottom_up`, except that the nodes already
+/// converted, keyed by their address, are reused along with their subtrees.
+fn convert_shared<'n, N, P, R: Clone>(
+    root: &'n N,
+    children: fn(&'n N) -> &'n [P],
+    converted: &mut HashMap<*const N, R>,
+    mut f: impl FnMut(&'n N, Vec<R>) -> R,
+) -> R
+where
+    P: Deref<Target = N> + 'n,
+{
+    let mut stack = vec![(root, false)];
+    let mut values = Vec::new();
+    while let Some((node, expanded)) = stack.pop() {
+        let nodes = children(node);
+        if expanded {
+            let children = values.split_off(values.len() - nodes.len());
+            let value = f(node, children);
+            converted.insert(node as *const N, value.clone());
+            values.push(value);
+        } else if let Some(value) = converted.get(&(node as *const N)) {
+            values.push(value.clone());
+        } else {
+            stack.push((node, true));
+            stack.extend(nodes.iter().rev().map(|child| (&**child, false)));
+        }
+    }
+    values.pop().unwrap()
 }
 
-fn write_subtree_to_dot<'a, W>(
+/// Drops the children with an explicit stack, like the `Drop` of [`ParseNode`].
+impl<T> Drop for SyncParseNode<'_, T> {
+    fn drop(&mut self) {
+        let mut stack = std::mem::take(&mut self.children);
+        while let Some(child) = stack.pop() {
+            if let Some(mut child) = Arc::into_inner(child) {
+                stack.append(&mut child.children);
+            }
+        }
+    }
+}
+
+/// Writes the tree on one line, with the children of each nonterminal in brackets
+/// after it, like `EXP[EXP[n] + EXP[n]]`. A nonterminal that derives the empty word
+/// is written with a λ as its only child, like `S[λ]`.
+impl<T: Symbol> fmt::Debug for ParseNode<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The nodes still to write, and the separators between them.
+        let mut stack = vec![Ok(self)];
+        while let Some(item) = stack.pop() {
+            let node = match item {
+                Ok(node) => node,
+                Err(separator) => {
+                    f.write_str(separator)?;
+                    continue;
+                }
+            };
+            match node.token {
+                Token::T(_) => write!(f, "{}", node.token)?,
+                Token::NT(_) if node.children.is_empty() => write!(f, "{}[\u{03BB}]", node.token)?,
+                Token::NT(_) => {
+                    write!(f, "{}[", node.token)?;
+                    stack.push(Err("]"));
+                    for (index, child) in node.children.iter().enumerate().rev() {
+                        stack.push(Ok(&**child));
+                        if index > 0 {
+                            stack.push(Err(" "));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Two trees are equal if they have the same shape and the same tokens.
+/// The spans are not compared: trees of the same word that have the same shape
+/// also have the same spans.
+///
+/// The trees are compared and hashed iteratively, so deep trees don't overflow the stack.
+impl<T: Symbol> PartialEq for ParseNode<'_, T> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if std::ptr::eq(a, b) {
+                continue;
+            }
+            if a.token != b.token || a.children.len() != b.children.len() {
+                return false;
+            }
+            for (x, y) in a.children.iter().zip(b.children.iter()) {
+                stack.push((x, y));
+            }
+        }
+        true
+    }
+}
+
+impl<T: Symbol> Eq for ParseNode<'_, T> {}
+
+impl<T: Symbol> Hash for ParseNode<'_, T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        // The tokens in preorder, along with the number of children of each node,
+        // determine the shape of the tree.
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            node.token.hash(state);
+            node.children.len().hash(state);
+            for child in node.children.iter().rev() {
+                stack.push(child);
+            }
+        }
+    }
+}
+
+/// Returns the rules applied by the leftmost derivation of the tree, in order,
+/// written like `A -> x Y z`. Applying them one after the other to the leftmost
+/// nonterminal, starting from the root, gives the word of the tree.
+pub fn leftmost_derivation<T: Symbol>(root: &ParseNode<T>) -> Vec<String> {
+    // The rules of the leftmost derivation are the nonterminals in pre-order.
+    root.preorder()
+        .filter(|node| node.as_nonterminal().is_some())
+        .map(|node| {
+            let mut production = format!("{} ->", node.token);
+            for child in node.children.iter() {
+                production.push_str(&format!(" {}", child.token));
+            }
+            production
+        })
+        .collect()
+}
+
+/// Builds the parse trees of all the derivations of a finished state
+/// that belongs to the set at index `end`. The spans of the nodes are shifted
+/// by `offset`, for tables built over a part of the input.
+pub fn build_parse_trees<'a, T: Symbol>(
+    state: &Rc<EarleyState<'a, T>>,
+    offset: usize,
+    end: usize,
+) -> Vec<Rc<ParseNode<'a, T>>> {
+    tree_folder(offset).fold(state, end)
+}
+
+/// Returns a folder that turns the derivations of the states into parse trees,
+/// whose spans are shifted by `offset`.
+#[allow(clippy::type_complexity)]
+pub fn tree_folder<'a, T: Symbol>(
+    offset: usize,
+) -> forest::Folder<
+    'a,
+    T,
+    Rc<ParseNode<'a, T>>,
+    impl Fn(Token<'a, T>, usize) -> Rc<ParseNode<'a, T>>,
+    impl Fn(&EarleyState<'a, T>, Vec<Rc<ParseNode<'a, T>>>, usize) -> Rc<ParseNode<'a, T>>,
+> {
+    let (leaf, node) = tree_builders(offset);
+    forest::Folder::new(leaf, node)
+}
+
+/// Builds the parse tree of the derivation with the given index of a finished
+/// state that belongs to the set at index `end`, once counted by the counter.
+/// The spans of the nodes are shifted by `offset`.
+pub fn build_nth_parse_tree<'a, T: Symbol>(
+    counter: &forest::Counter<'a, T>,
+    state: &Rc<EarleyState<'a, T>>,
+    offset: usize,
+    end: usize,
+    index: usize,
+) -> Rc<ParseNode<'a, T>> {
+    let (leaf, node) = tree_builders(offset);
+    counter.build(state, end, index, leaf, node)
+}
+
+/// Returns the functions that build the leaves and the nodes of the parse trees,
+/// whose spans are shifted by `offset`.
+#[allow(clippy::type_complexity)]
+fn tree_builders<'a, T: Symbol>(
+    offset: usize,
+) -> (
+    impl Fn(Token<'a, T>, usize) -> Rc<ParseNode<'a, T>>,
+    impl Fn(&EarleyState<'a, T>, Vec<Rc<ParseNode<'a, T>>>, usize) -> Rc<ParseNode<'a, T>>,
+) {
+    let leaf = move |token, position| {
+        Rc::new(ParseNode {
+            token,
+            children: Vec::new(),
+            start: offset + position,
+            end: offset + position + 1,
+            rule: None,
+        })
+    };
+    let node = move |state: &EarleyState<'a, T>, children: Vec<Rc<ParseNode<'a, T>>>, end| {
+        // Each derivation moves the dot one symbol to the right, so the children
+        // are built from left to right.
+        debug_assert!(children
+            .iter()
+            .zip(state.rule.to.iter())
+            .all(|(child, &token)| match (token, child.token) {
+                (Token::NT(expected), Token::NT(found)) => expected == found,
+                // The leaves can hold the matched symbols instead of the terminals.
+                (Token::T(_), Token::T(_)) => true,
+                _ => false,
+            }));
+        debug_assert!(children.windows(2).all(|pair| pair[0].end == pair[1].start));
+        Rc::new(ParseNode {
+            token: Token::NT(state.rule.from),
+            children,
+            start: offset + state.origin,
+            end: offset + end,
+            rule: Some(&state.rule.to[..]),
+        })
+    };
+
+    (leaf, node)
+}
+
+/// The attributes of a kind of node in the DOT output.
+#[derive(Clone)]
+pub struct NodeStyle {
+    /// The Graphviz shape of the node, like `box` or `ellipse`.
+    pub shape: String,
+    /// The Graphviz color of the node, like `black` or `#808080`.
+    pub color: String,
+}
+
+impl NodeStyle {
+    pub fn new(shape: &str, color: &str) -> Self {
+        NodeStyle {
+            shape: shape.to_string(),
+            color: color.to_string(),
+        }
+    }
+}
+
+/// The styles of the terminals, the nonterminals, and the lambda
+/// children of the nonterminals that derive the empty word.
+#[derive(Clone)]
+pub struct DotStyle {
+    pub terminal: NodeStyle,
+    pub nonterminal: NodeStyle,
+    pub epsilon: NodeStyle,
+}
+
+impl Default for DotStyle {
+    /// Terminals are boxes, nonterminals are ellipses and lambdas are gray.
+    fn default() -> Self {
+        DotStyle {
+            terminal: NodeStyle::new("box", "black"),
+            nonterminal: NodeStyle::new("ellipse", "black"),
+            epsilon: NodeStyle::new("plaintext", "gray"),
+        }
+    }
+}
+
+fn write_node_to_dot<W>(
+    to: &mut W,
+    id: usize,
+    label: &str,
+    style: Option<&NodeStyle>,
+) -> Result<(), fmt::Error>
+where
+    W: fmt::Write,
+{
+    match style {
+        Some(style) => to.write_str(&format!(
+            "{} [label=\"{}\", shape={}, color=\"{}\", fontcolor=\"{}\"]\n",
+            id, label, style.shape, style.color, style.color
+        )),
+        None => to.write_str(&format!("{} [label=\"{}\"]\n", id, label)),
+    }
+}
+
+/// A node whose subtree is being written, along with the number
+/// of its children that were already visited, and their ids.
+struct DotFrame<'n, 'a, T> {
+    node: &'n ParseNode<'a, T>,
+    visited: usize,
+    children_ids: Vec<usize>,
+}
+
+/// Starts writing the subtree of the node.
+fn enter_dot_node<'n, 'a, T: Symbol, W>(
     to: &mut W,
-    node: &ParseNode<'a>,
+    node: &'n ParseNode<'a, T>,
     current_id: &mut usize,
-) -> Result<usize, fmt::Error>
+    style: Option<&DotStyle>,
+) -> Result<DotFrame<'n, 'a, T>, fmt::Error>
 where
     W: fmt::Write,
 {
@@ -54,59 +616,512 @@ where
     // If we got a nonterminal with an empty list of children, it's a lambda production.
     if node.children.is_empty() {
         if let Token::NT(_) = node.token {
-            to.write_str(&format!("{} [label=\"{}\"]\n", *current_id, "\u{03BB}"))?;
+            let epsilon_style = style.map(|style| &style.epsilon);
+            write_node_to_dot(to, *current_id, "\u{03BB}", epsilon_style)?;
             children_ids.push(*current_id);
             *current_id += 1;
         }
-    } else {
-        for child in node.children.iter() {
-            children_ids.push(write_subtree_to_dot(to, child, current_id)?);
+    }
+
+    Ok(DotFrame {
+        node,
+        visited: 0,
+        children_ids,
+    })
+}
+
+/// Writes the subtree of the node, and returns the id of the node.
+/// The children are written before their parent, so the ids are given in post-order.
+///
+/// The tree is walked with an explicit stack, so very deep trees don't overflow the native stack.
+fn write_subtree_to_dot<'a, T: Symbol, W>(
+    to: &mut W,
+    node: &ParseNode<'a, T>,
+    current_id: &mut usize,
+    style: Option<&DotStyle>,
+) -> Result<usize, fmt::Error>
+where
+    W: fmt::Write,
+{
+    let mut stack = vec![enter_dot_node(to, node, current_id, style)?];
+
+    loop {
+        let frame = stack.last_mut().unwrap();
+        if let Some(child) = frame.node.children.get(frame.visited) {
+            frame.visited += 1;
+            let child_frame = enter_dot_node(to, child, current_id, style)?;
+            stack.push(child_frame);
+            continue;
+        }
+
+        // All the children were written, so write the node.
+        let frame = stack.pop().unwrap();
+        let our_id = *current_id;
+        *current_id += 1;
+
+        let node_style = style.map(|style| match frame.node.token {
+            Token::NT(_) => &style.nonterminal,
+            Token::T(_) => &style.terminal,
+        });
+        write_node_to_dot(to, our_id, &frame.node.token.to_string(), node_style)?;
+        for id in frame.children_ids {
+            to.write_str(&format!("{} -> {}\n", our_id, id))?;
+        }
+
+        match stack.last_mut() {
+            Some(parent) => parent.children_ids.push(our_id),
+            None => return Ok(our_id),
         }
     }
+}
 
-    let our_id = *current_id;
-    *current_id += 1;
+/// Writes the tree as a graph in the DOT language. This doesn't need `dot`,
+/// which is only needed to render the graph as an image.
+pub fn write_tree_to_dot<'a, T: Symbol, W>(
+    to: &mut W,
+    root: &ParseNode<'a, T>,
+) -> Result<(), fmt::Error>
+where
+    W: fmt::Write,
+{
+    to.write_str("digraph G{\n")?;
+    let mut curr_id = 0;
+    write_subtree_to_dot(to, root, &mut curr_id, None)?;
+    to.write_str("}")
+}
+
+/// Writes the tree like `write_tree_to_dot`, but gives the nodes
+/// the shapes and colors of the given style.
+pub fn write_tree_to_dot_styled<'a, T: Symbol, W>(
+    to: &mut W,
+    root: &ParseNode<'a, T>,
+    style: &DotStyle,
+) -> Result<(), fmt::Error>
+where
+    W: fmt::Write,
+{
+    to.write_str("digraph G{\n")?;
+    let mut curr_id = 0;
+    write_subtree_to_dot(to, root, &mut curr_id, Some(style))?;
+    to.write_str("}")
+}
 
-    to.write_str(&format!("{} [label=\"{}\"]\n", our_id, node.token))?;
-    for id in children_ids {
-        to.write_str(&format!("{} -> {}\n", our_id, id))?;
+/// Returns the tree as indented text: every node is on its own line,
+/// indented by two spaces for each level, and followed by its children.
+/// Terminals are quoted.
+pub fn to_ascii<T: Symbol>(root: &ParseNode<T>) -> String {
+    let mut result = String::new();
+    // The nodes are written in pre-order, along with their depth.
+    let mut stack = vec![(root, 0)];
+    while let Some((node, depth)) = stack.pop() {
+        let indent = "  ".repeat(depth);
+        match node.token {
+            Token::NT(_) => result.push_str(&format!("{}{}\n", indent, node.token)),
+            Token::T(_) => result.push_str(&format!("{}'{}'\n", indent, node.token)),
+        }
+
+        // Just like in the DOT output, a lambda production gets a lambda child.
+        if node.children.is_empty() {
+            if let Token::NT(_) = node.token {
+                result.push_str(&format!("{}  {}\n", indent, "\u{03BB}"));
+            }
+        }
+        for child in node.children.iter().rev() {
+            stack.push((child, depth + 1));
+        }
+    }
+    result
+}
+
+/// The characters that connect the nodes written by `to_tree_string`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum TreeStyle {
+    /// `|--` before a child, `` `-- `` before the last child, and `|` below a
+    /// node that has more children.
+    Ascii,
+    /// The box-drawing characters `├──`, `└──` and `│`.
+    #[default]
+    Unicode,
+}
+
+impl TreeStyle {
+    /// Returns the connectors before a child and before the last child,
+    /// and the prefixes below them.
+    fn connectors(self) -> [&'static str; 4] {
+        match self {
+            TreeStyle::Ascii => ["|-- ", "`-- ", "|   ", "    "],
+            TreeStyle::Unicode => [
+                "\u{251C}\u{2500}\u{2500} ",
+                "\u{2514}\u{2500}\u{2500} ",
+                "\u{2502}   ",
+                "    ",
+            ],
+        }
+    }
+}
+
+/// A line written by `to_tree_string`, and the subtree to write below it,
+/// along with the prefix of its lines.
+type TreeLine<'n, 'a, T> = (String, Option<(&'n ParseNode<'a, T>, String)>);
+
+/// Returns the lines of the children of the node, whose lines start with the prefix.
+fn child_lines<'n, 'a, T: Symbol>(
+    node: &'n ParseNode<'a, T>,
+    prefix: &str,
+    style: TreeStyle,
+) -> Vec<TreeLine<'n, 'a, T>> {
+    let [child, last_child, below_child, below_last] = style.connectors();
+    // An empty nonterminal gets an epsilon leaf.
+    let mut labels: Vec<(String, Option<&ParseNode<T>>)> = node
+        .children
+        .iter()
+        .map(|child| match child.token {
+            Token::NT(_) => (child.token.to_string(), Some(&**child)),
+            Token::T(_) => (format!("'{}'", child.token), None),
+        })
+        .collect();
+    if labels.is_empty() && node.as_nonterminal().is_some() {
+        labels.push(("\u{03B5}".to_string(), None));
     }
 
-    Ok(our_id)
+    let count = labels.len();
+    let mut lines = Vec::new();
+    for (index, (label, subtree)) in labels.into_iter().enumerate() {
+        let last = index + 1 == count;
+        let connector = if last { last_child } else { child };
+        let below = if last { below_last } else { below_child };
+        lines.push((
+            format!("{}{}{}\n", prefix, connector, label),
+            subtree.map(|subtree| (subtree, format!("{}{}", prefix, below))),
+        ));
+    }
+    lines
 }
 
-pub fn write_tree_to_dot<'a, W>(to: &mut W, root: &ParseNode<'a>) -> Result<(), fmt::Error>
+/// Returns the tree as text with a line for each node, connected to its
+/// parent like the output of `tree`:
+///
+/// ```text
+/// S
+/// ├── A
+/// │   └── ε
+/// └── 'b'
+/// ```
+///
+/// Terminals are quoted, and a nonterminal that derives the empty word has an `ε` leaf.
+pub fn to_tree_string<T: Symbol>(root: &ParseNode<T>, style: TreeStyle) -> String {
+    let mut result = match root.token {
+        Token::NT(_) => format!("{}\n", root.token),
+        Token::T(_) => format!("'{}'\n", root.token),
+    };
+    // The lines are written in pre-order, each one followed by its subtree.
+    let mut stack = child_lines(root, "", style);
+    stack.reverse();
+    while let Some((line, subtree)) = stack.pop() {
+        result.push_str(&line);
+        if let Some((subtree, prefix)) = subtree {
+            stack.extend(child_lines(subtree, &prefix, style).into_iter().rev());
+        }
+    }
+    result
+}
+
+/// Writes all the trees to a single graph, each tree in its own cluster,
+/// labeled with the index of the tree. The ids of the nodes are unique
+/// across the clusters.
+pub fn write_forest_to_dot<T: Symbol, W>(
+    to: &mut W,
+    trees: &[Rc<ParseNode<T>>],
+) -> Result<(), fmt::Error>
 where
     W: fmt::Write,
 {
     to.write_str("digraph G{\n")?;
     let mut curr_id = 0;
-    write_subtree_to_dot(to, root, &mut curr_id)?;
+    for (index, tree) in trees.iter().enumerate() {
+        to.write_str(&format!("subgraph cluster_{} {{\n", index))?;
+        to.write_str(&format!("label=\"Tree {}\"\n", index))?;
+        write_subtree_to_dot(to, tree, &mut curr_id, None)?;
+        to.write_str("}\n")?;
+    }
     to.write_str("}")
 }
 
-pub fn render_tree<'a>(root: &ParseNode<'a>, path: &str) -> io::Result<()> {
+/// How a DAG draws the edges from a node to a child it has more than once,
+/// like the two `n` of `EXP -> EXP + EXP` in `n+n`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum ParallelEdges {
+    /// Draw one edge for each time the child appears.
+    #[default]
+    Keep,
+    /// Draw a single edge, labelled with the number of times the child appears.
+    Count,
+    /// Draw a single edge.
+    Merge,
+}
+
+/// A node of the DAG is identified by its token (or None for a lambda)
+/// and the ids of its children.
+type DagKey<'a, T> = (Option<Token<'a, T>>, Vec<usize>);
+
+/// Writes the node to the graph if no identical node was written before,
+/// and returns the id of the node.
+fn write_dag_node<'a, T: Symbol, W>(
+    to: &mut W,
+    key: DagKey<'a, T>,
+    label: &str,
+    memo: &mut HashMap<DagKey<'a, T>, usize>,
+    edges: ParallelEdges,
+) -> Result<usize, fmt::Error>
+where
+    W: fmt::Write,
+{
+    if let Some(&id) = memo.get(&key) {
+        return Ok(id);
+    }
+
+    let id = memo.len();
+    to.write_str(&format!("{} [label=\"{}\"]\n", id, label))?;
+    if edges == ParallelEdges::Keep {
+        for child_id in key.1.iter() {
+            to.write_str(&format!("{} -> {}\n", id, child_id))?;
+        }
+    } else {
+        // The children are drawn in the order of their first appearance.
+        let mut counts: Vec<(usize, usize)> = Vec::new();
+        for &child_id in key.1.iter() {
+            match counts.iter_mut().find(|(other, _)| *other == child_id) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((child_id, 1)),
+            }
+        }
+        for (child_id, count) in counts {
+            match edges {
+                ParallelEdges::Count if count > 1 => {
+                    to.write_str(&format!("{} -> {} [label=\"{}\"]\n", id, child_id, count))?
+                }
+                _ => to.write_str(&format!("{} -> {}\n", id, child_id))?,
+            }
+        }
+    }
+    memo.insert(key, id);
+    Ok(id)
+}
+
+fn write_subtree_to_dag<'a, T: Symbol, W>(
+    to: &mut W,
+    root: &ParseNode<'a, T>,
+    memo: &mut HashMap<DagKey<'a, T>, usize>,
+    edges: ParallelEdges,
+) -> Result<usize, fmt::Error>
+where
+    W: fmt::Write,
+{
+    fold_bottom_up(
+        root,
+        children_of,
+        |node, children: Vec<Result<usize, _>>| {
+            let mut children_ids = children.into_iter().collect::<Result<Vec<usize>, _>>()?;
+
+            // If we got a nonterminal with an empty list of children, it's a lambda production.
+            if node.children.is_empty() {
+                if let Token::NT(_) = node.token {
+                    children_ids.push(write_dag_node(
+                        to,
+                        (None, Vec::new()),
+                        "\u{03BB}",
+                        memo,
+                        edges,
+                    )?);
+                }
+            }
+
+            let label = node.token.to_string();
+            write_dag_node(to, (Some(node.token), children_ids), &label, memo, edges)
+        },
+    )
+}
+
+/// Writes all the trees to a single graph, where structurally identical
+/// subtrees (the same symbols, in the same shape) are drawn only once.
+/// Subtrees shared by many trees or repeated inside one tree have many parents,
+/// so the graph is a DAG instead of a forest of disjoint trees.
+///
+/// Note that the positions of the subtrees are ignored, so a subtree that
+/// appears at different positions in the input is also shared. A node whose
+/// children are the same subtree has parallel edges to it, see
+/// `write_forest_dag_to_dot_with` to draw them differently.
+pub fn write_forest_dag_to_dot<T: Symbol, W>(
+    to: &mut W,
+    trees: &[Rc<ParseNode<T>>],
+) -> Result<(), fmt::Error>
+where
+    W: fmt::Write,
+{
+    write_forest_dag_to_dot_with(to, trees, ParallelEdges::Keep)
+}
+
+/// Writes all the trees to a single graph like `write_forest_dag_to_dot`,
+/// drawing the parallel edges as given.
+pub fn write_forest_dag_to_dot_with<T: Symbol, W>(
+    to: &mut W,
+    trees: &[Rc<ParseNode<T>>],
+    edges: ParallelEdges,
+) -> Result<(), fmt::Error>
+where
+    W: fmt::Write,
+{
+    to.write_str("digraph G{\n")?;
+    let mut memo = HashMap::new();
+    for tree in trees.iter() {
+        write_subtree_to_dag(to, tree, &mut memo, edges)?;
+    }
+    to.write_str("}")
+}
+
+/// Writes all the trees as text, one line for each nonterminal of each span,
+/// like `#0 EXP => (#1 + #2) | (#1 * #2)`. The nonterminals of the same span
+/// share an ID, and the different sequences of children that derive them are
+/// listed as alternatives, so the ambiguous nodes are the lines with a `|`.
+/// The terminals are written as themselves, and the empty word as `()`.
+///
+/// Unlike `write_forest_dag_to_dot`, the positions are kept: a subtree is
+/// shared only by the nodes that cover the same span of the input.
+pub fn forest_to_text<T: Symbol>(trees: &[Rc<ParseNode<T>>]) -> String {
+    let mut forest = TextForest {
+        ids: HashMap::new(),
+        lines: Vec::new(),
+        labels: HashMap::new(),
+    };
+    for tree in trees.iter() {
+        forest.label(tree);
+    }
+
+    let mut result = String::new();
+    for (id, (name, alternatives)) in forest.lines.iter().enumerate() {
+        let alternatives: Vec<String> = alternatives
+            .iter()
+            .map(|children| format!("({})", children.join(" ")))
+            .collect();
+        result.push_str(&format!(
+            "#{} {} => {}\n",
+            id,
+            name,
+            alternatives.join(" | ")
+        ));
+    }
+    result
+}
+
+/// The state of `forest_to_text`: the ID of each nonterminal span, the name and
+/// the alternatives of each ID, and the labels of the nodes already written.
+struct TextForest<'a, T> {
+    ids: HashMap<(&'a str, usize, usize), usize>,
+    lines: Vec<(&'a str, Vec<Vec<String>>)>,
+    labels: HashMap<*const ParseNode<'a, T>, String>,
+}
+
+impl<'a, T: Symbol> TextForest<'a, T> {
+    /// Labels the nodes of the tree, each one with its terminal or the ID of its span,
+    /// and adds their children to the alternatives of their spans. The IDs are given
+    /// in pre-order, and the tree is walked with an explicit stack.
+    fn label(&mut self, root: &Rc<ParseNode<'a, T>>) {
+        // Each node is pushed with a flag that tells whether its children were pushed.
+        let mut stack = vec![(root, false)];
+        while let Some((node, expanded)) = stack.pop() {
+            let name = match node.as_nonterminal() {
+                _ if self.labels.contains_key(&Rc::as_ptr(node)) => continue,
+                None => {
+                    self.labels.insert(Rc::as_ptr(node), node.token.to_string());
+                    continue;
+                }
+                Some(name) => name,
+            };
+
+            let key = (name, node.start, node.end);
+            if !expanded {
+                if !self.ids.contains_key(&key) {
+                    self.lines.push((name, Vec::new()));
+                    self.ids.insert(key, self.lines.len() - 1);
+                }
+                stack.push((node, true));
+                stack.extend(node.children.iter().rev().map(|child| (child, false)));
+                continue;
+            }
+
+            let id = self.ids[&key];
+            let children: Vec<String> = node
+                .children
+                .iter()
+                .map(|child| self.labels[&Rc::as_ptr(child)].clone())
+                .collect();
+            if !self.lines[id].1.contains(&children) {
+                self.lines[id].1.push(children);
+            }
+            self.labels.insert(Rc::as_ptr(node), format!("#{}", id));
+        }
+    }
+}
+
+/// Writes the tree as a graph in the DOT language to the file at the given path,
+/// without running `dot`. The graph can be rendered later, with `dot -Tsvg`.
+pub fn write_tree_to_dot_file<'a, T: Symbol>(
+    root: &ParseNode<'a, T>,
+    path: &str,
+) -> io::Result<()> {
+    let mut dot = String::new();
+    write_tree_to_dot(&mut dot, root).unwrap();
+    fs::write(path, dot)
+}
+
+/// Renders the tree as an svg image in the file at the given path.
+/// The file is only written if `dot` succeeds. If `dot` is not installed,
+/// the error has the kind `io::ErrorKind::NotFound`.
+pub fn render_tree<'a, T: Symbol>(root: &ParseNode<'a, T>, path: &str) -> io::Result<()> {
+    let mut image = Vec::new();
+    render_tree_to_writer(root, "svg", &mut image)?;
+    fs::write(path, image)
+}
+
+/// Renders the tree with `dot`, in the given output format (like `svg` or `png`),
+/// and writes the output to `out`.
+///
+/// The graph is written to `dot` on another thread while its output is read,
+/// so that neither of them blocks when the pipes fill up on large trees.
+pub fn render_tree_to_writer<'a, T: Symbol, W: Write>(
+    root: &ParseNode<'a, T>,
+    format: &str,
+    out: &mut W,
+) -> io::Result<()> {
     let mut dot = String::new();
     write_tree_to_dot(&mut dot, root).unwrap();
 
     let mut child = Command::new("dot")
-        .args(["-Tsvg", "-o", path])
+        .arg(format!("-T{}", format))
         .stdin(Stdio::piped())
-        .stdout(Stdio::inherit())
+        .stdout(Stdio::piped())
         .spawn()?;
 
-    let child_stdin = child.stdin.as_mut().expect("Failed to open stdin!");
-    write!(child_stdin, "{}", dot)?;
+    let mut child_stdin = child.stdin.take().expect("Failed to open stdin!");
+    let writer = thread::spawn(move || child_stdin.write_all(dot.as_bytes()));
+    let copied = io::copy(child.stdout.as_mut().expect("Failed to open stdout!"), out);
+    if let Err(error) = copied {
+        // Nothing reads the output of dot anymore, so it could block on a full
+        // pipe, and the writer on its input. Stop it before waiting for them.
+        drop(child.stdout.take());
+        let _ = child.kill();
+        let _ = writer.join();
+        let _ = child.wait();
+        return Err(error);
+    }
+    writer.join().expect("The writing thread panicked!")?;
 
     match child.wait()?.code() {
         Some(0) => Ok(()),
-        Some(e) => Err(Error::new(
-            ErrorKind::Other,
-            format!("dot program returned error code {}", e),
-        )),
-        None => Err(Error::new(
-            ErrorKind::Other,
-            "dot program was killed by a signal",
-        )),
+        Some(e) => Err(Error::other(format!(
+            "dot program returned error code {}",
+            e
+        ))),
+        None => Err(Error::other("dot program was killed by a signal")),
     }
 }
@@ -0,0 +1,105 @@
+use super::*;
+
+/// The side that an operator groups to when it is repeated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    /// `n+n+n` is read as `(n+n)+n`.
+    Left,
+    /// `n^n^n` is read as `n^(n^n)`.
+    Right,
+}
+
+/// The precedence levels and associativity of the operators of a grammar,
+/// used to choose between the trees of an ambiguous expression grammar.
+///
+/// An operator is a terminal in the middle of a rule shaped like `A -> A op A`.
+/// Operators with a higher level bind tighter, so with `*` above `+`,
+/// `n+n*n` is read as `n+(n*n)`.
+#[derive(Debug, Default, Clone)]
+pub struct Precedence {
+    operators: HashMap<char, (usize, Associativity)>,
+}
+
+impl Precedence {
+    pub fn new() -> Self {
+        Precedence::default()
+    }
+
+    /// Adds a left associative operator at the given level.
+    pub fn left(mut self, operator: char, level: usize) -> Self {
+        self.operators
+            .insert(operator, (level, Associativity::Left));
+        self
+    }
+
+    /// Adds a right associative operator at the given level.
+    pub fn right(mut self, operator: char, level: usize) -> Self {
+        self.operators
+            .insert(operator, (level, Associativity::Right));
+        self
+    }
+
+    /// Puts all the given operators at the same level, with the same associativity.
+    fn uniform(operators: impl Iterator<Item = char>, associativity: Associativity) -> Self {
+        Precedence {
+            operators: operators.map(|c| (c, (0, associativity))).collect(),
+        }
+    }
+
+    /// Returns the level and associativity of the operator applied at the node,
+    /// if the node was derived by an operator rule.
+    fn operator_of(&self, node: &ParseNode) -> Option<(usize, Associativity)> {
+        let [left, operator, right] = node.children.as_slice() else {
+            return None;
+        };
+        if left.token != node.token || right.token != node.token {
+            return None;
+        }
+        match operator.token {
+            Token::T(Terminal::Symbol(c)) => self.operators.get(&c).copied(),
+            _ => None,
+        }
+    }
+
+    /// Returns true if no operator in the tree has an operand that should
+    /// have been applied after it.
+    fn allows(&self, node: &ParseNode) -> bool {
+        if let Some((level, associativity)) = self.operator_of(node) {
+            let operand_allowed = |operand: &ParseNode, side| match self.operator_of(operand) {
+                Some((inner, _)) => inner > level || (inner == level && associativity == side),
+                None => true,
+            };
+            if !operand_allowed(&node.children[0], Associativity::Left)
+                || !operand_allowed(&node.children[2], Associativity::Right)
+            {
+                return false;
+            }
+        }
+        node.children.iter().all(|child| self.allows(child))
+    }
+}
+
+impl<'a> Grammar<'a> {
+    /// Returns the parse trees of the word that respect the precedence and
+    /// associativity of the operators. The operators missing from the table
+    /// don't restrict the trees.
+    pub fn parse_disambiguated(&self, s: &str, precedence: &Precedence) -> Vec<Rc<ParseNode<'_>>> {
+        self.parse_filtered(s, |tree| precedence.allows(tree))
+    }
+
+    /// Returns the tree of the word in which every operator rule, shaped like
+    /// `A -> A op A`, groups to the given side, as if all the terminals were
+    /// operators of the same level. With `Left`, `n+n+n` is read as `(n+n)+n`.
+    ///
+    /// Returns `None` if the word is not in the language. If the word is still
+    /// ambiguous for other reasons, the first tree is returned, like `parse` orders them.
+    pub fn parse_associative(
+        &self,
+        s: &str,
+        associativity: Associativity,
+    ) -> Option<Rc<ParseNode<'_>>> {
+        let operators = self.terminals.iter().filter_map(|t| t.content());
+        let precedence = Precedence::uniform(operators, associativity);
+        self.parse_disambiguated(s, &precedence).into_iter().next()
+    }
+}
@@ -0,0 +1,240 @@
+use super::*;
+use std::collections::BTreeMap;
+
+/// A scored state is identified by the index of its rule, the position of
+/// the dot and its origin. Unlike `EarleyState`, it does not hold all of its
+/// derivations, only a back-pointer to the best one found so far.
+type Item = (usize, usize, usize);
+
+/// How the best derivation of a state was obtained.
+#[derive(Copy, Clone)]
+enum BackPointer {
+    /// The state was predicted, so the dot is at the start of the production.
+    Predicted,
+    /// The state was obtained by scanning a terminal from the given state
+    /// in the previous set.
+    Scanned(Item),
+    /// The state was obtained by completing the given child state (in the same set)
+    /// from the given state (in the set at the origin of the child).
+    Completed(Item, Item),
+}
+
+#[derive(Copy, Clone)]
+struct Entry {
+    score: f64,
+    back: BackPointer,
+}
+
+/// The sets are ordered maps, so that ties are always broken the same way.
+struct ScoredTable {
+    sets: Vec<BTreeMap<Item, Entry>>,
+}
+
+impl ScoredTable {
+    /// Inserts the state if it is new or if it improves the best score.
+    /// Returns true if the table changed.
+    fn relax(&mut self, k: usize, item: Item, entry: Entry) -> bool {
+        match self.sets[k].get(&item) {
+            Some(old) if old.score >= entry.score => false,
+            _ => {
+                self.sets[k].insert(item, entry);
+                true
+            }
+        }
+    }
+}
+
+impl<'a> Grammar<'a> {
+    fn awaited_token(&self, item: Item) -> Option<Token<'a>> {
+        let (rule, dot, _) = item;
+        self.rules[rule].to.get(dot).copied()
+    }
+
+    fn scored_prediction(&self, table: &mut ScoredTable, k: usize) -> bool {
+        let mut to_add = Vec::new();
+        for &item in table.sets[k].keys() {
+            if let Some(Token::NT(nonterminal)) = self.awaited_token(item) {
+                for &index in self.rules_for(nonterminal) {
+                    let entry = Entry {
+                        score: self.weights[index],
+                        back: BackPointer::Predicted,
+                    };
+                    to_add.push(((index, 0, k), entry));
+                }
+            }
+        }
+
+        let mut changed = false;
+        for (item, entry) in to_add {
+            changed |= table.relax(k, item, entry);
+        }
+        changed
+    }
+
+    fn scored_scan(&self, table: &mut ScoredTable, k: usize, next_char: char) {
+        let mut to_add = Vec::new();
+        for (&item, entry) in table.sets[k].iter() {
+            if let Some(Token::T(terminal)) = self.awaited_token(item) {
+                if terminal.matches(next_char) {
+                    let (rule, dot, origin) = item;
+                    let entry = Entry {
+                        score: entry.score,
+                        back: BackPointer::Scanned(item),
+                    };
+                    to_add.push(((rule, dot + 1, origin), entry));
+                }
+            }
+        }
+
+        for (item, entry) in to_add {
+            table.relax(k + 1, item, entry);
+        }
+    }
+
+    fn scored_complete(&self, table: &mut ScoredTable, k: usize) -> bool {
+        let mut to_add = Vec::new();
+        for (&child, child_entry) in table.sets[k].iter() {
+            let (child_rule, child_dot, child_origin) = child;
+            if child_dot != self.rules[child_rule].to.len() {
+                continue;
+            }
+
+            let completed = self.rules[child_rule].from;
+            for (&waiting, waiting_entry) in table.sets[child_origin].iter() {
+                if self.awaited_token(waiting) != Some(Token::NT(completed)) {
+                    continue;
+                }
+                let (rule, dot, origin) = waiting;
+                let entry = Entry {
+                    score: waiting_entry.score + child_entry.score,
+                    back: BackPointer::Completed(waiting, child),
+                };
+                to_add.push(((rule, dot + 1, origin), entry));
+            }
+        }
+
+        let mut changed = false;
+        for (item, entry) in to_add {
+            changed |= table.relax(k, item, entry);
+        }
+        changed
+    }
+
+    /// Builds the tree of the best derivation of the finished state `item`
+    /// from the set at index `end`, given the characters of the word.
+    ///
+    /// The completed children are built on a stack of the states being followed,
+    /// instead of recursive calls, which deep derivations would run out of.
+    fn build_best_tree(
+        &self,
+        table: &ScoredTable,
+        chars: &[char],
+        item: Item,
+        end: usize,
+    ) -> Rc<ParseNode<'_>> {
+        // The finished states whose trees are being built, along with the state
+        // and the position reached by following their back-pointers, and the
+        // children found so far, from the last one.
+        let mut stack = vec![(item, end, item, end, Vec::new())];
+
+        loop {
+            let (_, _, current, position, children) = stack.last_mut().unwrap();
+
+            // Follow the back-pointers from the finished state to the predicted one.
+            match table.sets[*position][&*current].back {
+                BackPointer::Predicted => {}
+                BackPointer::Scanned(previous) => {
+                    let (rule, dot, _) = *current;
+                    children.push(Rc::new(ParseNode {
+                        token: leaf_token(self.rules[rule].to[dot - 1], chars[*position - 1]),
+                        children: Vec::new(),
+                        start: *position - 1,
+                        end: *position,
+                        rule: None,
+                    }));
+                    *current = previous;
+                    *position -= 1;
+                    continue;
+                }
+                BackPointer::Completed(previous, child) => {
+                    let child_end = *position;
+                    *current = previous;
+                    let (_, _, child_origin) = child;
+                    *position = child_origin;
+                    stack.push((child, child_end, child, child_end, Vec::new()));
+                    continue;
+                }
+            }
+
+            let ((rule, _, origin), end, _, _, mut children) = stack.pop().unwrap();
+            children.reverse();
+            let tree = Rc::new(ParseNode {
+                token: Token::NT(self.rules[rule].from),
+                children,
+                start: origin,
+                end,
+                rule: Some(&self.rules[rule].to[..]),
+            });
+            match stack.last_mut() {
+                Some((.., children)) => children.push(tree),
+                None => return tree,
+            }
+        }
+    }
+
+    /// Returns the derivation of the word that maximizes the sum of the weights
+    /// (the product of the probabilities) of the rules used, along with its score.
+    /// Ties are broken deterministically.
+    ///
+    /// Instead of enumerating all the derivations, only the best score of each
+    /// state is propagated through the table. Since the weights are log-probabilities
+    /// (never positive, see `set_weights`), a cycle can't improve a score.
+    pub fn parse_best(&self, s: &str) -> Option<(Rc<ParseNode<'_>>, f64)> {
+        let mut table = ScoredTable {
+            sets: vec![BTreeMap::new(); s.chars().count() + 1],
+        };
+
+        // Add the starting rules.
+        for &index in self.rules_for(self.start) {
+            let entry = Entry {
+                score: self.weights[index],
+                back: BackPointer::Predicted,
+            };
+            table.relax(0, (index, 0, 0), entry);
+        }
+
+        let chars: Vec<char> = s.chars().collect();
+        for position in 0..table.sets.len() {
+            // Repeat prediction and completion until no state can be added
+            // or improved in the current set.
+            loop {
+                let predicted = self.scored_prediction(&mut table, position);
+                let completed = self.scored_complete(&mut table, position);
+                if !predicted && !completed {
+                    break;
+                }
+            }
+            if let Some(&c) = chars.get(position) {
+                self.scored_scan(&mut table, position, c);
+            }
+        }
+
+        let last = chars.len();
+        let (&item, entry) = table.sets[last]
+            .iter()
+            .filter(|(&(rule, dot, origin), _)| {
+                self.rules[rule].from == self.start
+                    && dot == self.rules[rule].to.len()
+                    && origin == 0
+            })
+            .fold(None, |best: Option<(&Item, &Entry)>, current| match best {
+                Some(best) if best.1.score >= current.1.score => Some(best),
+                _ => Some(current),
+            })?;
+
+        Some((
+            self.build_best_tree(&table, &chars, item, last),
+            entry.score,
+        ))
+    }
+}
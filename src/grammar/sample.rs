@@ -0,0 +1,405 @@
+use super::*;
+use std::collections::BTreeSet;
+
+/// A source of random numbers for sampling words from a grammar.
+pub trait Rng {
+    /// Returns the next random number, uniformly distributed.
+    fn next_u64(&mut self) -> u64;
+
+    /// Returns a random number in the range `0..n`.
+    /// The result is slightly biased when n is not a power of two.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+/// A small and fast generator, good enough for fuzzing and demos,
+/// but not for anything that needs unpredictable numbers.
+/// The same seed always gives the same numbers.
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+}
+
+impl Rng for SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl<'a> Grammar<'a> {
+    /// Returns, for each nonterminal that derives at least one word, the smallest
+    /// height of a derivation tree of that nonterminal, counting only the nonterminals.
+    fn min_heights(&self) -> HashMap<NonTerminal<'a>, usize> {
+        let mut heights: HashMap<NonTerminal, usize> = HashMap::new();
+
+        // Repeat until no height can be lowered.
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                if let Some(height) = self.rule_height(rule, &heights) {
+                    let current = heights.entry(rule.from).or_insert(usize::MAX);
+                    if height < *current {
+                        *current = height;
+                        changed = true;
+                    }
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+        heights
+    }
+
+    /// Returns the smallest height of a derivation tree that starts with the rule,
+    /// or None if some nonterminal of the rule has no known height, or if some
+    /// terminal has no character to sample.
+    fn rule_height(
+        &self,
+        rule: &Rule<'a>,
+        heights: &HashMap<NonTerminal<'a>, usize>,
+    ) -> Option<usize> {
+        let mut height = 0;
+        for token in rule.to.iter() {
+            match token {
+                Token::NT(n) => height = height.max(*heights.get(n)?),
+                Token::T(t) => {
+                    if t.chars().is_none_or(|chars| chars.is_empty()) {
+                        return None;
+                    }
+                }
+            }
+        }
+        Some(height + 1)
+    }
+
+    fn sample_nonterminal<R: Rng>(
+        &self,
+        nonterminal: NonTerminal<'a>,
+        rng: &mut R,
+        depth: usize,
+        heights: &HashMap<NonTerminal<'a>, usize>,
+        result: &mut String,
+    ) {
+        // Only choose the rules that can finish before reaching the maximum depth.
+        let choices: Vec<&Rule> = self
+            .rules_for(nonterminal)
+            .iter()
+            .map(|&index| &self.rules[index])
+            .filter(|rule| matches!(self.rule_height(rule, heights), Some(h) if h <= depth))
+            .collect();
+        let rule = choices[rng.below(choices.len())];
+
+        for token in rule.to.iter() {
+            match token {
+                Token::T(t) => {
+                    let chars = t.chars().expect("The rule has a height!");
+                    result.push(chars[rng.below(chars.len())]);
+                }
+                Token::NT(n) => self.sample_nonterminal(*n, rng, depth - 1, heights, result),
+            }
+        }
+    }
+
+    /// Returns a random word of the language, derived by choosing a random
+    /// rule for every nonterminal, starting from the start nonterminal.
+    ///
+    /// The derivation tree has at most `max_depth` levels of nonterminals: as the
+    /// depth grows, only the rules that can still finish in time are chosen.
+    /// Returns None if no word can be derived within `max_depth` levels.
+    ///
+    /// The rules with a terminal that has no character to sample, like a
+    /// predicate, whose characters can't be listed, or an empty set, are never chosen.
+    pub fn sample<R: Rng>(&self, rng: &mut R, max_depth: usize) -> Option<String> {
+        let heights = self.min_heights();
+        if *heights.get(&self.start)? > max_depth {
+            return None;
+        }
+
+        let mut result = String::new();
+        self.sample_nonterminal(self.start, rng, max_depth, &heights, &mut result);
+        Some(result)
+    }
+
+    /// Returns the words of the given sequence of tokens, grouped by length,
+    /// knowing the words of each nonterminal.
+    fn sequence_words(
+        &self,
+        tokens: &[Token<'a>],
+        words: &HashMap<NonTerminal<'a>, Vec<BTreeSet<String>>>,
+        max_len: usize,
+    ) -> Vec<BTreeSet<String>> {
+        let mut current = vec![BTreeSet::new(); max_len + 1];
+        current[0].insert(String::new());
+
+        for token in tokens {
+            // The words of a terminal are its characters, of length 1.
+            let mut terminal_words = vec![BTreeSet::new(); max_len + 1];
+            let token_words = match token {
+                Token::T(t) => {
+                    if max_len > 0 {
+                        let chars = t.chars().unwrap_or_default();
+                        terminal_words[1].extend(chars.into_iter().map(String::from));
+                    }
+                    &terminal_words
+                }
+                Token::NT(n) => words.get(n).unwrap_or(&terminal_words),
+            };
+
+            let mut next = vec![BTreeSet::new(); max_len + 1];
+            for (prefix_len, prefixes) in current.iter().enumerate() {
+                for (suffix_len, suffixes) in token_words.iter().enumerate() {
+                    if prefix_len + suffix_len > max_len {
+                        break;
+                    }
+                    for prefix in prefixes.iter() {
+                        for suffix in suffixes.iter() {
+                            next[prefix_len + suffix_len].insert(format!("{}{}", prefix, suffix));
+                        }
+                    }
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    /// Returns every word of the language with at most `max_len` characters, once,
+    /// from the shortest to the longest. Words of the same length are sorted.
+    ///
+    /// The words of every nonterminal are computed up to the given length before
+    /// the first one is returned, so this is only practical for small lengths.
+    /// A predicate matches no character here, since its characters can't be
+    /// listed, so the words that need one are missing (see `alphabet`).
+    pub fn enumerate(&self, max_len: usize) -> impl Iterator<Item = String> {
+        let mut words: HashMap<NonTerminal, Vec<BTreeSet<String>>> = HashMap::new();
+
+        // A word of a nonterminal is made of words of the symbols of one of its rules.
+        // Repeat until no new word is found.
+        loop {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                let found = self.sequence_words(&rule.to, &words, max_len);
+                let known = words
+                    .entry(rule.from)
+                    .or_insert_with(|| vec![BTreeSet::new(); max_len + 1]);
+                for (known, found) in known.iter_mut().zip(found) {
+                    for word in found {
+                        changed |= known.insert(word);
+                    }
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        words
+            .remove(&self.start)
+            .unwrap_or_default()
+            .into_iter()
+            .flatten()
+    }
+
+    /// Returns every word of the language once, like `enumerate`, or None if the
+    /// language is infinite (see `is_finite`), or if a terminal is a predicate,
+    /// whose characters can't be listed.
+    pub fn enumerate_all(&self) -> Option<Vec<String>> {
+        if !self.is_finite() || self.alphabet().is_none() {
+            return None;
+        }
+        Some(self.enumerate(self.longest_word_length()).collect())
+    }
+
+    /// Returns the length of the longest word of a finite language.
+    fn longest_word_length(&self) -> usize {
+        let mut longest: HashMap<NonTerminal, usize> = HashMap::new();
+
+        // The rules that derive words only go through the productive nonterminals,
+        // which get a length once all the symbols of one of their rules have one.
+        // The longest derivation that adds characters has a step for each nonterminal,
+        // so the lengths stop growing after that many rounds. Nonterminals that can't
+        // be reached could grow forever, but they don't change the length of the start.
+        for _ in 0..=self.nonterminal_count() {
+            let mut changed = false;
+            for rule in self.rules.iter() {
+                let length = rule.to.iter().try_fold(0usize, |total, token| match token {
+                    Token::T(_) => Some(total.saturating_add(1)),
+                    Token::NT(n) => longest.get(n).map(|length| total.saturating_add(*length)),
+                });
+                match (length, longest.get(&rule.from)) {
+                    (None, _) => {}
+                    (Some(length), Some(&known)) if known >= length => {}
+                    (Some(length), _) => {
+                        longest.insert(rule.from, length);
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        longest.get(&self.start).copied().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sample() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> N
+        N -> D N
+        N -> D
+        D -> 0
+        D -> 1";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let mut rng = SplitMix64::new(42);
+        let mut lengths = HashSet::new();
+        for _ in 0..200 {
+            let word = grammar.sample(&mut rng, 6).unwrap();
+            assert!(grammar.recognizes(&word), "{} is not recognized", word);
+            lengths.insert(word.len());
+        }
+        assert!(lengths.len() > 5);
+
+        // EXP -> N -> D -> 0 needs three levels.
+        assert!(grammar.sample(&mut rng, 2).is_none());
+        assert!(grammar.sample(&mut rng, 3).is_some());
+
+        // The same seed gives the same words.
+        let first = grammar.sample(&mut SplitMix64::new(7), 10);
+        let second = grammar.sample(&mut SplitMix64::new(7), 10);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn test_sample_unproductive() {
+        let grammar_string = "S
+        S -> a S
+        S -> A
+        A -> a A";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert!(grammar.sample(&mut SplitMix64::new(1), 100).is_none());
+
+        let grammar_string = "S
+        S -> a S
+        S -> A
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut rng = SplitMix64::new(1);
+        for _ in 0..50 {
+            let word = grammar.sample(&mut rng, 20).unwrap();
+            assert!(word.len() < 20);
+            assert!(grammar.recognizes(&word));
+        }
+    }
+
+    #[test]
+    fn test_sample_predicate() {
+        // The rules with a predicate are never chosen.
+        let config = GrammarConfig::default().terminal_pred("space", char::is_whitespace);
+        let grammar_string = "S
+        S -> a \\p{space}
+        S -> b";
+        let grammar = Grammar::from_rules_with_config(grammar_string, &config).unwrap();
+        let mut rng = SplitMix64::new(3);
+        for _ in 0..20 {
+            assert_eq!(grammar.sample(&mut rng, 5).as_deref(), Some("b"));
+        }
+
+        let grammar = Grammar::from_rules_with_config("S\nS -> \\p{space}", &config).unwrap();
+        assert_eq!(grammar.sample(&mut rng, 5), None);
+    }
+
+    #[test]
+    fn test_enumerate() {
+        let grammar_string = "S
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let words: Vec<String> = grammar.enumerate(4).collect();
+        assert_eq!(words, vec!["", "()", "(())", "()()"]);
+
+        // The number of balanced words with 5 pairs of parentheses.
+        let words: Vec<String> = grammar.enumerate(10).collect();
+        assert_eq!(words.iter().filter(|word| word.len() == 10).count(), 42);
+        for word in words.iter() {
+            assert!(grammar.recognizes(word));
+        }
+    }
+
+    #[test]
+    fn test_enumerate_all() {
+        let grammar_string = "S
+        S -> a A
+        S -> B B
+        A -> b
+        A -> 
+        A -> A
+        B -> c
+        B -> d
+        B -> D
+        D -> B
+        C -> c C";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let words = grammar.enumerate_all().unwrap();
+        assert_eq!(words, vec!["a", "ab", "cc", "cd", "dc", "dd"]);
+
+        let grammar = Grammar::from_rules("S\nS -> a S\nS -> ").unwrap();
+        assert!(grammar.enumerate_all().is_none());
+
+        let grammar = Grammar::from_rules("S\nS -> A\nA -> a A").unwrap();
+        assert_eq!(grammar.enumerate_all(), Some(Vec::new()));
+        let grammar = Grammar::from_rules("S\nS -> ").unwrap();
+        assert_eq!(grammar.enumerate_all(), Some(vec![String::new()]));
+
+        // The characters of a predicate can't be listed.
+        let config = GrammarConfig::default().terminal_pred("space", char::is_whitespace);
+        let grammar = Grammar::from_rules_with_config("S\nS -> a \\p{space}", &config).unwrap();
+        assert_eq!(grammar.enumerate_all(), None);
+        assert_eq!(grammar.enumerate(2).count(), 0);
+    }
+
+    #[test]
+    fn test_enumerate_ambiguous() {
+        // Every word has many derivations, and the unit and epsilon rules form cycles.
+        let grammar_string = "S
+        S -> S S
+        S -> A
+        A -> S
+        A -> a
+        A -> b
+        A -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+
+        let words: Vec<String> = grammar.enumerate(2).collect();
+        assert_eq!(words, vec!["", "a", "b", "aa", "ab", "ba", "bb"]);
+        assert_eq!(grammar.enumerate(0).collect::<Vec<_>>(), vec![""]);
+
+        let grammar_string = "S
+        S -> a S";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        assert_eq!(grammar.enumerate(5).count(), 0);
+    }
+}
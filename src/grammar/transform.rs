@@ -0,0 +1,726 @@
+use super::analysis::{nullable_nonterminals, reachable_nonterminals};
+use super::*;
+
+/// Creates the names of new nonterminals, which are not used by a grammar.
+///
+/// A name can only be borrowed once it is written into the string given by the
+/// caller, and the string can't grow while it is borrowed. So a transform runs
+/// twice: first with placeholders, to find the names it needs, and then with
+/// the names written by `write`, which come in the same order.
+struct NameGenerator<'a> {
+    used: HashSet<&'a str>,
+    names: Vec<String>,
+}
+
+impl<'a> NameGenerator<'a> {
+    fn new(grammar: &Grammar<'a>) -> Self {
+        NameGenerator {
+            used: grammar.nonterminals.keys().copied().collect(),
+            names: Vec::new(),
+        }
+    }
+
+    /// Finds a new name `base_i`, for the smallest number i that gives
+    /// an unused name, and returns a placeholder for its nonterminal.
+    fn fresh(&mut self, base: &str) -> NonTerminal<'static> {
+        let mut index = 0;
+        loop {
+            let name = format!("{}_{}", base, index);
+            if !self.used.contains(name.as_str()) && !self.names.contains(&name) {
+                self.names.push(name);
+                return NonTerminal::new("");
+            }
+            index += 1;
+        }
+    }
+
+    /// Replaces the contents of the string with the new names, and returns
+    /// their nonterminals, in the order they were found.
+    fn write(self, store: &mut String) -> impl Iterator<Item = NonTerminal<'_>> {
+        store.clear();
+        let mut ends = Vec::new();
+        for name in self.names.iter() {
+            store.push_str(name);
+            ends.push(store.len());
+        }
+        let store: &str = store;
+        let starts = std::iter::once(0).chain(ends.clone());
+        starts
+            .zip(ends)
+            .map(move |(start, end)| NonTerminal::new(&store[start..end]))
+    }
+}
+
+/// Removes the duplicated rules, keeping the first occurrence of each rule.
+fn dedup_rules(rules: Vec<Rule>) -> Vec<Rule> {
+    let mut seen = HashSet::new();
+    rules
+        .into_iter()
+        .filter(|rule| seen.insert(rule.clone()))
+        .collect()
+}
+
+/// Removes the epsilon rules. Each rule is replaced by all the rules obtained
+/// by keeping or removing each of its nullable symbols, as long as the result is not empty.
+/// The language of each nonterminal stays the same, except for the empty word.
+///
+/// If `start` is nullable, the rule `start -> ` is kept, so that the empty word
+/// is still derived from it.
+fn remove_epsilon_rules<'a>(rules: Vec<Rule<'a>>, start: NonTerminal<'a>) -> Vec<Rule<'a>> {
+    let nullable = nullable_nonterminals(&rules);
+
+    let mut result = Vec::new();
+    for rule in rules.iter() {
+        // Build all the variants of the rule, symbol by symbol.
+        let mut variants: Vec<Vec<Token>> = vec![Vec::new()];
+        for token in rule.to.iter() {
+            let optional = match token {
+                Token::NT(n) => nullable.contains(n),
+                Token::T(_) => false,
+            };
+
+            let mut next = Vec::new();
+            for variant in variants {
+                if optional {
+                    next.push(variant.clone());
+                }
+                let mut variant = variant;
+                variant.push(*token);
+                next.push(variant);
+            }
+            variants = next;
+        }
+
+        for to in variants {
+            if !to.is_empty() {
+                result.push(Rule {
+                    from: rule.from,
+                    to,
+                    name: rule.name,
+                });
+            }
+        }
+    }
+
+    // Nonterminals that could only derive the empty word have no rules left,
+    // so the rules that still use them are dead. Removing those rules can leave
+    // more nonterminals without rules, so repeat until nothing changes.
+    let originally_defined: HashSet<NonTerminal> = rules.iter().map(|rule| rule.from).collect();
+    loop {
+        let defined: HashSet<NonTerminal> = result.iter().map(|rule| rule.from).collect();
+        let old_size = result.len();
+        result.retain(|rule| {
+            rule.to.iter().all(|token| match token {
+                Token::NT(n) => defined.contains(n) || !originally_defined.contains(n),
+                Token::T(_) => true,
+            })
+        });
+        if result.len() == old_size {
+            break;
+        }
+    }
+
+    if nullable.contains(&start) {
+        result.push(Rule {
+            from: start,
+            to: Vec::new(),
+            name: None,
+        });
+    }
+    dedup_rules(result)
+}
+
+/// Removes the unit rules, the rules of the form A -> B.
+/// For each nonterminal A, and each nonterminal B that can be reached from A using
+/// only unit rules, the rules of B that are not unit rules are copied to A.
+fn remove_unit_rules(rules: Vec<Rule>) -> Vec<Rule> {
+    let is_unit = |rule: &Rule| rule.to.len() == 1 && matches!(rule.to[0], Token::NT(_));
+
+    // The nonterminals in the order of their first rule, so the result is deterministic.
+    let mut order = Vec::new();
+    for rule in rules.iter() {
+        if !order.contains(&rule.from) {
+            order.push(rule.from);
+        }
+    }
+
+    let mut result = Vec::new();
+    for &from in order.iter() {
+        // Find the nonterminals reachable from `from` using unit rules.
+        let mut reachable = vec![from];
+        let mut index = 0;
+        while index < reachable.len() {
+            let current = reachable[index];
+            for rule in rules.iter() {
+                if rule.from != current || !is_unit(rule) {
+                    continue;
+                }
+                if let Token::NT(next) = rule.to[0] {
+                    if !reachable.contains(&next) {
+                        reachable.push(next);
+                    }
+                }
+            }
+            index += 1;
+        }
+
+        for &current in reachable.iter() {
+            for rule in rules.iter() {
+                if rule.from == current && !is_unit(rule) {
+                    result.push(Rule {
+                        from,
+                        to: rule.to.clone(),
+                        name: rule.name,
+                    });
+                }
+            }
+        }
+    }
+    dedup_rules(result)
+}
+
+impl<'a> Grammar<'a> {
+    /// Returns an equivalent grammar without epsilon rules, computed by adding,
+    /// for each rule, all the variants with some of its nullable symbols removed.
+    ///
+    /// If the empty word is in the language, the only epsilon rule is S -> ,
+    /// where S is the start nonterminal. If the old start appears in the right side
+    /// of a rule, S is a new start nonterminal with the rules S -> and S -> old start.
+    ///
+    /// The name of the new start is written into `names`, replacing its contents,
+    /// and the result borrows it along with the names of this grammar.
+    pub fn remove_epsilon<'b>(&self, names: &'b mut String) -> Grammar<'b>
+    where
+        'a: 'b,
+    {
+        let nullable = self.nullable();
+        let start_on_right = self
+            .rules
+            .iter()
+            .any(|rule| rule.to.contains(&Token::NT(self.start)));
+
+        let mut start = self.start;
+        let mut rules: Vec<Rule<'b>> = self.rules.clone();
+        names.clear();
+        if nullable.contains(&self.start) && start_on_right {
+            let mut generator = NameGenerator::new(self);
+            generator.fresh(self.start.name);
+            start = generator.write(names).next().expect("The start was named!");
+            rules.push(Rule {
+                from: start,
+                to: vec![Token::NT(self.start)],
+                name: None,
+            });
+        }
+
+        Grammar::with_symbols(start, remove_epsilon_rules(rules, start))
+    }
+
+    /// Returns an equivalent grammar without unit rules, the rules of the form A -> B.
+    /// The rules of each nonterminal that can be reached from A using only unit rules
+    /// are copied to A, except for the unit rules themselves.
+    ///
+    /// Epsilon rules are copied like any other rule, so a rule like A -> B C, where C
+    /// is nullable, still behaves like a unit rule. Call `remove_epsilon` first
+    /// to remove all of them.
+    pub fn remove_unit(&self) -> Grammar<'a> {
+        Grammar::with_symbols(self.start, remove_unit_rules(self.rules.clone()))
+    }
+
+    /// Returns an equivalent grammar without useless rules: the rules that use
+    /// a nonterminal that derives no word, and then the rules of the nonterminals
+    /// that can't be reached from the start. Removing the unproductive rules
+    /// first matters, since it can make more nonterminals unreachable.
+    /// The rules keep their order and their weights.
+    pub fn normalize(&self) -> Grammar<'a> {
+        let productive = self.productive();
+        let (rules, weights): (Vec<Rule<'a>>, Vec<f64>) = self
+            .rules
+            .iter()
+            .zip(self.weights.iter())
+            .filter(|(rule, _)| {
+                rule.to.iter().all(|token| match token {
+                    Token::NT(n) => productive.contains(n),
+                    Token::T(_) => true,
+                })
+            })
+            .map(|(rule, &weight)| (rule.clone(), weight))
+            .unzip();
+
+        let reachable = reachable_nonterminals(self.start, &rules);
+        let (rules, weights) = rules
+            .into_iter()
+            .zip(weights)
+            .filter(|(rule, _)| reachable.contains(&rule.from))
+            .unzip();
+
+        let mut grammar = Grammar::with_symbols(self.start, rules);
+        grammar.weights = weights;
+        grammar
+    }
+
+    /// Returns an equivalent grammar in Chomsky Normal Form.
+    /// Every rule has the form A -> B C or A -> a, except for the rule S -> ,
+    /// which is present only if the empty word is in the language, and where S is
+    /// the new start nonterminal, which doesn't appear in the right side of any rule.
+    ///
+    /// The names of the new nonterminals are built from the names of the old ones,
+    /// followed by an underscore and a number. They are written into `names`,
+    /// replacing its contents, and the result borrows them along with the names
+    /// of this grammar.
+    pub fn to_cnf<'b>(&self, names: &'b mut String) -> Grammar<'b>
+    where
+        'a: 'b,
+    {
+        let mut generator = NameGenerator::new(self);
+        self.binarized_rules(|base| generator.fresh(base));
+        let mut new_names = generator.write(names);
+        let rules = self.binarized_rules(|_| {
+            new_names
+                .next()
+                .expect("Both passes create the same names!")
+        });
+
+        // The first rule is the one of the new start.
+        let start = rules[0].from;
+        let rules = remove_epsilon_rules(rules, start);
+        let rules = remove_unit_rules(rules);
+        Grammar::with_symbols(start, rules)
+    }
+
+    /// Returns the rules of `to_cnf` before the epsilon and the unit rules are
+    /// removed, starting with the rule of the new start. The new nonterminals are
+    /// created by `fresh`, given the name that theirs must be built from.
+    fn binarized_rules<'b>(&self, mut fresh: impl FnMut(&str) -> NonTerminal<'b>) -> Vec<Rule<'b>>
+    where
+        'a: 'b,
+    {
+        // Add a new start nonterminal, so the start never appears on the right side.
+        let start = fresh(self.start.name);
+        let mut rules = vec![Rule {
+            from: start,
+            to: vec![Token::NT(self.start)],
+            name: None,
+        }];
+        rules.extend(self.rules.iter().cloned());
+
+        // Replace the terminals in rules with more than one symbol by new nonterminals.
+        let mut lifted: Vec<(Terminal, NonTerminal)> = Vec::new();
+        for rule in rules.iter_mut() {
+            if rule.to.len() < 2 {
+                continue;
+            }
+            for token in rule.to.iter_mut() {
+                if let Token::T(terminal) = *token {
+                    let nonterminal = match lifted.iter().find(|(t, _)| *t == terminal) {
+                        Some(&(_, n)) => n,
+                        None => {
+                            let n = fresh("T");
+                            lifted.push((terminal, n));
+                            n
+                        }
+                    };
+                    *token = Token::NT(nonterminal);
+                }
+            }
+        }
+        for (terminal, nonterminal) in lifted {
+            rules.push(Rule {
+                from: nonterminal,
+                to: vec![Token::T(terminal)],
+                name: None,
+            });
+        }
+
+        // Split the rules with more than two symbols into chains of binary rules.
+        let mut binarized = Vec::new();
+        for rule in rules {
+            if rule.to.len() <= 2 {
+                binarized.push(rule);
+                continue;
+            }
+            // The first rule of the chain keeps the name of the split rule.
+            // The rules split here are the rules of this grammar, so their
+            // nonterminal is not a placeholder.
+            let mut from = rule.from;
+            let mut name = rule.name;
+            for &token in rule.to[..rule.to.len() - 2].iter() {
+                let next = fresh(rule.from.name);
+                binarized.push(Rule {
+                    from,
+                    to: vec![token, Token::NT(next)],
+                    name: name.take(),
+                });
+                from = next;
+            }
+            binarized.push(Rule {
+                from,
+                to: rule.to[rule.to.len() - 2..].to_vec(),
+                name: None,
+            });
+        }
+        binarized
+    }
+}
+
+/// The error returned when two grammars can't be merged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeError {
+    /// The given start nonterminal has no rules in either grammar.
+    UndefinedStart(String),
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeError::UndefinedStart(name) => {
+                write!(f, "The start nonterminal {} has no rules", name)
+            }
+        }
+    }
+}
+
+impl<'a, T: Symbol> Grammar<'a, T> {
+    /// Returns the grammar with the rules of both grammars, whose start is the
+    /// nonterminal named `new_start`, which must have at least one rule in either grammar.
+    ///
+    /// Nonterminals with the same name are the same nonterminal, so their rules are
+    /// joined. The rules of this grammar come first, followed by the rules of the other
+    /// grammar that this one doesn't already have. The rules keep their weights.
+    ///
+    /// The merged grammar borrows the names of both grammars, so both must outlive it.
+    pub fn merge(
+        &self,
+        other: &Grammar<'a, T>,
+        new_start: &str,
+    ) -> Result<Grammar<'a, T>, MergeError> {
+        let mut seen = HashSet::new();
+        let mut rules = Vec::new();
+        let mut weights = Vec::new();
+        let all_rules = self.rules.iter().zip(self.weights.iter());
+        for (rule, &weight) in all_rules.chain(other.rules.iter().zip(other.weights.iter())) {
+            if seen.insert(rule) {
+                rules.push(rule.clone());
+                weights.push(weight);
+            }
+        }
+
+        let start = rules
+            .iter()
+            .map(|rule| rule.from)
+            .find(|from| from.name == new_start)
+            .ok_or_else(|| MergeError::UndefinedStart(new_start.to_string()))?;
+
+        let mut grammar = Grammar::with_symbols(start, rules);
+        grammar.weights = weights;
+        Ok(grammar)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn is_cnf(grammar: &Grammar) -> bool {
+        grammar.rules.iter().all(|rule| match rule.to.as_slice() {
+            [] => rule.from == grammar.start,
+            [Token::T(_)] => true,
+            [Token::NT(a), Token::NT(b)] => *a != grammar.start && *b != grammar.start,
+            _ => false,
+        })
+    }
+
+    fn assert_same_language(grammar: &Grammar, other: &Grammar, words: &[&str]) {
+        for word in words {
+            assert_eq!(
+                grammar.parse(word).is_empty(),
+                other.parse(word).is_empty(),
+                "The grammars disagree on {:?}",
+                word
+            );
+        }
+    }
+
+    #[test]
+    fn test_cnf_expression_grammar() {
+        let grammar_string = "EXP
+        EXP -> EXP + EXP
+        EXP -> EXP * EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let cnf = grammar.to_cnf(&mut names);
+
+        assert!(is_cnf(&cnf));
+        assert_same_language(
+            &grammar,
+            &cnf,
+            &[
+                "",
+                "n",
+                "n+n*n",
+                "(n)",
+                "((n+n)*n)",
+                "n+",
+                "()",
+                "(n))",
+                "+n",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_cnf_nullable_grammars() {
+        let grammar_string = "S
+        S -> ( S ) S
+        S -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let cnf = grammar.to_cnf(&mut names);
+
+        assert!(is_cnf(&cnf));
+        assert_same_language(
+            &grammar,
+            &cnf,
+            &["", "()", "(())()", "(()", ")(", "()()()", "((()())())"],
+        );
+
+        let grammar_string = "S
+        S -> a S a
+        S -> b S b
+        S ->
+        S -> a
+        S -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let cnf = grammar.to_cnf(&mut names);
+
+        assert!(is_cnf(&cnf));
+        assert_same_language(
+            &grammar,
+            &cnf,
+            &["", "a", "abba", "aabaa", "aabab", "ab", "babbab"],
+        );
+
+        let grammar_string = "S
+        S -> a b C d e
+        C -> D
+        D -> E
+        E -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let cnf = grammar.to_cnf(&mut names);
+
+        assert!(is_cnf(&cnf));
+        assert_same_language(&grammar, &cnf, &["", "abde", "abcde", "abd", "abdee"]);
+    }
+
+    #[test]
+    fn test_cnf_fresh_names() {
+        // The new start is named T_0, so the lifted terminals start at T_1.
+        let grammar_string = "T
+        T -> a T b
+        T -> c";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let cnf = grammar.to_cnf(&mut names);
+
+        assert!(is_cnf(&cnf));
+        assert!(cnf.nonterminals.contains_key("T"));
+        assert!(cnf.nonterminals.contains_key("T_0"));
+        assert!(cnf.nonterminals.contains_key("T_1"));
+        assert!(cnf.start.name == "T_0");
+        assert_same_language(&grammar, &cnf, &["c", "acb", "aacbb", "ab", "aacb"]);
+
+        // The new names are written into the given string, which the result borrows.
+        drop(cnf);
+        assert_eq!(names, "T_0T_1T_2T_3");
+
+        // The contents are replaced, so the string can be reused.
+        let mut names = String::from("unused");
+        assert_eq!(grammar.remove_epsilon(&mut names).start.name, "T");
+        assert!(names.is_empty());
+        let grammar = Grammar::from_rules("S\nS -> a S\nS -> ").unwrap();
+        assert_eq!(grammar.remove_epsilon(&mut names).start.name, "S_0");
+        assert_eq!(names, "S_0");
+    }
+
+    #[test]
+    fn test_remove_epsilon() {
+        let grammar_string = "S
+        S -> a b C d e
+        C -> D
+        D -> E
+        E -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let result = grammar.remove_epsilon(&mut names);
+
+        // The whole chain derives only the empty word, so it disappears.
+        assert_eq!(result.rules.len(), 1);
+        assert_eq!(format!("{}", result.rules[0]), "S -> a b d e ");
+        assert_eq!(result.parse("abde").len(), 1);
+        assert_same_language(&grammar, &result, &["", "abde", "abcde", "abd"]);
+    }
+
+    #[test]
+    fn test_remove_epsilon_nullable_start() {
+        // The start is nullable and appears on the right side, so it is replaced.
+        let grammar_string = "S
+        S -> a S a
+        S -> b S b
+        S ->
+        S -> a
+        S -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let result = grammar.remove_epsilon(&mut names);
+
+        assert!(result.start.name == "S_0");
+        for rule in result.rules.iter() {
+            assert!(!rule.to.is_empty() || rule.from == result.start);
+        }
+        assert_eq!(
+            result
+                .rules
+                .iter()
+                .filter(|rule| rule.to.is_empty())
+                .count(),
+            1
+        );
+        assert_same_language(
+            &grammar,
+            &result,
+            &["", "a", "aa", "abba", "aabaa", "aabab", "ab", "babbab"],
+        );
+
+        // The start is nullable but never appears on the right side, so it is kept.
+        let grammar_string = "S
+        S -> A b A
+        S -> A
+        A -> a
+        A -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let result = grammar.remove_epsilon(&mut names);
+
+        assert!(result.start == grammar.start);
+        assert_same_language(&grammar, &result, &["", "a", "b", "ab", "aba", "aa", "abb"]);
+    }
+
+    #[test]
+    fn test_remove_unit() {
+        let grammar_string = "S
+        S -> A B
+        S -> B
+        A -> B A
+        A -> a
+        B -> A
+        B -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let result = grammar.remove_unit();
+
+        for rule in result.rules.iter() {
+            assert!(rule.to.len() != 1 || matches!(rule.to[0], Token::T(_)));
+        }
+        assert_eq!(grammar.parse("bab").len(), 1);
+        assert!(!result.parse("bab").is_empty());
+        assert_same_language(
+            &grammar,
+            &result,
+            &["", "a", "b", "ab", "bab", "bba", "abab"],
+        );
+    }
+
+    #[test]
+    fn test_remove_unit_after_epsilon() {
+        let grammar_string = "S
+        S -> A C
+        A -> B
+        B -> b
+        C -> c
+        C -> ";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let mut names = String::new();
+        let result = grammar.remove_epsilon(&mut names).remove_unit();
+
+        for rule in result.rules.iter() {
+            assert!(rule.to.len() != 1 || matches!(rule.to[0], Token::T(_)));
+            assert!(!rule.to.is_empty());
+        }
+        assert_same_language(&grammar, &result, &["", "b", "bc", "c", "bcc"]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let grammar_string = "S
+        S -> A A
+        A -> a A
+        A -> b
+        A -> a A
+        B -> b";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let normalized = grammar.normalize();
+        assert_eq!(normalized.rule_count(), 3);
+        assert_eq!(normalized.nonterminal_count(), 2);
+        for word in ["bab", "bb", "aabab", "ab", ""] {
+            assert_eq!(grammar.parse(word), normalized.parse(word));
+        }
+
+        // C is reachable only through the unproductive D.
+        let grammar_string = "S
+        S -> a
+        S -> C D
+        C -> c
+        D -> D d";
+        let grammar = Grammar::from_rules(grammar_string).unwrap();
+        let normalized = grammar.normalize();
+        assert_eq!(normalized.to_grammar_string(), "S\nS -> a\n");
+        assert_same_language(&grammar, &normalized, &["a", "c", "cd", ""]);
+
+        // A grammar with an empty language keeps only its start.
+        let grammar = Grammar::from_rules("S\nS -> S a").unwrap();
+        assert_eq!(grammar.normalize().rule_count(), 0);
+        assert_eq!(grammar.normalize().nonterminal_count(), 1);
+    }
+
+    #[test]
+    fn test_merge() {
+        let expressions = "EXP
+        EXP -> EXP + EXP
+        EXP -> ( EXP )
+        EXP -> n";
+        let statements = "PROG
+        PROG -> STMT PROG
+        PROG -> STMT
+        STMT -> p EXP
+        EXP -> v";
+        let mut expressions = Grammar::from_rules(expressions).unwrap();
+        let statements = Grammar::from_rules(statements).unwrap();
+        expressions.set_weights(&[-1.0, -2.0, -3.0]);
+
+        // The statements can only print variables on their own.
+        assert!(statements.parse("pn+n").is_empty());
+        assert!(expressions.parse("pn+n").is_empty());
+
+        let merged = expressions.merge(&statements, "PROG").unwrap();
+        assert_eq!(merged.start().name(), "PROG");
+        assert_eq!(merged.rule_count(), 7);
+        assert_eq!(merged.parse("pn+npv").len(), 1);
+        assert_eq!(merged.parse("p(v+n)").len(), 1);
+        assert_eq!(merged.weights, vec![-1.0, -2.0, -3.0, 0.0, 0.0, 0.0, 0.0]);
+
+        // The rules that both grammars share are kept once.
+        let same = expressions.merge(&expressions, "EXP").unwrap();
+        assert!(same.rules == expressions.rules);
+
+        assert_eq!(
+            expressions.merge(&statements, "STMTS").err(),
+            Some(MergeError::UndefinedStart("STMTS".to_string()))
+        );
+        // A nonterminal that is only used on the right side can't be the start.
+        let grammar = Grammar::from_rules("S\nS -> A").unwrap();
+        assert!(grammar.merge(&grammar, "A").is_err());
+    }
+}
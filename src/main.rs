@@ -1,26 +1,49 @@
-use earley_parser::grammar::{render_tree, write_tree_to_dot, Grammar};
-use std::fs;
-use std::io;
+use earley_parser::grammar::{render_tree, to_ascii, write_tree_to_dot_file, Grammar};
+use std::io::{self, ErrorKind};
+use std::process;
 
 fn main() {
-    let rules = fs::read_to_string("grammar").unwrap();
-    let grammar = Grammar::from_rules(&rules).unwrap();
+    let mut text = String::new();
+    let grammar = match Grammar::from_file("grammar", &mut text) {
+        Ok(grammar) => grammar,
+        Err(error) => {
+            eprintln!("Could not read the grammar: {}", error);
+            process::exit(1);
+        }
+    };
     println!("{}", grammar);
     println!("Enter your words:");
 
     loop {
         let mut line = String::new();
-        io::stdin().read_line(&mut line).unwrap();
+        // Stop at the end of the input.
+        match io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
         let line = line.trim();
         let trees = grammar.parse(line);
 
         println!("Got {} derivation trees", trees.len());
 
         for (index, tree) in trees.iter().enumerate() {
-            let mut to = String::new();
-            write_tree_to_dot(&mut to, &tree).unwrap();
             let path = format!("tree_{}.svg", index);
-            render_tree(&tree, &path).unwrap();
+            match render_tree(tree, &path) {
+                Ok(()) => {}
+                // Without dot, save the graph so that it can be rendered later.
+                Err(error) if error.kind() == ErrorKind::NotFound => {
+                    let dot_path = format!("tree_{}.dot", index);
+                    print!("{}", to_ascii(tree));
+                    match write_tree_to_dot_file(tree, &dot_path) {
+                        Ok(()) => println!(
+                            "dot was not found, render the tree with: dot -Tsvg {} -o {}",
+                            dot_path, path
+                        ),
+                        Err(error) => eprintln!("Could not write {}: {}", dot_path, error),
+                    }
+                }
+                Err(_) => print!("{}", to_ascii(tree)),
+            }
         }
     }
 }